@@ -0,0 +1,75 @@
+/// File: benchmark.rs
+///
+/// This file contains the benchmark comparing the dictionary index against the brute-force scan.
+/// Every log entry is tokenized the same way the detector does it, and each token is looked up with
+/// both methods; the results are compared to make sure the index returns exactly the same values.
+/// The file includes the following items:
+///
+/// - `BenchmarkReport`: The timings and the number of mismatching lookups.
+/// - `run_benchmark`: Looks up the tokens of the log entries with both methods.
+///
+/// Modules Required:
+/// - `dict_index`: Provides the indexed and brute-force lookups.
+/// - `log_ops`: Provides the tokenization of log entries.
+use std::time::{Duration, Instant};
+
+use crate::dict_index::DictionaryIndex;
+use crate::log_format::LogFormat;
+use crate::log_ops;
+
+/// The timings and the number of mismatching lookups of one benchmark run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BenchmarkReport
+{
+    /// The number of tokens looked up.
+    pub tokens: usize,
+    /// The time spent comparing every token with every dictionary entry.
+    pub brute_force: Duration,
+    /// The time spent looking the tokens up in the index.
+    pub indexed: Duration,
+    /// The number of tokens for which the two methods disagree.
+    pub mismatches: usize,
+}
+
+impl BenchmarkReport
+{
+    /// Returns how many times faster the index is than the brute-force scan.
+    pub fn speedup(&self) -> f64
+    {
+        self.brute_force.as_secs_f64() / self.indexed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Looks up the tokens of the log entries with both methods.
+///
+/// # Parameters
+/// - `dictionary`: The indexed dictionary.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `logs`: The log entries whose tokens are looked up.
+///
+/// # Returns
+/// The benchmark report.
+pub fn run_benchmark(dictionary: &DictionaryIndex, format: &dyn LogFormat, delimiters: &[String], logs: &[String]) -> BenchmarkReport
+{
+    let mut tokens: Vec<String> = Vec::new();
+    for log in logs
+    {
+        tokens.extend(log_ops::tokenize_log_entry(log, format, delimiters, dictionary.normalization(), dictionary.text_unit()));
+    }
+
+    let start = Instant::now();
+    let brute_force: Vec<_> = tokens.iter().map(|token| dictionary.best_matches_brute_force(token)).collect();
+    let brute_force_duration = start.elapsed();
+
+    let start = Instant::now();
+    let indexed: Vec<_> = tokens.iter().map(|token| dictionary.best_matches(token)).collect();
+    let indexed_duration = start.elapsed();
+
+    BenchmarkReport {
+        tokens: tokens.len(),
+        brute_force: brute_force_duration,
+        indexed: indexed_duration,
+        mismatches: brute_force.iter().zip(indexed.iter()).filter(|(a, b)| a != b).count(),
+    }
+}
@@ -0,0 +1,296 @@
+/// File: checkpoint.rs
+///
+/// This file contains the checkpoint of the live watcher: how far a log file has been analyzed. The
+/// checkpoint is saved after every processed batch, so a restarted watcher resumes at the stored byte
+/// offset instead of rescanning the file or skipping what was appended while it was down. The inode and
+/// the hash of the last analyzed line identify the file, which detects rotation and truncation.
+/// The file includes the following items:
+///
+/// - `FileChange`: How a log file changed since the checkpoint was taken.
+/// - `Checkpoint`: The watched file, its inode, the byte offset analyzed so far and the last analyzed line.
+/// - `checkpoint_file`: Returns the file the checkpoint of a log file is stored in.
+/// - `file_inode`: Returns the inode of a file, or 0 on platforms without inodes.
+/// - `file_device`: Returns the device holding a file, or 0 on platforms without inodes.
+/// - `file_links`: Returns the number of names of a file, or 0 on platforms without inodes.
+///
+/// Modules Required:
+/// - `config`: Provides the `key = value` parser the checkpoint file is read with.
+/// - `file_ops`: Provides atomic writes of the checkpoint file.
+/// - `hash_ops`: Provides the stable hash of the last analyzed line and of the log file path.
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::file_ops;
+use crate::hash_ops;
+
+/// How a log file changed since the checkpoint was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange
+{
+    /// The file still holds the analyzed lines; analysis continues at the offset.
+    Unchanged,
+    /// The path now refers to another file, e.g. after logrotate created a new one.
+    Rotated,
+    /// The file is shorter than the analyzed offset.
+    Truncated,
+    /// The line before the offset is no longer the last analyzed line.
+    Rewritten,
+}
+
+/// How far a log file has been analyzed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint
+{
+    /// The path of the watched log file.
+    pub path: String,
+    /// The inode of the file when it was last read.
+    pub inode: u64,
+    /// The byte offset up to which the file has been analyzed; always at the start of a line.
+    pub offset: u64,
+    /// The FNV-1a hash of the last analyzed line, including its line break.
+    pub last_line_hash: u64,
+    /// The length in bytes of the last analyzed line, including its line break.
+    pub last_line_length: u64,
+}
+
+impl Checkpoint
+{
+    /// Creates a checkpoint at a byte offset of a file, recording the line that ends at the offset.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the log file.
+    /// - `offset`: The byte offset; the start of a line, usually the end of the file.
+    ///
+    /// # Returns
+    /// A result containing the checkpoint or an error if the file cannot be read.
+    pub fn at_offset(path: &str, offset: u64) -> io::Result<Checkpoint>
+    {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let last_line = read_line_ending_at(&mut file, offset)?;
+
+        Ok(Checkpoint {
+            path: path.to_string(),
+            inode: file_inode(&metadata),
+            offset,
+            last_line_hash: hash_ops::calculate_stable_hash(&last_line),
+            last_line_length: last_line.len() as u64,
+        })
+    }
+
+    /// Creates a checkpoint at the start of a file.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the log file.
+    /// - `inode`: The inode of the file.
+    ///
+    /// # Returns
+    /// The checkpoint.
+    pub fn start_of(path: &str, inode: u64) -> Checkpoint
+    {
+        Checkpoint {
+            path: path.to_string(),
+            inode,
+            offset: 0,
+            last_line_hash: hash_ops::calculate_stable_hash(&[]),
+            last_line_length: 0,
+        }
+    }
+
+    /// Loads a checkpoint file.
+    ///
+    /// # Parameters
+    /// - `checkpoint_file`: The path of the checkpoint file.
+    ///
+    /// # Returns
+    /// A result containing the checkpoint, `None` if the file does not exist, or an error if it is malformed.
+    pub fn load(checkpoint_file: &Path) -> io::Result<Option<Checkpoint>>
+    {
+        if !checkpoint_file.exists()
+        {
+            return Ok(None);
+        }
+
+        let file_name = checkpoint_file.to_string_lossy();
+        let values = Config::load(&file_name)?;
+        let number = |key: &str| -> io::Result<u64> {
+            values.get(key)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: missing or invalid `{}`", file_name, key)))
+        };
+
+        Ok(Some(Checkpoint {
+            path: values.get("path").unwrap_or_default().to_string(),
+            inode: number("inode")?,
+            offset: number("offset")?,
+            last_line_hash: number("last_line_hash")?,
+            last_line_length: number("last_line_length")?,
+        }))
+    }
+
+    /// Saves the checkpoint, replacing the checkpoint file atomically.
+    ///
+    /// # Parameters
+    /// - `checkpoint_file`: The path of the checkpoint file; its directory is created if needed.
+    ///
+    /// # Returns
+    /// A result indicating success or failure.
+    pub fn save(&self, checkpoint_file: &Path) -> io::Result<()>
+    {
+        if let Some(directory) = checkpoint_file.parent()
+        {
+            fs::create_dir_all(directory)?;
+        }
+
+        let contents = format!(
+            "path = {}\ninode = {}\noffset = {}\nlast_line_hash = {}\nlast_line_length = {}\n",
+            self.path, self.inode, self.offset, self.last_line_hash, self.last_line_length
+        );
+        file_ops::write_file_atomically(&checkpoint_file.to_string_lossy(), contents.as_bytes())
+    }
+
+    /// Compares the checkpoint against an open file, which is assumed to be the checkpointed one.
+    ///
+    /// # Parameters
+    /// - `file`: The open log file.
+    ///
+    /// # Returns
+    /// A result containing `Truncated`, `Rewritten` or `Unchanged`, or an error if the file cannot be read.
+    pub fn detect_change(&self, file: &mut File) -> io::Result<FileChange>
+    {
+        if file.metadata()?.len() < self.offset
+        {
+            return Ok(FileChange::Truncated);
+        }
+
+        // A checkpoint whose last line would start before the file is corrupt, so nothing can be trusted
+        let Some(last_line_start) = self.offset.checked_sub(self.last_line_length) else
+        {
+            return Ok(FileChange::Rewritten);
+        };
+
+        let last_line = read_bytes(file, last_line_start, self.last_line_length)?;
+        if last_line.len() as u64 != self.last_line_length || hash_ops::calculate_stable_hash(&last_line) != self.last_line_hash
+        {
+            return Ok(FileChange::Rewritten);
+        }
+
+        Ok(FileChange::Unchanged)
+    }
+
+    /// Moves the checkpoint past analyzed bytes.
+    ///
+    /// # Parameters
+    /// - `consumed`: The bytes read at the offset; they end with a line break, unless they hold the
+    ///   trailing partial line of a rotated file.
+    pub fn advance(&mut self, consumed: &[u8])
+    {
+        if consumed.is_empty()
+        {
+            return;
+        }
+
+        let body = &consumed[..consumed.len() - 1];
+        let last_line_start = body.iter().rposition(|&byte| byte == b'\n').map_or(0, |index| index + 1);
+        let last_line = consumed[last_line_start..].to_vec();
+
+        self.offset += consumed.len() as u64;
+        self.last_line_hash = hash_ops::calculate_stable_hash(&last_line);
+        self.last_line_length = last_line.len() as u64;
+    }
+}
+
+/// Returns the file the checkpoint of a log file is stored in: `<hash of its path>.checkpoint` in the
+/// checkpoint directory.
+///
+/// # Parameters
+/// - `checkpoint_dir`: The directory checkpoints are stored in, usually `Workspace::checkpoints_dir`.
+/// - `log_file`: The path of the watched log file.
+///
+/// # Returns
+/// The path of the checkpoint file.
+pub fn checkpoint_file(checkpoint_dir: &Path, log_file: &str) -> PathBuf
+{
+    let log_path = Path::new(log_file).canonicalize().unwrap_or_else(|_| PathBuf::from(log_file));
+    checkpoint_dir.join(format!("{}.checkpoint", hash_ops::calculate_stable_hash(log_path.as_os_str().as_encoded_bytes())))
+}
+
+/// Returns the inode of a file, or 0 on platforms without inodes.
+#[cfg(unix)]
+pub fn file_inode(metadata: &Metadata) -> u64
+{
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+/// Returns the inode of a file, or 0 on platforms without inodes.
+#[cfg(not(unix))]
+pub fn file_inode(_metadata: &Metadata) -> u64
+{
+    0
+}
+
+/// Returns the device holding a file, or 0 on platforms without inodes.
+#[cfg(unix)]
+pub fn file_device(metadata: &Metadata) -> u64
+{
+    use std::os::unix::fs::MetadataExt;
+    metadata.dev()
+}
+
+/// Returns the device holding a file, or 0 on platforms without inodes.
+#[cfg(not(unix))]
+pub fn file_device(_metadata: &Metadata) -> u64
+{
+    0
+}
+
+/// Returns the number of names of a file, 0 once an open file is removed, or 0 on platforms without inodes.
+#[cfg(unix)]
+pub fn file_links(metadata: &Metadata) -> u64
+{
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink()
+}
+
+/// Returns the number of names of a file, 0 once an open file is removed, or 0 on platforms without inodes.
+#[cfg(not(unix))]
+pub fn file_links(_metadata: &Metadata) -> u64
+{
+    0
+}
+
+/// Reads up to `length` bytes of a file at an offset.
+fn read_bytes(file: &mut File, offset: u64, length: u64) -> io::Result<Vec<u8>>
+{
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(offset))?;
+    file.take(length).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Size of the blocks read backwards while looking for the start of a line.
+const BACKWARD_READ_SIZE: u64 = 4096;
+
+/// Reads the line that ends at an offset, including its line break; empty at the start of the file.
+fn read_line_ending_at(file: &mut File, offset: u64) -> io::Result<Vec<u8>>
+{
+    let mut start = offset.saturating_sub(1);
+
+    while start > 0
+    {
+        let block_start = start.saturating_sub(BACKWARD_READ_SIZE);
+        let block = read_bytes(file, block_start, start - block_start)?;
+
+        if let Some(index) = block.iter().rposition(|&byte| byte == b'\n')
+        {
+            start = block_start + index as u64 + 1;
+            break;
+        }
+        start = block_start;
+    }
+
+    read_bytes(file, start, offset - start)
+}
@@ -0,0 +1,471 @@
+/// File: cli.rs
+///
+/// This file contains the command-line interface definition. Every subcommand maps to one of the
+/// handlers in `commands`. The file includes the following items:
+///
+/// - `Cli`: The top level program parameters (global options and the subcommand).
+/// - `Command`: The available subcommands (`scan`, `watch`, `dict`, `hash`, `evaluate`, `sweep`, `bench`).
+/// - `DictCommand`: The subcommands of `dict`.
+/// - `ScoringArgs`: Weight, threshold, normalization and similarity metric flags shared by the analysis subcommands.
+/// - `LogFormatArgs`: Flags selecting the log format, the fields read from JSON records, the text unit requests are compared in and their normalization.
+/// - `DictionaryArgs`: Flags selecting the dictionary and delimiter files shared by the analysis subcommands.
+/// - `DatasetArgs`: Flags selecting the training and labeled datasets shared by `evaluate`, `sweep` and `bench`.
+/// - `ScanArgs`, `WatchArgs`, `DictBuildArgs`, `HashArgs`, `EvaluateArgs`, `SweepArgs`, `BenchArgs`: The flags of each subcommand.
+use std::io;
+
+use clap::{ArgAction, Args, Parser, Subcommand};
+
+use crate::config::Config;
+use crate::file_watcher::WatcherKind;
+use crate::json_format::JsonFields;
+use crate::log_format::LogFormatKind;
+use crate::log_ops::ScoringConfig;
+use crate::logging::LoggingOptions;
+use crate::normalize::NormalizationConfig;
+use crate::report::{OutputMode, ReportFormat};
+use crate::similarity::{self, WeightedMetric};
+use crate::string_utils::TextUnit;
+use crate::threshold_sweep;
+
+/// Configuration file key holding the additional similarity metrics, e.g. `metrics = jaro-winkler=0.2,lcs=0.1`.
+pub const METRICS_CONFIG_KEY: &str = "metrics";
+
+/// Detects malicious requests in web server logs by comparing them against a dictionary of known attacks.
+#[derive(Debug, Parser)]
+#[command(name = "fs", version, about)]
+pub struct Cli
+{
+    /// Data directory all relative file names are resolved against (overrides FS_DATA_DIR and `data_dir` in the config file).
+    #[arg(long, global = true, value_name = "DIR")]
+    pub data_dir: Option<String>,
+
+    /// Configuration file (overrides FS_CONFIG; defaults to `fs.conf` in the current directory).
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<String>,
+
+    /// Increase diagnostic output (-v info, -vv debug, -vvv trace); warnings only by default.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Per-module log filter, e.g. `warn,fs::log_ops=debug` (overrides -v and FS_LOG).
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log_filter: Option<String>,
+
+    /// File diagnostic events are additionally written to as JSON lines.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub log_json: Option<String>,
+
+    /// Subcommand to run; `scan` with default flags when omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli
+{
+    /// Returns the logging options given on the command line.
+    pub fn logging_options(&self) -> LoggingOptions
+    {
+        LoggingOptions {
+            verbosity: self.verbose,
+            filter: self.log_filter.clone(),
+            json_file: self.log_json.clone(),
+        }
+    }
+}
+
+/// The available subcommands.
+#[derive(Debug, Subcommand)]
+pub enum Command
+{
+    /// Analyze log files in batch and save the malicious requests.
+    Scan(ScanArgs),
+    /// Watch log files and analyze new entries as they are appended.
+    Watch(WatchArgs),
+    /// Manage the dictionary of known malicious patterns.
+    #[command(subcommand)]
+    Dict(DictCommand),
+    /// Calculate and store the hashes of the training files.
+    Hash(HashArgs),
+    /// Measure precision and recall on labeled benign and malicious datasets.
+    Evaluate(EvaluateArgs),
+    /// Sweep the composite score cutoff and weights to produce ROC and precision-recall curves.
+    Sweep(SweepArgs),
+    /// Compare the dictionary index against the brute-force scan on the labeled datasets.
+    Bench(BenchArgs),
+}
+
+impl Command
+{
+    /// Returns the scoring flags of the subcommand, if it scores requests.
+    pub fn scoring_args_mut(&mut self) -> Option<&mut ScoringArgs>
+    {
+        match self
+        {
+            Command::Scan(args) => Some(&mut args.scoring),
+            Command::Watch(args) => Some(&mut args.scoring),
+            Command::Evaluate(args) => Some(&mut args.scoring),
+            Command::Sweep(args) => Some(&mut args.scoring),
+            Command::Dict(_) | Command::Hash(_) | Command::Bench(_) => None,
+        }
+    }
+}
+
+/// The subcommands of `dict`.
+#[derive(Debug, Subcommand)]
+pub enum DictCommand
+{
+    /// Build the dictionary from training logs, or load it if it is up to date.
+    Build(DictBuildArgs),
+}
+
+/// Weight, threshold and normalization flags shared by the analysis subcommands.
+#[derive(Debug, Clone, Default, Args)]
+pub struct ScoringArgs
+{
+    /// Weight of the normalized Levenshtein similarity in the composite score [default: 0.5].
+    #[arg(long, value_name = "WEIGHT", value_parser = similarity::parse_weight)]
+    pub alpha: Option<f64>,
+
+    /// Weight of the Dice coefficient in the composite score [default: 0.5].
+    #[arg(long, value_name = "WEIGHT", value_parser = similarity::parse_weight)]
+    pub beta: Option<f64>,
+
+    /// Composite score from which a request is rated "Medium" [default: 0.3].
+    #[arg(long, value_name = "SCORE")]
+    pub medium_threshold: Option<f64>,
+
+    /// Composite score from which a request is rated "High", i.e. malicious [default: 0.7].
+    #[arg(long, value_name = "SCORE")]
+    pub high_threshold: Option<f64>,
+
+    /// Distance used to normalize Levenshtein distances [default: length of the longest dictionary entry].
+    #[arg(long, value_name = "DISTANCE")]
+    pub max_levenshtein_distance: Option<usize>,
+
+    /// Additional similarity metric averaged into the composite score with its weight: damerau-levenshtein,
+    /// jaro-winkler, jaccard[:N], cosine[:N] (TF-IDF over N-grams) or lcs; repeatable or comma-separated
+    /// [default: the `metrics` key of the config file].
+    #[arg(long = "metric", value_name = "METRIC=WEIGHT", value_delimiter = ',')]
+    pub metrics: Vec<WeightedMetric>,
+}
+
+impl ScoringArgs
+{
+    /// Applies the flags given on the command line on top of the default scoring configuration.
+    pub fn to_scoring_config(&self) -> ScoringConfig
+    {
+        let mut scoring = ScoringConfig::default();
+
+        if let Some(alpha) = self.alpha
+        {
+            scoring.alpha = alpha;
+        }
+
+        if let Some(beta) = self.beta
+        {
+            scoring.beta = beta;
+        }
+
+        if let Some(threshold) = self.medium_threshold
+        {
+            scoring.medium_threshold = threshold;
+        }
+
+        if let Some(threshold) = self.high_threshold
+        {
+            scoring.high_threshold = threshold;
+        }
+
+        scoring.max_levenshtein_distance = self.max_levenshtein_distance;
+        scoring.metrics = self.metrics.clone();
+        scoring
+    }
+
+    /// Takes the settings not given on the command line from the configuration file.
+    ///
+    /// # Parameters
+    /// - `config`: The loaded configuration file.
+    ///
+    /// # Returns
+    /// A result indicating an invalid value in the configuration file.
+    pub fn apply_config(&mut self, config: &Config) -> io::Result<()>
+    {
+        if let (true, Some(list)) = (self.metrics.is_empty(), config.get(METRICS_CONFIG_KEY))
+        {
+            self.metrics = similarity::parse_weighted_metrics(list)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", METRICS_CONFIG_KEY, e)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Flags selecting the log format, the fields read from JSON records, the text unit requests are compared in and their normalization.
+#[derive(Debug, Clone, Default, Args)]
+pub struct LogFormatArgs
+{
+    /// Format of the log files: auto, request, combined (also common, nginx), w3c (also iis) or json (JSON Lines).
+    #[arg(long, value_name = "FORMAT", default_value_t)]
+    pub log_format: LogFormatKind,
+
+    /// JSON pointer to the HTTP method of JSON records [default: /method].
+    #[arg(long, value_name = "POINTER")]
+    pub json_method: Option<String>,
+
+    /// JSON pointer to the request URI of JSON records [default: /uri].
+    #[arg(long, value_name = "POINTER")]
+    pub json_uri: Option<String>,
+
+    /// JSON pointer to the request headers of JSON records; their values are analyzed too.
+    #[arg(long, value_name = "POINTER")]
+    pub json_headers: Option<String>,
+
+    /// JSON pointer to the request body of JSON records; it is analyzed too.
+    #[arg(long, value_name = "POINTER")]
+    pub json_body: Option<String>,
+
+    /// Unit requests are split and compared in: char (Unicode scalar values), grapheme (user-perceived
+    /// characters) or byte.
+    #[arg(long, value_name = "UNIT", default_value_t)]
+    pub text_unit: TextUnit,
+
+    /// Comma-separated normalization steps applied to the analyzed fields before they are split: percent,
+    /// html, unicode, null, backslash, path, case and whitespace; or all, or none.
+    #[arg(long, value_name = "STEPS", default_value_t)]
+    pub normalize: NormalizationConfig,
+}
+
+impl LogFormatArgs
+{
+    /// Applies the flags given on the command line on top of the default JSON fields.
+    pub fn to_json_fields(&self) -> JsonFields
+    {
+        let mut fields = JsonFields::default();
+
+        if let Some(pointer) = &self.json_method
+        {
+            fields.method = Some(pointer.clone());
+        }
+
+        if let Some(pointer) = &self.json_uri
+        {
+            fields.uri = pointer.clone();
+        }
+
+        fields.headers = self.json_headers.clone();
+        fields.body = self.json_body.clone();
+        fields
+    }
+}
+
+/// Flags selecting the dictionary and delimiter files shared by the analysis subcommands.
+#[derive(Debug, Clone, Default, Args)]
+pub struct DictionaryArgs
+{
+    /// Dictionary file to compare against, resolved against the data directory [default: the
+    /// `malicious_logs*` files in the data directory].
+    #[arg(long, value_name = "FILE")]
+    pub dictionary: Option<String>,
+
+    /// File with one delimiter per line, resolved against the data directory [default: the
+    /// `special_strings*` files in the data directory].
+    #[arg(long, value_name = "FILE")]
+    pub delimiters: Option<String>,
+
+    #[command(flatten)]
+    pub format: LogFormatArgs,
+}
+
+/// Flags of the `scan` subcommand.
+#[derive(Debug, Clone, Default, Args)]
+pub struct ScanArgs
+{
+    /// Log files to analyze, resolved against the data directory [default: the `logs_to_check*` files in
+    /// the data directory].
+    #[arg(long = "input", value_name = "FILE")]
+    pub inputs: Vec<String>,
+
+    /// File the malicious requests are written to, resolved against the data directory [default:
+    /// `malicious_logs.txt` for text reports, `detection_report.<format>` otherwise].
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Format of the report: text (the bare log lines), jsonl, csv or sarif.
+    #[arg(long, value_name = "FORMAT", default_value_t)]
+    pub report_format: ReportFormat,
+
+    /// What to do with an existing output file: overwrite it, append to it (not for sarif), or rotate
+    /// it to `<file>.<n>` before writing a new one.
+    #[arg(long, value_name = "MODE", default_value_t)]
+    pub output_mode: OutputMode,
+
+    /// Analyze at most this many log entries.
+    #[arg(long, value_name = "COUNT")]
+    pub limit: Option<usize>,
+
+    /// Number of worker threads analyzing the log entries [default: one per CPU].
+    #[arg(long, value_name = "COUNT")]
+    pub threads: Option<usize>,
+
+    #[command(flatten)]
+    pub dictionary: DictionaryArgs,
+
+    #[command(flatten)]
+    pub scoring: ScoringArgs,
+}
+
+/// Flags of the `watch` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct WatchArgs
+{
+    /// Log files to watch: a file, a directory or a glob pattern such as `/var/log/nginx/*.access.log`,
+    /// resolved against the data directory; repeat to watch several.
+    #[arg(long = "input", value_name = "PATTERN", default_value = "logs_to_check.txt")]
+    pub inputs: Vec<String>,
+
+    /// Directory the watcher saves its position in each file in, to resume there after a restart,
+    /// resolved against the data directory [default: `checkpoints`].
+    #[arg(long, value_name = "DIR")]
+    pub checkpoint_dir: Option<String>,
+
+    /// How changes to the watched files are detected: native notifications, or poll for network file
+    /// systems and containers where notifications do not fire.
+    #[arg(long, value_name = "KIND", default_value_t)]
+    pub watcher: WatcherKind,
+
+    /// Time between two scans of the poll watcher, in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    pub poll_interval: u64,
+
+    #[command(flatten)]
+    pub dictionary: DictionaryArgs,
+
+    #[command(flatten)]
+    pub scoring: ScoringArgs,
+}
+
+/// Flags of the `dict build` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct DictBuildArgs
+{
+    /// Training log files, resolved against the data directory [default: the `malicious_logs*` files in
+    /// the data directory].
+    #[arg(long = "input", value_name = "FILE")]
+    pub inputs: Vec<String>,
+
+    /// Dictionary file to write or load, resolved against the data directory [default: `dictionary.txt`].
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// File with one delimiter per line, resolved against the data directory [default: the
+    /// `special_strings*` files in the data directory].
+    #[arg(long, value_name = "FILE")]
+    pub delimiters: Option<String>,
+
+    #[command(flatten)]
+    pub format: LogFormatArgs,
+
+    /// Rebuild the dictionary even if the training files have not changed.
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Flags of the `hash` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct HashArgs
+{
+    /// Prefix of the data directory files to hash.
+    #[arg(long, value_name = "PREFIX", default_value = "malicious_logs")]
+    pub prefix: String,
+}
+
+/// Flags selecting the training and labeled datasets shared by `evaluate`, `sweep` and `bench`.
+#[derive(Debug, Clone, Args)]
+pub struct DatasetArgs
+{
+    /// Training log files the dictionary is built from; relative paths are resolved against the data directory.
+    #[arg(long = "training", value_name = "FILE", default_value = "datasets/malicious_logs_trainingdataset.txt")]
+    pub training: Vec<String>,
+
+    /// Log files whose entries are labeled benign; relative paths are resolved against the data directory.
+    #[arg(long = "benign", value_name = "FILE", default_value = "datasets/logs_to_check_testdataset_benign.txt")]
+    pub benign: Vec<String>,
+
+    /// Log files whose entries are labeled malicious; relative paths are resolved against the data directory.
+    #[arg(long = "malicious", value_name = "FILE", default_value = "datasets/logs_to_check_testdataset_malicious.txt")]
+    pub malicious: Vec<String>,
+
+    /// Dictionary file to compare against instead of training one from `--training`; relative paths are
+    /// resolved against the data directory.
+    #[arg(long, value_name = "FILE")]
+    pub dictionary: Option<String>,
+
+    /// File with one delimiter per line, resolved against the data directory [default: the
+    /// `special_strings*` files in the data directory].
+    #[arg(long, value_name = "FILE")]
+    pub delimiters: Option<String>,
+
+    #[command(flatten)]
+    pub format: LogFormatArgs,
+}
+
+/// Flags of the `evaluate` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct EvaluateArgs
+{
+    #[command(flatten)]
+    pub datasets: DatasetArgs,
+
+    /// Unlabeled log files (e.g. `datasets/evaluation_set.txt`), resolved against the data directory; only
+    /// the number of detections is reported.
+    #[arg(long = "input", value_name = "FILE")]
+    pub inputs: Vec<String>,
+
+    /// File the evaluation report is written to, in addition to standard output; relative paths are
+    /// resolved against the data directory.
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub scoring: ScoringArgs,
+}
+
+/// Flags of the `sweep` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct SweepArgs
+{
+    #[command(flatten)]
+    pub datasets: DatasetArgs,
+
+    /// CSV file the ROC curve (false positive rate, true positive rate per cutoff) is written to, resolved
+    /// against the data directory.
+    #[arg(long, value_name = "FILE", default_value = "roc_curve.csv")]
+    pub roc_output: String,
+
+    /// CSV file the precision-recall curve is written to, resolved against the data directory.
+    #[arg(long, value_name = "FILE", default_value = "pr_curve.csv")]
+    pub pr_output: String,
+
+    /// CSV file the areas under the curves of every alpha/beta weighting are written to, resolved against
+    /// the data directory.
+    #[arg(long, value_name = "FILE", default_value = "weights_sweep.csv")]
+    pub weights_output: String,
+
+    /// Distance between two alpha values of the weight sweep (beta is `1 - alpha`).
+    #[arg(long, value_name = "STEP", default_value_t = 0.1, value_parser = threshold_sweep::parse_weight_step)]
+    pub weight_step: f64,
+
+    /// Highest acceptable false positive rate of the recommended operating point.
+    #[arg(long, value_name = "RATE", default_value_t = 0.01)]
+    pub target_fpr: f64,
+
+    #[command(flatten)]
+    pub scoring: ScoringArgs,
+}
+
+/// Flags of the `bench` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct BenchArgs
+{
+    #[command(flatten)]
+    pub datasets: DatasetArgs,
+}
@@ -0,0 +1,553 @@
+/// File: commands.rs
+///
+/// This file contains the handlers of the command-line subcommands. Each handler loads its inputs
+/// relative to the workspace, drives the corresponding module and reports the outcome.
+/// The file includes the following functions:
+///
+/// - `run_scan`: Analyzes log files in batch and saves the malicious requests.
+/// - `run_watch`: Watches log files and analyzes new entries as they are appended.
+/// - `run_dict_build`: Builds the dictionary from training logs, or loads it if it is up to date.
+/// - `run_hash`: Calculates and stores the hashes of the training files.
+/// - `run_evaluate`: Measures precision and recall on labeled benign and malicious datasets.
+/// - `run_sweep`: Sweeps the composite score cutoff and weights to produce ROC and precision-recall curves.
+/// - `run_bench`: Compares the dictionary index against the brute-force scan on the labeled datasets.
+///
+/// Modules Required:
+/// - `cli`: Provides the subcommand flags.
+/// - `benchmark`, `dict_ops`, `evaluation`, `hash_ops`, `log_ops`, `thread_utils`, `threshold_sweep`: Provide the operations driven by the subcommands.
+/// - `file_ops`: Provides file handling utilities.
+/// - `file_watcher`: Provides the file system watching backends of the live watcher.
+/// - `log_format`: Provides the parsers of the supported log formats.
+/// - `report`: Provides the writers of the detection reports.
+/// - `supervisor`: Provides the supervised worker threads of the live watcher.
+/// - `watch_set`: Provides the set of log files followed by the live watcher.
+/// - `workspace`: Provides the data directory files are resolved against.
+use std::fs;
+use std::io;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::benchmark;
+use crate::cli::{BenchArgs, DatasetArgs, DictBuildArgs, DictionaryArgs, EvaluateArgs, HashArgs, LogFormatArgs, ScanArgs, SweepArgs, WatchArgs};
+use crate::dict_index::{DictionaryIndex, SharedDictionary};
+use crate::dict_ops;
+use crate::evaluation;
+use crate::file_ops;
+use crate::file_watcher;
+use crate::hash_ops;
+use crate::log_format::{self, LogFormat};
+use crate::log_ops::{self, ScoringConfig};
+use crate::report::{self, Finding, ReportFormat, SourceLocation};
+use crate::supervisor::{Inbox, Supervisor};
+use crate::thread_utils;
+use crate::threshold_sweep;
+use crate::watch_set::WatchSet;
+use crate::workspace::{Workspace, DETECTION_REPORT_OUTPUT, DICTIONARY_FILE, MALICIOUS_LOGS_OUTPUT};
+
+/// Prefix of the training files in the data directory.
+const MALICIOUS_LOGS_PREFIX: &str = "malicious_logs";
+
+/// Prefix of the delimiter files in the data directory.
+const SPECIAL_STRINGS_PREFIX: &str = "special_strings";
+
+/// Prefix of the files to analyze in the data directory.
+const LOGS_TO_CHECK_PREFIX: &str = "logs_to_check";
+
+/// Number of lines at the start of a watched file the log format is detected from.
+const FORMAT_SAMPLE_LINES: usize = 1000;
+
+/// Number of batches that can wait between two stages of the watcher before the earlier stage blocks.
+const WATCH_CHANNEL_CAPACITY: usize = 16;
+
+/// Resolves a file given on the command line against the data directory; absolute paths are kept.
+fn resolve_file_name(workspace: &Workspace, file_name: &str) -> String
+{
+    workspace.path(file_name).to_string_lossy().into_owned()
+}
+
+/// Resolves files given on the command line against the data directory; absolute paths are kept.
+fn resolve_file_names(workspace: &Workspace, file_names: &[String]) -> Vec<String>
+{
+    file_names.iter().map(|file_name| resolve_file_name(workspace, file_name)).collect()
+}
+
+/// Resolves the given files, or the data directory files with a prefix when none are given.
+fn input_file_names(workspace: &Workspace, file_names: &[String], filename_prefix: &str) -> Vec<String>
+{
+    if file_names.is_empty()
+    {
+        file_ops::get_filenames_with_prefix(workspace, filename_prefix.to_string())
+    }
+    else
+    {
+        resolve_file_names(workspace, file_names)
+    }
+}
+
+/// Loads the given files, or the data directory files with a prefix when none are given.
+///
+/// # Parameters
+/// - `workspace`: The workspace searched for files with the prefix.
+/// - `file_names`: The files given on the command line.
+/// - `filename_prefix`: The prefix used when no files are given.
+///
+/// # Returns
+/// A result containing all lines of the loaded files or an error.
+fn load_inputs(workspace: &Workspace, file_names: &[String], filename_prefix: &str) -> io::Result<Vec<String>>
+{
+    let mut lines: Vec<String> = Vec::new();
+    file_ops::load_files_into_vector(&mut lines, input_file_names(workspace, file_names, filename_prefix))?;
+    Ok(lines)
+}
+
+/// Loads the given files, or the data directory files with a prefix when none are given, and
+/// records the file and line number every line was read from.
+///
+/// # Parameters
+/// - `workspace`: The workspace searched for files with the prefix.
+/// - `file_names`: The files given on the command line.
+/// - `filename_prefix`: The prefix used when no files are given.
+///
+/// # Returns
+/// A result containing all lines of the loaded files and their locations, in the same order, or an error.
+fn load_inputs_with_sources(workspace: &Workspace, file_names: &[String], filename_prefix: &str) -> io::Result<(Vec<String>, Vec<SourceLocation>)>
+{
+    let mut lines: Vec<String> = Vec::new();
+    let mut sources: Vec<SourceLocation> = Vec::new();
+
+    for file_name in input_file_names(workspace, file_names, filename_prefix)
+    {
+        let file_lines = file_ops::read_file_line_by_line(&file_name)?;
+        sources.extend((1..=file_lines.len()).map(|line_number| SourceLocation { file: file_name.clone(), line_number }));
+        lines.extend(file_lines);
+    }
+
+    Ok((lines, sources))
+}
+
+/// Loads the delimiters from the given file, or from the `special_strings*` files of the data directory.
+fn load_delimiters(workspace: &Workspace, file_name: &Option<String>) -> io::Result<Vec<String>>
+{
+    let file_names: Vec<String> = file_name.iter().cloned().collect();
+    load_inputs(workspace, &file_names, SPECIAL_STRINGS_PREFIX)
+}
+
+/// Resolves the dictionary file given on the command line for the reloader of the live watcher.
+fn reloaded_dictionary_file(workspace: &Workspace, args: &DictionaryArgs) -> Option<String>
+{
+    args.dictionary.as_deref().map(|file_name| resolve_file_name(workspace, file_name))
+}
+
+/// Loads and indexes the dictionary from the given file, or the `malicious_logs*` files of the data
+/// directory, building the additional similarity metrics of the scoring configuration over it.
+fn load_dictionary(workspace: &Workspace, args: &DictionaryArgs, scoring: &ScoringConfig) -> io::Result<DictionaryIndex>
+{
+    let file_names: Vec<String> = args.dictionary.iter().cloned().collect();
+    let entries = load_inputs(workspace, &file_names, MALICIOUS_LOGS_PREFIX)?;
+    Ok(DictionaryIndex::new(entries, args.format.text_unit, args.format.normalize.clone())
+        .with_metrics(&scoring.metrics))
+}
+
+/// Creates the parser of the log format selected on the command line.
+fn create_log_format(args: &LogFormatArgs, lines: &[String]) -> Box<dyn LogFormat>
+{
+    let format = log_format::create_log_format(args.log_format, &args.to_json_fields(), lines);
+    debug!("Parsing log entries as {}", format.name());
+    format
+}
+
+/// Analyzes log files in batch and saves the malicious requests.
+///
+/// # Parameters
+/// - `workspace`: The workspace default files are resolved against.
+/// - `args`: The flags of the `scan` subcommand.
+///
+/// # Returns
+/// A result indicating success or failure.
+pub fn run_scan(workspace: &Workspace, args: &ScanArgs) -> io::Result<()>
+{
+    let output_file = match &args.output
+    {
+        Some(output) => resolve_file_name(workspace, output),
+        None if args.report_format == ReportFormat::Text => workspace.path(MALICIOUS_LOGS_OUTPUT).to_string_lossy().into_owned(),
+        None => workspace.path(format!("{}.{}", DETECTION_REPORT_OUTPUT, args.report_format)).to_string_lossy().into_owned(),
+    };
+    let workspace = &workspace.with_output(&output_file);
+
+    let scoring = args.scoring.to_scoring_config();
+    let dictionary = load_dictionary(workspace, &args.dictionary, &scoring)?;
+    let delimiters = load_delimiters(workspace, &args.dictionary.delimiters)?;
+    let (logs_to_check, sources) = load_inputs_with_sources(workspace, &args.inputs, LOGS_TO_CHECK_PREFIX)?;
+    let format = create_log_format(&args.dictionary.format, &logs_to_check);
+
+    // Analyze the loaded logs and save malicious ones to the output file
+    let malicious_logs = log_ops::find_malicious_logs(
+        &logs_to_check,
+        format.as_ref(),
+        &delimiters,
+        &dictionary,
+        args.limit,
+        &scoring,
+        args.threads,
+    )?;
+
+    let findings: Vec<Finding> = malicious_logs.iter()
+        .map(|(index, result)| Finding::new(&sources[*index], &logs_to_check[*index], result))
+        .collect();
+    report::save_findings(&findings, &output_file, args.report_format, args.output_mode)?;
+
+    println!("Malicious logs analysis complete and saved.");
+    Ok(())
+}
+
+/// Watches log files and analyzes new entries as they are appended.
+///
+/// # Summary
+/// The watcher, analyzer and reporter run as supervised threads (see `Supervisor`), restarted when they
+/// crash. The dictionary is reloaded whenever the training files change, without pausing the analysis.
+/// On SIGINT or SIGTERM, the entries already read are analyzed and reported and their checkpoints saved
+/// before returning.
+///
+/// # Parameters
+/// - `workspace`: The workspace the watched patterns and default files are resolved against.
+/// - `args`: The flags of the `watch` subcommand.
+///
+/// # Returns
+/// A result once the workers have shut down, or an error if the inputs or a checkpoint cannot be loaded.
+pub fn run_watch(workspace: &Workspace, args: &WatchArgs) -> io::Result<()>
+{
+    let scoring = args.scoring.to_scoring_config();
+    let dictionary = SharedDictionary::new(load_dictionary(workspace, &args.dictionary, &scoring)?);
+    let delimiters = load_delimiters(workspace, &args.dictionary.delimiters)?;
+    let checkpoint_dir = match &args.checkpoint_dir
+    {
+        Some(directory) => workspace.path(directory),
+        None => workspace.checkpoints_dir(),
+    };
+
+    let mut watch_set = WatchSet::new(workspace, &args.inputs, &checkpoint_dir)?;
+    watch_set.discover()?;
+
+    let sample = match watch_set.paths().next()
+    {
+        Some(path) => file_ops::read_first_lines(&path.to_string_lossy(), FORMAT_SAMPLE_LINES)?,
+        None => Vec::new(),
+    };
+    let format = create_log_format(&args.dictionary.format, &sample);
+
+    let watcher_kind = args.watcher;
+    let poll_interval = Duration::from_millis(args.poll_interval);
+
+    let mut supervisor = Supervisor::new();
+    supervisor.install_signal_handler()?;
+    let (batch_sender, batches) = Inbox::channel(WATCH_CHANNEL_CAPACITY);
+    let (result_sender, results) = Inbox::channel(WATCH_CHANNEL_CAPACITY);
+
+    // A restarted watcher discovers the files again and resumes them at their saved checkpoints
+    {
+        let workspace = workspace.clone();
+        let inputs = args.inputs.clone();
+        let discovered = Mutex::new(Some(watch_set));
+        supervisor.spawn("watcher", move |shutdown| {
+            let discovered = discovered.lock().unwrap_or_else(PoisonError::into_inner).take();
+            let mut watch_set = match discovered
+            {
+                Some(watch_set) => watch_set,
+                None =>
+                {
+                    let mut watch_set = WatchSet::new(&workspace, &inputs, &checkpoint_dir)?;
+                    watch_set.discover()?;
+                    watch_set
+                },
+            };
+            let mut watcher = file_watcher::create_file_watcher(watcher_kind, poll_interval)?;
+            thread_utils::watch_for_new_log_entries(&mut watch_set, watcher.as_mut(), &batch_sender, shutdown)
+        })?;
+    }
+
+    {
+        let dictionary = dictionary.clone();
+        supervisor.spawn("analyzer", move |_| {
+            thread_utils::analyze_log_batches(&batches, &result_sender, format.as_ref(), &delimiters, &dictionary, &scoring)
+        })?;
+    }
+
+    supervisor.spawn("reporter", move |_| thread_utils::report_analyzed_batches(&results))?;
+
+    // The dictionary is rebuilt on its own thread when the training files change, while detection goes on
+    {
+        let workspace = workspace.clone();
+        let dictionary_file = reloaded_dictionary_file(&workspace, &args.dictionary);
+        supervisor.spawn("dictionary-reload", move |shutdown| {
+            let mut watcher = file_watcher::create_file_watcher(watcher_kind, poll_interval)?;
+            thread_utils::watch_for_malicious_log_changes(&workspace, dictionary_file.as_deref(), MALICIOUS_LOGS_PREFIX, &dictionary, watcher.as_mut(), shutdown)
+        })?;
+    }
+
+    supervisor.run()
+}
+
+/// Builds the dictionary from training logs, or loads it if it is up to date.
+///
+/// # Summary
+/// With the default inputs, the dictionary is rebuilt only when the stored hashes of the
+/// `malicious_logs*` files no longer match, and the hashes are refreshed afterwards.
+///
+/// # Parameters
+/// - `workspace`: The workspace default files are resolved against.
+/// - `args`: The flags of the `dict build` subcommand.
+///
+/// # Returns
+/// A result indicating success or failure.
+pub fn run_dict_build(workspace: &Workspace, args: &DictBuildArgs) -> io::Result<()>
+{
+    let output_file = match &args.output
+    {
+        Some(output) => resolve_file_name(workspace, output),
+        None => workspace.path(DICTIONARY_FILE).to_string_lossy().into_owned(),
+    };
+    let workspace = &workspace.with_output(&output_file);
+
+    let log_entries = load_inputs(workspace, &args.inputs, MALICIOUS_LOGS_PREFIX)?;
+    let delimiters = load_delimiters(workspace, &args.delimiters)?;
+
+    let uses_default_inputs = args.inputs.is_empty();
+    let update_dictionary = args.force
+        || !uses_default_inputs
+        || dict_ops::are_dictionaries_updated(workspace, MALICIOUS_LOGS_PREFIX.to_string())?;
+
+    let format = create_log_format(&args.format, &log_entries);
+    let dictionary = dict_ops::generate_or_load_dictionary(&log_entries, format.as_ref(), &delimiters, &args.format.normalize, args.format.text_unit, update_dictionary, output_file.clone())?;
+
+    if update_dictionary && uses_default_inputs
+    {
+        hash_ops::calculate_and_save_file_hashes(workspace, MALICIOUS_LOGS_PREFIX.to_string())?;
+    }
+
+    println!("Dictionary {} holds {} entries.", output_file, dictionary.len());
+    Ok(())
+}
+
+/// Calculates and stores the hashes of the training files.
+///
+/// # Parameters
+/// - `workspace`: The workspace the files are looked up in and the hashes are saved to.
+/// - `args`: The flags of the `hash` subcommand.
+///
+/// # Returns
+/// A result indicating success or failure.
+pub fn run_hash(workspace: &Workspace, args: &HashArgs) -> io::Result<()>
+{
+    hash_ops::calculate_and_save_file_hashes(workspace, args.prefix.clone())?;
+    println!("Hashes saved to {}", workspace.hashes_dir().display());
+    Ok(())
+}
+
+/// The dictionary, log format, delimiters and labeled entries used by `evaluate`, `sweep` and `bench`.
+struct LabeledDatasets
+{
+    dictionary: DictionaryIndex,
+    format: Box<dyn LogFormat>,
+    delimiters: Vec<String>,
+    benign: Vec<String>,
+    malicious: Vec<String>,
+}
+
+/// Loads the labeled datasets, training the dictionary from the training files unless a dictionary file is given.
+fn load_labeled_datasets(workspace: &Workspace, args: &DatasetArgs, scoring: &ScoringConfig) -> io::Result<LabeledDatasets>
+{
+    let delimiters = load_delimiters(workspace, &args.delimiters)?;
+
+    let mut training_entries: Vec<String> = Vec::new();
+    if args.dictionary.is_none()
+    {
+        file_ops::load_files_into_vector(&mut training_entries, resolve_file_names(workspace, &args.training))?;
+    }
+    let mut benign: Vec<String> = Vec::new();
+    file_ops::load_files_into_vector(&mut benign, resolve_file_names(workspace, &args.benign))?;
+    let mut malicious: Vec<String> = Vec::new();
+    file_ops::load_files_into_vector(&mut malicious, resolve_file_names(workspace, &args.malicious))?;
+
+    let all_entries: Vec<String> = training_entries.iter().chain(benign.iter()).chain(malicious.iter()).cloned().collect();
+    let format = create_log_format(&args.format, &all_entries);
+
+    let dictionary = match &args.dictionary
+    {
+        Some(file_name) => file_ops::read_file_line_by_line(&resolve_file_name(workspace, file_name))?,
+        None => evaluation::train_dictionary(&training_entries, format.as_ref(), &delimiters, &args.format.normalize, args.format.text_unit),
+    };
+
+    let dictionary = DictionaryIndex::new(dictionary, args.format.text_unit, args.format.normalize.clone())
+        .with_metrics(&scoring.metrics);
+    Ok(LabeledDatasets { dictionary, format, delimiters, benign, malicious })
+}
+
+/// Measures precision and recall on labeled benign and malicious datasets.
+///
+/// # Summary
+/// Trains the dictionary from the training files (unless a dictionary file is given), classifies every
+/// labeled entry and prints the confusion matrix, the derived metrics and the misclassified entries.
+/// Unlabeled inputs only get their number of detections reported.
+///
+/// # Parameters
+/// - `workspace`: The workspace default files are resolved against.
+/// - `args`: The flags of the `evaluate` subcommand.
+///
+/// # Returns
+/// A result indicating success or failure.
+pub fn run_evaluate(workspace: &Workspace, args: &EvaluateArgs) -> io::Result<()>
+{
+    let scoring = args.scoring.to_scoring_config();
+    let datasets = load_labeled_datasets(workspace, &args.datasets, &scoring)?;
+
+    let report = evaluation::evaluate(&datasets.benign, &datasets.malicious, datasets.format.as_ref(), &datasets.delimiters, &datasets.dictionary, &scoring);
+    let mut text = evaluation::format_report(&report);
+
+    for input in resolve_file_names(workspace, &args.inputs)
+    {
+        let logs = file_ops::read_file_line_by_line(&input)?;
+        let total = logs.len();
+        let malicious_logs = log_ops::analyze_logs_and_collect_malicious(&logs, datasets.format.as_ref(), &datasets.delimiters, &datasets.dictionary, None, &scoring);
+        text.push_str(&format!("{}: {} of {} entries detected as malicious\n", input, malicious_logs.len(), total));
+    }
+
+    print!("{}", text);
+
+    if let Some(output) = &args.output
+    {
+        fs::write(resolve_file_name(workspace, output), &text)?;
+    }
+
+    Ok(())
+}
+
+/// Sweeps the composite score cutoff and weights to produce ROC and precision-recall curves.
+///
+/// # Summary
+/// Scores every labeled entry once, writes the ROC and precision-recall curves for the configured
+/// alpha/beta as CSV, writes the areas under the curves for a grid of alpha/beta weightings, and
+/// recommends the cutoff with the best recall within the target false positive rate.
+///
+/// # Parameters
+/// - `workspace`: The workspace default files are resolved against.
+/// - `args`: The flags of the `sweep` subcommand.
+///
+/// # Returns
+/// A result indicating success or failure.
+pub fn run_sweep(workspace: &Workspace, args: &SweepArgs) -> io::Result<()>
+{
+    let scoring = args.scoring.to_scoring_config();
+    let datasets = load_labeled_datasets(workspace, &args.datasets, &scoring)?;
+
+    let entries = threshold_sweep::score_dataset(&datasets.benign, &datasets.malicious, datasets.format.as_ref(), &datasets.delimiters, &datasets.dictionary, &scoring);
+    let points = threshold_sweep::sweep_thresholds(&entries, scoring.alpha, scoring.beta);
+    let weights = threshold_sweep::sweep_weights(&entries, args.weight_step);
+
+    let roc_output = resolve_file_name(workspace, &args.roc_output);
+    let pr_output = resolve_file_name(workspace, &args.pr_output);
+    let weights_output = resolve_file_name(workspace, &args.weights_output);
+    fs::write(&roc_output, threshold_sweep::roc_curve_csv(&points))?;
+    fs::write(&pr_output, threshold_sweep::pr_curve_csv(&points))?;
+    fs::write(&weights_output, threshold_sweep::weights_csv(&weights))?;
+
+    println!("Scored entries: {}", entries.len());
+    println!("Weights alpha={} beta={}: ROC AUC {:.4}, PR AUC {:.4}",
+        scoring.alpha, scoring.beta, threshold_sweep::roc_auc(&points), threshold_sweep::pr_auc(&points));
+
+    let best_weights = weights.iter().max_by(|a, b| a.roc_auc.total_cmp(&b.roc_auc));
+    if let Some(best) = best_weights
+    {
+        println!("Best weights alpha={:.2} beta={:.2}: ROC AUC {:.4}, PR AUC {:.4}", best.alpha, best.beta, best.roc_auc, best.pr_auc);
+    }
+
+    match threshold_sweep::recommend_operating_point(&points, args.target_fpr)
+    {
+        Some(point) => println!(
+            "Recommended --high-threshold {} (false positive rate {:.4}, recall {:.4}, precision {:.4})",
+            point.threshold,
+            point.matrix.false_positive_rate(),
+            point.matrix.recall(),
+            point.matrix.precision()
+        ),
+        None => println!("No cutoff keeps the false positive rate within {}", args.target_fpr),
+    }
+
+    println!("Curves saved to {}, {} and {}", roc_output, pr_output, weights_output);
+    Ok(())
+}
+
+/// Compares the dictionary index against the brute-force scan on the labeled datasets.
+///
+/// # Parameters
+/// - `workspace`: The data directory files are resolved against.
+/// - `args`: The flags of the `bench` subcommand.
+///
+/// # Returns
+/// A result indicating failure to read the datasets, or an error if the two methods disagree.
+pub fn run_bench(workspace: &Workspace, args: &BenchArgs) -> io::Result<()>
+{
+    let datasets = load_labeled_datasets(workspace, &args.datasets, &ScoringConfig::default())?;
+    let logs: Vec<String> = datasets.benign.iter().chain(datasets.malicious.iter()).cloned().collect();
+
+    let report = benchmark::run_benchmark(&datasets.dictionary, datasets.format.as_ref(), &datasets.delimiters, &logs);
+
+    println!("Dictionary entries: {}", datasets.dictionary.len());
+    println!("Tokens looked up: {}", report.tokens);
+    println!("Brute force: {:.3?}", report.brute_force);
+    println!("Indexed:     {:.3?}", report.indexed);
+    println!("Speedup:     {:.1}x", report.speedup());
+
+    if report.mismatches > 0
+    {
+        return Err(io::Error::other(format!("{} tokens scored differently by the index", report.mismatches)));
+    }
+
+    println!("The index matches the brute-force scan on every token");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::file_watcher::PollingWatcher;
+    use crate::test_support::test_dir;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+
+    #[test]
+    fn reloader_follows_a_relative_dictionary_in_the_data_directory()
+    {
+        // The data directory is not the current directory, so a relative dictionary only resolves against it
+        let directory = test_dir("commands-relative-dictionary");
+        fs::write(directory.join("dictionary.txt"), "<script>\n").unwrap();
+
+        let workspace = Workspace::new(&directory);
+        let args = DictionaryArgs { dictionary: Some("dictionary.txt".to_string()), ..Default::default() };
+        let dictionary = SharedDictionary::new(load_dictionary(&workspace, &args, &ScoringConfig::default()).unwrap());
+        let dictionary_file = reloaded_dictionary_file(&workspace, &args);
+
+        let shutdown = AtomicBool::new(false);
+        let mut watcher = PollingWatcher::new(Duration::from_millis(10));
+        std::thread::scope(|scope| {
+            let reloader = scope.spawn(|| {
+                thread_utils::watch_for_malicious_log_changes(&workspace, dictionary_file.as_deref(), MALICIOUS_LOGS_PREFIX, &dictionary, &mut watcher, &shutdown)
+            });
+
+            // Rewrite until the reloader picks the change up, as it may start watching after the first write
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while dictionary.load().len() != 2 && Instant::now() < deadline
+            {
+                fs::write(directory.join("dictionary.txt"), "/etc/passwd\nunion select\n").unwrap();
+                std::thread::sleep(Duration::from_secs(1));
+            }
+
+            shutdown.store(true, Ordering::SeqCst);
+            reloader.join().unwrap().unwrap();
+        });
+
+        assert_eq!(dictionary.load().len(), 2);
+    }
+}
@@ -0,0 +1,117 @@
+/// File: config.rs
+///
+/// This file contains the configuration file loader. The configuration file is a plain text file
+/// with one `key = value` pair per line; empty lines and lines starting with `#` are ignored.
+/// The file includes the following items:
+///
+/// - `Config`: Holds the key/value pairs read from a configuration file.
+/// - `Config::load`: Loads a configuration file from a given path.
+/// - `Config::locate_and_load`: Finds the configuration file (CLI flag, environment variable, default name) and loads it.
+/// - `Config::get`: Returns the value stored under a key.
+///
+/// Modules Required:
+/// - `file_ops`: Provides file handling utilities.
+use std::collections::HashMap;
+use std::env;
+use std::io;
+
+use crate::file_ops;
+
+/// Environment variable holding the path of the configuration file.
+pub const CONFIG_ENV_VAR: &str = "FS_CONFIG";
+
+/// Configuration file that is picked up from the current directory when no path is given.
+pub const DEFAULT_CONFIG_FILE: &str = "fs.conf";
+
+/// Key/value pairs read from a configuration file.
+#[derive(Debug, Default, Clone)]
+pub struct Config
+{
+    values: HashMap<String, String>,
+}
+
+impl Config
+{
+    /// Loads a configuration file.
+    ///
+    /// # Parameters
+    /// - `file_name`: A reference to a string slice holding the path of the configuration file.
+    ///
+    /// # Returns
+    /// A result containing the parsed configuration or an error.
+    pub fn load(file_name: &str) -> io::Result<Config>
+    {
+        let mut values = HashMap::new();
+
+        for (index, line) in file_ops::read_file_line_by_line(file_name)?.iter().enumerate()
+        {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+
+            match line.split_once('=')
+            {
+                Some((key, value)) =>
+                {
+                    let value = value.trim().trim_matches('"');
+                    values.insert(key.trim().to_string(), value.to_string());
+                },
+                None =>
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}:{}: expected `key = value`", file_name, index + 1),
+                    ));
+                }
+            }
+        }
+
+        Ok(Config { values })
+    }
+
+    /// Finds and loads the configuration file.
+    ///
+    /// # Summary
+    /// The path given on the command line wins, then the `FS_CONFIG` environment variable.
+    /// If neither is set, `fs.conf` is loaded from the current directory when it exists,
+    /// otherwise an empty configuration is returned.
+    ///
+    /// # Parameters
+    /// - `cli_path`: The configuration file path given on the command line, if any.
+    ///
+    /// # Returns
+    /// A result containing the configuration or an error if an explicitly requested file could not be read.
+    pub fn locate_and_load(cli_path: Option<&str>) -> io::Result<Config>
+    {
+        if let Some(path) = cli_path
+        {
+            return Config::load(path);
+        }
+
+        if let Ok(path) = env::var(CONFIG_ENV_VAR)
+        {
+            return Config::load(&path);
+        }
+
+        if file_ops::file_exists(DEFAULT_CONFIG_FILE)
+        {
+            return Config::load(DEFAULT_CONFIG_FILE);
+        }
+
+        Ok(Config::default())
+    }
+
+    /// Returns the value stored under a key.
+    ///
+    /// # Parameters
+    /// - `key`: The configuration key.
+    ///
+    /// # Returns
+    /// The value, or `None` if the key is not present.
+    pub fn get(&self, key: &str) -> Option<&str>
+    {
+        self.values.get(key).map(|value| value.as_str())
+    }
+}
@@ -0,0 +1,561 @@
+/// File: dict_index.rs
+///
+/// This file contains the dictionary index used to find, for one token, the closest dictionary entry by
+/// Levenshtein distance and the highest Dice coefficient with any entry, without comparing the token
+/// against every entry. Edit distance queries use a BK-tree; Dice queries use an inverted index from
+/// bigram to the entries containing it and how often, since entries sharing no bigram with the token
+/// have a Dice coefficient of 0. The results are exactly those of the brute-force scan, which is kept
+/// for benchmarking.
+/// The bigram profile of every entry is counted once, when the index is built.
+/// The file includes the following items:
+///
+/// - `TokenScores`: The minimum Levenshtein distance, its dictionary entry and the maximum Dice coefficient of a token.
+/// - `DictionaryIndex`: The dictionary entries with their BK-tree, bigram profiles and bigram index.
+/// - `DictionaryIndex::new`: Normalizes a list of dictionary entries and builds the index over them.
+/// - `DictionaryIndex::best_matches`: Finds the scores of a token using the index.
+/// - `DictionaryIndex::best_matches_brute_force`: Finds the scores of a token by comparing it with every entry.
+/// - `DictionaryIndex::with_metrics`: Builds the additional similarity metrics over the entries.
+/// - `DictionaryIndex::metric_similarities`: Finds the highest similarity of a token with any entry for each additional metric, skipping the entries the bigram index or the lengths rule out.
+/// - `SharedDictionary`: A dictionary index shared between threads that can be replaced while it is in use.
+///
+/// Modules Required:
+/// - `normalize`: Provides the normalization applied to the entries and stored with the index.
+/// - `similarity`: Provides the additional similarity metrics.
+/// - `string_utils`: Provides the Levenshtein distance, Dice coefficient and bigram extraction, in a text unit.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::normalize::NormalizationConfig;
+use crate::similarity::{SimilarityMetric, WeightedMetric};
+use crate::string_utils::{self, BigramProfile, TextUnit};
+
+/// The best dictionary matches of one token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenScores
+{
+    /// The minimum Levenshtein distance to a dictionary entry (`usize::MAX` for an empty dictionary).
+    pub min_levenshtein: usize,
+    /// The index of the first dictionary entry at the minimum distance.
+    pub best_entry: Option<usize>,
+    /// The maximum Dice coefficient with a dictionary entry.
+    pub max_dice_coefficient: f64,
+}
+
+/// A node of the BK-tree; every child sits at a distinct Levenshtein distance from the node.
+#[derive(Debug, Clone)]
+struct BkNode
+{
+    entry: usize,
+    children: Vec<(usize, usize)>,
+}
+
+/// The dictionary entries with their BK-tree, bigram profiles and bigram index.
+#[derive(Debug, Clone, Default)]
+pub struct DictionaryIndex
+{
+    entries: Vec<String>,
+    /// The unit distances and bigrams are counted in.
+    text_unit: TextUnit,
+    /// The normalization requests go through before they are split into tokens and looked up.
+    normalization: NormalizationConfig,
+    /// The additional similarity metrics, built over the entries, with their weights.
+    metrics: Vec<(WeightedMetric, Arc<dyn SimilarityMetric>)>,
+    max_length: usize,
+    nodes: Vec<BkNode>,
+    /// The entries containing each bigram, with the number of times they contain it.
+    bigrams: HashMap<Vec<u8>, Vec<(usize, usize)>>,
+    /// The bigram profile of each entry.
+    profiles: Vec<BigramProfile>,
+    /// The entries of each length in text units, for the metrics whose similarity is bounded by the lengths.
+    lengths: BTreeMap<usize, Vec<usize>>,
+}
+
+impl DictionaryIndex
+{
+    /// Builds the index over a list of dictionary entries.
+    ///
+    /// # Summary
+    /// The entries go through the same normalization as the requests, so an entry such as
+    /// `..%2f..%2fetc/passwd` matches the normalized request it was taken from. The Levenshtein distance
+    /// over whole text units is a metric for any input, which the BK-tree relies on, so every entry goes
+    /// into the tree.
+    ///
+    /// # Parameters
+    /// - `entries`: The dictionary entries; their order decides which entry wins a tie.
+    /// - `text_unit`: The unit distances, lengths and bigrams are counted in, for the entries and the tokens looked up.
+    /// - `normalization`: The normalization applied to the entries, and to requests before they are split
+    ///   into tokens, so every user of the index (and of its reloaded replacements) does the same.
+    ///
+    /// # Returns
+    /// The new `DictionaryIndex`.
+    pub fn new(entries: Vec<String>, text_unit: TextUnit, normalization: NormalizationConfig) -> DictionaryIndex
+    {
+        let entries: Vec<String> = if normalization.steps().next().is_none()
+        {
+            entries
+        }
+        else
+        {
+            entries.iter().map(|entry| normalization.normalize(entry).text).collect()
+        };
+
+        let mut index = DictionaryIndex {
+            text_unit,
+            normalization,
+            max_length: entries.iter().map(|s| string_utils::unit_count(s, text_unit)).max().unwrap_or(0),
+            ..DictionaryIndex::default()
+        };
+
+        for (entry_index, entry) in entries.iter().enumerate()
+        {
+            index.insert_into_tree(&entries, entry_index);
+
+            let profile = BigramProfile::new(entry, text_unit);
+            for (bigram, count) in profile.counts()
+            {
+                index.bigrams.entry(bigram.to_vec()).or_default().push((entry_index, count));
+            }
+            index.profiles.push(profile);
+            index.lengths.entry(string_utils::unit_count(entry, text_unit)).or_default().push(entry_index);
+        }
+
+        index.entries = entries;
+        index
+    }
+
+    /// Builds the additional similarity metrics over the entries; metrics that learn from the dictionary
+    /// (TF-IDF) are rebuilt whenever a new index is built.
+    ///
+    /// # Parameters
+    /// - `metrics`: The metrics and their weights in the composite score.
+    ///
+    /// # Returns
+    /// The index with the metrics built.
+    pub fn with_metrics(mut self, metrics: &[WeightedMetric]) -> DictionaryIndex
+    {
+        self.metrics = metrics.iter()
+            .map(|metric| (*metric, metric.kind.build(&self.entries, self.text_unit)))
+            .collect();
+        self
+    }
+
+    /// Inserts an entry into the BK-tree; exact duplicates of an earlier entry are skipped.
+    fn insert_into_tree(&mut self, entries: &[String], entry_index: usize)
+    {
+        if self.nodes.is_empty()
+        {
+            self.nodes.push(BkNode { entry: entry_index, children: Vec::new() });
+            return;
+        }
+
+        let mut node_index = 0;
+        loop
+        {
+            let node_entry = &entries[self.nodes[node_index].entry];
+            let distance = string_utils::levenshtein(node_entry, &entries[entry_index], self.text_unit);
+
+            if distance == 0
+            {
+                return;
+            }
+
+            match self.nodes[node_index].children.iter().find(|(d, _)| *d == distance)
+            {
+                Some(&(_, child)) => node_index = child,
+                None =>
+                {
+                    let child = self.nodes.len();
+                    self.nodes.push(BkNode { entry: entry_index, children: Vec::new() });
+                    self.nodes[node_index].children.push((distance, child));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns the dictionary entries.
+    #[allow(dead_code)]
+    pub fn entries(&self) -> &[String]
+    {
+        &self.entries
+    }
+
+    /// Returns the dictionary entry at an index.
+    pub fn entry(&self, index: usize) -> &str
+    {
+        &self.entries[index]
+    }
+
+    /// Returns the number of dictionary entries.
+    pub fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    /// Returns whether the dictionary has no entries.
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool
+    {
+        self.entries.is_empty()
+    }
+
+    /// Returns the unit distances, lengths and bigrams are counted in.
+    pub fn text_unit(&self) -> TextUnit
+    {
+        self.text_unit
+    }
+
+    /// Returns the normalization requests go through before they are split into tokens.
+    pub fn normalization(&self) -> &NormalizationConfig
+    {
+        &self.normalization
+    }
+
+    /// Returns the additional similarity metrics with their weights.
+    pub fn metrics(&self) -> &[(WeightedMetric, Arc<dyn SimilarityMetric>)]
+    {
+        &self.metrics
+    }
+
+    /// Returns the weights of the additional similarity metrics, to build them again over new entries.
+    pub fn weighted_metrics(&self) -> Vec<WeightedMetric>
+    {
+        self.metrics.iter().map(|(metric, _)| *metric).collect()
+    }
+
+    /// Returns the length of the longest dictionary entry in text units, used to normalize Levenshtein distances.
+    pub fn max_length(&self) -> usize
+    {
+        self.max_length
+    }
+
+    /// Finds the scores of a token using the index.
+    ///
+    /// # Parameters
+    /// - `token`: The token to look up.
+    ///
+    /// # Returns
+    /// The same scores as `best_matches_brute_force`.
+    pub fn best_matches(&self, token: &str) -> TokenScores
+    {
+        let (min_levenshtein, best_entry) = self.nearest(token);
+
+        TokenScores {
+            min_levenshtein,
+            best_entry,
+            max_dice_coefficient: self.max_dice_coefficient(token),
+        }
+    }
+
+    /// Finds the entry closest to a token, preferring the lowest entry index among equal distances.
+    fn nearest(&self, token: &str) -> (usize, Option<usize>)
+    {
+        let mut best: (usize, Option<usize>) = (usize::MAX, None);
+
+        let consider = |distance: usize, entry: usize, best: &mut (usize, Option<usize>)| {
+            let better = match best.1
+            {
+                Some(best_entry) => (distance, entry) < (best.0, best_entry),
+                None => true,
+            };
+            if better
+            {
+                *best = (distance, Some(entry));
+            }
+        };
+
+        if self.nodes.is_empty()
+        {
+            return best;
+        }
+
+        let mut stack: Vec<usize> = vec![0];
+        while let Some(node_index) = stack.pop()
+        {
+            let node = &self.nodes[node_index];
+            let distance = string_utils::levenshtein(token, &self.entries[node.entry], self.text_unit);
+            consider(distance, node.entry, &mut best);
+
+            // Ties are kept so that the lowest entry index wins, hence the inclusive bounds
+            let radius = best.0;
+            for &(edge, child) in &node.children
+            {
+                if edge.saturating_add(radius) >= distance && edge <= distance.saturating_add(radius)
+                {
+                    stack.push(child);
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Finds the highest Dice coefficient of a token with the entries sharing at least one bigram with it.
+    ///
+    /// # Summary
+    /// The multiset intersection with an entry adds up, over the distinct bigrams of the token, the smaller
+    /// of the two counts, which the postings of the token's bigrams hold, so the coefficient is calculated
+    /// from the postings without comparing the strings.
+    fn max_dice_coefficient(&self, token: &str) -> f64
+    {
+        let token_profile = BigramProfile::new(token, self.text_unit);
+
+        let mut intersection_counts: HashMap<usize, usize> = HashMap::new();
+        for (bigram, token_count) in token_profile.counts()
+        {
+            if let Some(entries) = self.bigrams.get(bigram)
+            {
+                for &(entry, entry_count) in entries
+                {
+                    *intersection_counts.entry(entry).or_insert(0) += token_count.min(entry_count);
+                }
+            }
+        }
+
+        let mut max_dice_coefficient = 0.0;
+        for (entry, intersection_count) in intersection_counts
+        {
+            let total_bigrams = token_profile.total() + self.profiles[entry].total();
+            let dice_coefficient = (2 * intersection_count) as f64 / total_bigrams as f64;
+            if dice_coefficient > max_dice_coefficient
+            {
+                max_dice_coefficient = dice_coefficient;
+            }
+        }
+        max_dice_coefficient
+    }
+
+    /// Finds the highest similarity of a token with any dictionary entry for each additional metric.
+    ///
+    /// # Summary
+    /// A metric that needs a shared n-gram of at least two units is only calculated for the entries
+    /// sharing a bigram with the token, found with the bigram index. The other metrics visit the entries
+    /// by length, from the length allowing the highest similarity down, and stop once the length rules
+    /// out beating the best similarity found. The results are those of comparing with every entry.
+    ///
+    /// # Parameters
+    /// - `token`: The token to look up.
+    ///
+    /// # Returns
+    /// The highest similarity for each metric, in the order of `metrics`; 0 for an empty dictionary.
+    pub fn metric_similarities(&self, token: &str) -> Vec<f64>
+    {
+        let mut sharing_a_bigram: Option<Vec<&str>> = None;
+
+        self.metrics.iter()
+            .map(|(_, metric)| match metric.required_ngram_size()
+            {
+                Some(ngram_size) if ngram_size >= 2 =>
+                {
+                    let candidates = sharing_a_bigram.get_or_insert_with(|| self.entries_sharing_a_bigram(token));
+                    metric.best_similarity(token, candidates)
+                },
+                _ => self.best_similarity_by_length(token, metric.as_ref()),
+            })
+            .collect()
+    }
+
+    /// Returns the entries sharing at least one bigram with a token, in entry order.
+    fn entries_sharing_a_bigram(&self, token: &str) -> Vec<&str>
+    {
+        let mut entries: Vec<usize> = BigramProfile::new(token, self.text_unit).counts()
+            .filter_map(|(bigram, _)| self.bigrams.get(bigram))
+            .flatten()
+            .map(|&(entry, _)| entry)
+            .collect::<HashSet<usize>>()
+            .into_iter()
+            .collect();
+        entries.sort_unstable();
+
+        entries.into_iter().map(|entry| self.entries[entry].as_str()).collect()
+    }
+
+    /// Finds the highest similarity of a token with any entry, visiting the entries by length from the
+    /// highest upper bound of the metric down.
+    fn best_similarity_by_length(&self, token: &str, metric: &dyn SimilarityMetric) -> f64
+    {
+        let token_length = string_utils::unit_count(token, self.text_unit);
+
+        let mut lengths: Vec<(f64, &Vec<usize>)> = self.lengths.iter()
+            .map(|(&length, entries)| (metric.upper_bound(token_length, length), entries))
+            .collect();
+        lengths.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut best = 0.0;
+        for (upper_bound, entries) in lengths
+        {
+            if upper_bound <= best
+            {
+                break;
+            }
+
+            let patterns: Vec<&str> = entries.iter().map(|&entry| self.entries[entry].as_str()).collect();
+            best = metric.best_similarity(token, &patterns).max(best);
+        }
+        best
+    }
+
+    /// Finds the scores of a token by comparing it with every dictionary entry.
+    ///
+    /// # Parameters
+    /// - `token`: The token to look up.
+    ///
+    /// # Returns
+    /// The minimum Levenshtein distance (first entry wins ties) and the maximum Dice coefficient.
+    pub fn best_matches_brute_force(&self, token: &str) -> TokenScores
+    {
+        let mut scores = TokenScores {
+            min_levenshtein: usize::MAX,
+            best_entry: None,
+            max_dice_coefficient: 0.0,
+        };
+
+        let token_profile = BigramProfile::new(token, self.text_unit);
+
+        for (entry_index, pattern) in self.entries.iter().enumerate()
+        {
+            let levenshtein_distance = string_utils::levenshtein(token, pattern, self.text_unit);
+            let dice_coefficient = token_profile.dice_coefficient(&self.profiles[entry_index]);
+
+            if levenshtein_distance < scores.min_levenshtein
+            {
+                scores.min_levenshtein = levenshtein_distance;
+                scores.best_entry = Some(entry_index);
+            }
+
+            if dice_coefficient > scores.max_dice_coefficient
+            {
+                scores.max_dice_coefficient = dice_coefficient;
+            }
+        }
+
+        scores
+    }
+}
+
+/// A dictionary index shared between threads that can be replaced while it is in use.
+///
+/// # Summary
+/// Readers take a snapshot of the current index and keep using it for as long as they hold it, while a
+/// writer builds the next index on the side and swaps it in (read-copy-update); the lock is only held
+/// to clone or replace the pointer, so analysis never waits for a rebuild.
+#[derive(Debug, Clone, Default)]
+pub struct SharedDictionary
+{
+    current: Arc<RwLock<Arc<DictionaryIndex>>>,
+}
+
+impl SharedDictionary
+{
+    /// Creates a shared handle holding an index.
+    pub fn new(index: DictionaryIndex) -> SharedDictionary
+    {
+        SharedDictionary { current: Arc::new(RwLock::new(Arc::new(index))) }
+    }
+
+    /// Returns a snapshot of the current index.
+    pub fn load(&self) -> Arc<DictionaryIndex>
+    {
+        Arc::clone(&self.current.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Replaces the current index; snapshots taken before keep the previous one.
+    pub fn store(&self, index: DictionaryIndex)
+    {
+        let index = Arc::new(index);
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = index;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::Random;
+    use std::collections::BTreeSet;
+    use crate::similarity::MetricKind;
+
+    const PIECES: [&str; 10] = ["a", "b", "c", "/", ".", "é", "日", "e\u{301}", "\u{301}", "😀"];
+
+    fn all_metrics() -> Vec<WeightedMetric>
+    {
+        [
+            MetricKind::DamerauLevenshtein,
+            MetricKind::JaroWinkler,
+            MetricKind::Jaccard(1),
+            MetricKind::Jaccard(2),
+            MetricKind::Jaccard(3),
+            MetricKind::TfIdfCosine(1),
+            MetricKind::TfIdfCosine(2),
+            MetricKind::TfIdfCosine(3),
+            MetricKind::LongestCommonSubstring,
+        ]
+        .into_iter()
+        .map(|kind| WeightedMetric { kind, weight: 1.0 })
+        .collect()
+    }
+
+    #[test]
+    fn entries_are_normalized_like_requests()
+    {
+        let normalization: NormalizationConfig = "percent,case".parse().unwrap();
+        let entries = vec!["/ETC/%70asswd".to_string(), "<script>".to_string()];
+        let index = DictionaryIndex::new(entries, TextUnit::Char, normalization.clone());
+
+        assert_eq!(index.entries(), ["/etc/passwd", "<script>"]);
+        assert_eq!(index.normalization(), &normalization);
+        assert_eq!(index.best_matches("/etc/passwd").min_levenshtein, 0);
+    }
+
+    #[test]
+    fn index_matches_brute_force_on_random_dictionaries()
+    {
+        let mut random = Random(0x0bad_5eed_1234_5678);
+        for unit in [TextUnit::Byte, TextUnit::Char, TextUnit::Grapheme]
+        {
+            for _ in 0..20
+            {
+                let entries: Vec<String> = (0..random.below(30)).map(|_| random.string(&PIECES, 8)).collect();
+                let index = DictionaryIndex::new(entries.clone(), unit, NormalizationConfig::default()).with_metrics(&all_metrics());
+                let all_entries: Vec<&str> = entries.iter().map(String::as_str).collect();
+
+                for _ in 0..20
+                {
+                    let token = if entries.is_empty() || random.below(4) > 0 { random.string(&PIECES, 8) } else { entries[random.below(entries.len())].clone() };
+
+                    assert_eq!(index.best_matches(&token), index.best_matches_brute_force(&token), "{:?} {:?}", token, entries);
+
+                    let expected: Vec<f64> = index.metrics().iter()
+                        .map(|(_, metric)| metric.best_similarity(&token, &all_entries))
+                        .collect();
+                    // TF-IDF sums its products in hash map order, so only the last bits may differ
+                    let similarities = index.metric_similarities(&token);
+                    assert!(
+                        similarities.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-12),
+                        "{:?} {:?} {}: {:?} != {:?}", token, entries, unit, similarities, expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn index_matches_brute_force_on_the_bundled_dictionary()
+    {
+        let entries: Vec<String> = include_str!("files/dictionary.txt").lines().map(str::to_string).collect();
+        let index = DictionaryIndex::new(entries, TextUnit::Char, NormalizationConfig::default());
+
+        // The distinct path segments and query values of a sample of the bundled requests
+        let tokens: BTreeSet<&str> = include_str!("files/logs_to_check.txt").lines()
+            .step_by(1000)
+            .flat_map(|line| line.split([' ', '/', '?', '&', '=']))
+            .filter(|token| !token.is_empty())
+            .collect();
+        assert!(tokens.len() > 30, "{}", tokens.len());
+
+        for token in tokens
+        {
+            assert_eq!(index.best_matches(token), index.best_matches_brute_force(token), "{:?}", token);
+        }
+    }
+}
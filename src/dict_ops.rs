@@ -9,18 +9,22 @@
 /// - `are_dictionaries_updated`: Compares the current hashes of log files with the stored hashes to determine if updates are needed.
 ///
 /// Modules Required:
-/// - `log_ops`: Provides operations to modify log entries.
-/// - `string_utils`: Provides string manipulation utilities.
+/// - `log_ops`: Provides the tokenization of log entries.
 /// - `file_ops`: Provides file handling utilities.
 /// - `hash_ops`: Provides hashing utilities.
+/// - `workspace`: Provides the data directory files are resolved against.
 ///
 /// Author: Lazar Marinkovic
 /// Date: July 7th, 2024
-
-mod log_ops;
-mod string_utils;
-mod file_ops;
-mod hash_ops;
+use crate::log_format::LogFormat;
+use crate::log_ops;
+use crate::normalize::NormalizationConfig;
+use crate::string_utils::TextUnit;
+use crate::file_ops;
+use crate::hash_ops;
+use crate::workspace::Workspace;
+use std::io;
+use tracing::{debug, info};
 
 /// Extract unique dictionary entries from log entries
 ///
@@ -28,20 +32,22 @@ mod hash_ops;
 /// Processes log entries to extract unique strings, excluding request types (e.g., GET, POST).
 ///
 /// # Parameters
-/// - `log_entries`: A mutable reference to a vector of log entry strings.
-/// - `delimiters`: A mutable reference to a vector of delimiter strings used for splitting log entries.
+/// - `log_entries`: The log entry strings; they are left untouched.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `normalization`: The normalization applied to the log entries before they are split.
+/// - `text_unit`: The text unit whose boundaries the delimiters have to match at.
 ///
 /// # Returns
 /// A vector of unique strings extracted from log entries.
-pub fn extract_unique_entries_from_logs(log_entries: &mut Vec<String>, delimiters: &mut Vec<String>) -> Vec<String>
+pub fn extract_unique_entries_from_logs(log_entries: &[String], format: &dyn LogFormat, delimiters: &[String], normalization: &NormalizationConfig, text_unit: TextUnit) -> Vec<String>
 {
-    println!("Extracting unique entries from logs...");
+    debug!("Extracting unique entries from logs...");
     let mut unique_entries: Vec<String> = Vec::new();
     
-    for entry in log_entries.iter_mut()
+    for entry in log_entries.iter()
     {
-        log_ops::remove_request_type_from_log(entry); // Exclude request types like GET, POST, etc.
-        let temp_entries = string_utils::split_string_by_multiple_delimiters(entry, delimiters);
+        let temp_entries = log_ops::tokenize_log_entry(entry, format, delimiters, normalization, text_unit); // Excludes request types like GET, POST, etc.
         
         for temp_entry in temp_entries
         {
@@ -62,30 +68,33 @@ pub fn extract_unique_entries_from_logs(log_entries: &mut Vec<String>, delimiter
 /// Optionally updates the dictionary if specified or if the dictionary file doesn't exist.
 ///
 /// # Parameters
-/// - `log_entries`: A mutable reference to a vector of log entry strings.
-/// - `delimiters`: A mutable reference to a vector of delimiter strings used for splitting log entries.
+/// - `log_entries`: The log entry strings; they are left untouched.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `normalization`: The normalization applied to the log entries before they are split.
+/// - `text_unit`: The text unit whose boundaries the delimiters have to match at.
 /// - `update_dictionary`: A boolean flag to force update of the dictionary.
 /// - `file_name`: The name of the file to load or save the dictionary.
 ///
 /// # Returns
-/// A vector representing the dictionary of log entries.
-pub fn generate_or_load_dictionary(log_entries: &mut Vec<String>, delimiters: &mut Vec<String>, update_dictionary: bool, file_name: String) -> Vec<String>
+/// A result containing the dictionary of log entries, or an error if the dictionary file cannot be written or read.
+pub fn generate_or_load_dictionary(log_entries: &[String], format: &dyn LogFormat, delimiters: &[String], normalization: &NormalizationConfig, text_unit: TextUnit, update_dictionary: bool, file_name: String) -> io::Result<Vec<String>>
 {
     let mut dictionary: Vec<String> = Vec::new();
     
     if update_dictionary || !file_ops::file_exists(&file_name)
     {
-        println!("Updating malicious files dictionary...");
-        dictionary = extract_unique_entries_from_logs(log_entries, delimiters);
-        file_ops::export_vector_to_file(&dictionary, &file_name);
+        info!("Updating malicious files dictionary...");
+        dictionary = extract_unique_entries_from_logs(log_entries, format, delimiters, normalization, text_unit);
+        file_ops::export_vector_to_file(&dictionary, &file_name)?;
     }
     else
     {
-        println!("Loading existing dictionary...");
-        file_ops::load_files_into_vector(&mut dictionary, vec![file_name]);
+        info!("Loading existing dictionary...");
+        file_ops::load_files_into_vector(&mut dictionary, vec![file_name])?;
     }
     
-    dictionary
+    Ok(dictionary)
 }
 
 /// Check if the log dictionaries have been updated (ex check_if_dictionaries_updated)
@@ -94,42 +103,44 @@ pub fn generate_or_load_dictionary(log_entries: &mut Vec<String>, delimiters: &m
 /// Compares the current hashes of log files with the stored hashes to determine if updates are needed.
 ///
 /// # Parameters
+/// - `workspace`: The workspace the log files and stored hashes are looked up in.
 /// - `filename_prefix`: A string representing the prefix of filenames to check.
 ///
 /// # Returns
-/// A boolean indicating whether the dictionaries have been updated.
-pub fn are_dictionaries_updated(filename_prefix: String) -> bool
+/// A result containing a boolean indicating whether the dictionaries have been updated, or an error if
+/// a log file or a stored hash cannot be read.
+pub fn are_dictionaries_updated(workspace: &Workspace, filename_prefix: String) -> io::Result<bool>
 {
-    let log_filenames = file_ops::get_filenames_with_prefix(filename_prefix);
+    let log_filenames = file_ops::get_filenames_with_prefix(workspace, filename_prefix);
     let mut current_hashes: Vec<String> = Vec::new();
     let mut stored_hashes: Vec<String> = Vec::new();
     
     for filename in &log_filenames
     {
-        current_hashes.push(hash_ops::calculate_hash(filename.clone()));
+        current_hashes.push(hash_ops::calculate_file_hash(filename.clone())?);
     }
 
     for filename in &log_filenames
     {
-        let hash_file = format!("hashes/{}", filename);
-        if file_ops::file_exists(&hash_file)
+        let hash_file = hash_ops::hash_file_name(filename);
+        if workspace.hashes_dir().join(&hash_file).exists()
         {
-            stored_hashes.push(file_ops::read_string_from_file(hash_file));
+            stored_hashes.push(file_ops::read_string_from_file(workspace, hash_file)?);
         }
         else
         {
-            return true;
+            return Ok(true);
         }
     }
 
     for (current_hash, stored_hash) in current_hashes.iter().zip(stored_hashes.iter())
     {
-        println!("Comparing {} with {}", current_hash, stored_hash);
+        debug!("Comparing {} with {}", current_hash, stored_hash);
         if current_hash != stored_hash
         {
-            return true;
+            return Ok(true);
         }
     }
     
-    false
+    Ok(false)
 }
@@ -0,0 +1,339 @@
+/// File: evaluation.rs
+///
+/// This file contains the evaluation harness used to measure detection quality on labeled datasets.
+/// The dictionary is trained from a training set, every entry of the labeled benign and malicious
+/// sets is classified with the detector's own `log_ops::is_malicious` decision, and the outcome is
+/// summarized as a confusion matrix.
+/// The file includes the following items:
+///
+/// - `ConfusionMatrix`: Counts of true/false positives and negatives, with precision, recall and F1.
+/// - `Misclassification`: A labeled entry the detector got wrong.
+/// - `EvaluationReport`: The confusion matrix and the misclassified entries of one evaluation run.
+/// - `train_dictionary`: Builds a dictionary from training log entries.
+/// - `evaluate`: Classifies the labeled benign and malicious entries and builds the report.
+/// - `format_report`: Formats a report as human readable text.
+///
+/// Modules Required:
+/// - `dict_ops`: Provides dictionary extraction from log entries.
+/// - `log_ops`: Provides the detector.
+use std::fmt::Write;
+
+use crate::dict_index::DictionaryIndex;
+use crate::log_format::LogFormat;
+use crate::dict_ops;
+use crate::log_ops::{self, Criticality, ScoringConfig};
+use crate::normalize::NormalizationConfig;
+use crate::string_utils::TextUnit;
+
+/// Counts of true/false positives and negatives, where "positive" means detected as malicious.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConfusionMatrix
+{
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub true_negatives: usize,
+    pub false_negatives: usize,
+}
+
+impl ConfusionMatrix
+{
+    /// Records the outcome of one classified entry.
+    ///
+    /// # Parameters
+    /// - `actual_malicious`: Whether the entry is labeled malicious.
+    /// - `predicted_malicious`: Whether the detector flagged the entry.
+    pub fn record(&mut self, actual_malicious: bool, predicted_malicious: bool)
+    {
+        match (actual_malicious, predicted_malicious)
+        {
+            (true, true) => self.true_positives += 1,
+            (false, true) => self.false_positives += 1,
+            (false, false) => self.true_negatives += 1,
+            (true, false) => self.false_negatives += 1,
+        }
+    }
+
+    /// Returns the number of recorded entries.
+    pub fn total(&self) -> usize
+    {
+        self.true_positives + self.false_positives + self.true_negatives + self.false_negatives
+    }
+
+    /// Returns the share of flagged entries that are malicious (0 when nothing was flagged).
+    pub fn precision(&self) -> f64
+    {
+        ratio(self.true_positives, self.true_positives + self.false_positives)
+    }
+
+    /// Returns the share of malicious entries that were flagged (0 when there are no malicious entries).
+    pub fn recall(&self) -> f64
+    {
+        ratio(self.true_positives, self.true_positives + self.false_negatives)
+    }
+
+    /// Returns the share of benign entries that were flagged (0 when there are no benign entries).
+    pub fn false_positive_rate(&self) -> f64
+    {
+        ratio(self.false_positives, self.false_positives + self.true_negatives)
+    }
+
+    /// Returns the share of entries that were classified correctly.
+    pub fn accuracy(&self) -> f64
+    {
+        ratio(self.true_positives + self.true_negatives, self.total())
+    }
+
+    /// Returns the harmonic mean of precision and recall (0 when both are 0).
+    pub fn f1(&self) -> f64
+    {
+        let precision = self.precision();
+        let recall = self.recall();
+
+        if precision + recall == 0.0
+        {
+            return 0.0;
+        }
+
+        2.0 * precision * recall / (precision + recall)
+    }
+}
+
+/// Divides two counts, returning 0 when the denominator is 0.
+fn ratio(numerator: usize, denominator: usize) -> f64
+{
+    if denominator == 0
+    {
+        return 0.0;
+    }
+
+    numerator as f64 / denominator as f64
+}
+
+/// A labeled entry the detector got wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Misclassification
+{
+    /// Whether the entry is labeled malicious (a false negative) or benign (a false positive).
+    pub actual_malicious: bool,
+    /// The 1-based line number of the entry in its labeled set.
+    pub line_number: usize,
+    /// The log entry as it appears in the labeled set.
+    pub log_entry: String,
+    /// The criticality the detector assigned to the entry.
+    pub criticality: Criticality,
+    /// The composite score the detector calculated for the entry.
+    pub composite_score: f64,
+}
+
+/// The confusion matrix and the misclassified entries of one evaluation run.
+#[derive(Debug, Default, Clone)]
+pub struct EvaluationReport
+{
+    pub matrix: ConfusionMatrix,
+    pub misclassifications: Vec<Misclassification>,
+}
+
+/// Builds a dictionary from training log entries.
+///
+/// # Parameters
+/// - `training_entries`: The training log entries; they are left untouched.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `normalization`: The normalization applied to the log entries before they are split.
+/// - `text_unit`: The text unit whose boundaries the delimiters have to match at.
+///
+/// # Returns
+/// A vector of unique dictionary entries.
+pub fn train_dictionary(training_entries: &[String], format: &dyn LogFormat, delimiters: &[String], normalization: &NormalizationConfig, text_unit: TextUnit) -> Vec<String>
+{
+    dict_ops::extract_unique_entries_from_logs(training_entries, format, delimiters, normalization, text_unit)
+}
+
+/// Classifies the labeled entries of one set and records the outcomes in the report.
+fn evaluate_set(
+    report: &mut EvaluationReport,
+    entries: &[String],
+    actual_malicious: bool,
+    format: &dyn LogFormat,
+    delimiters: &[String],
+    dictionary: &DictionaryIndex,
+    scoring: &ScoringConfig
+)
+{
+    for (index, entry) in entries.iter().enumerate()
+    {
+        let predicted_malicious = log_ops::is_malicious(entry, format, delimiters, dictionary, scoring);
+        report.matrix.record(actual_malicious, predicted_malicious);
+
+        if actual_malicious != predicted_malicious
+        {
+            // Only the misclassified entries are analyzed again, for their criticality and score
+            let result = log_ops::analyze_log_and_determine_criticality(entry, format, delimiters, dictionary, scoring);
+            report.misclassifications.push(Misclassification {
+                actual_malicious,
+                line_number: index + 1,
+                log_entry: entry.clone(),
+                criticality: result.criticality,
+                composite_score: result.composite_score,
+            });
+        }
+    }
+}
+
+/// Classifies the labeled benign and malicious entries and builds the report.
+///
+/// # Parameters
+/// - `benign`: The entries labeled benign.
+/// - `malicious`: The entries labeled malicious.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `dictionary`: The indexed dictionary of known patterns to compare against.
+/// - `scoring`: The thresholds and normalization used to determine the criticality.
+///
+/// # Returns
+/// The evaluation report.
+pub fn evaluate(
+    benign: &[String],
+    malicious: &[String],
+    format: &dyn LogFormat,
+    delimiters: &[String],
+    dictionary: &DictionaryIndex,
+    scoring: &ScoringConfig
+) -> EvaluationReport
+{
+    let mut report = EvaluationReport::default();
+    evaluate_set(&mut report, benign, false, format, delimiters, dictionary, scoring);
+    evaluate_set(&mut report, malicious, true, format, delimiters, dictionary, scoring);
+    report
+}
+
+/// Formats a report as human readable text.
+///
+/// # Parameters
+/// - `report`: The evaluation report.
+///
+/// # Returns
+/// The confusion matrix, the derived metrics and one line per misclassified entry.
+pub fn format_report(report: &EvaluationReport) -> String
+{
+    let matrix = &report.matrix;
+    let mut text = String::new();
+
+    let _ = writeln!(text, "Evaluated entries: {}", matrix.total());
+    let _ = writeln!(text, "                    predicted malicious  predicted benign");
+    let _ = writeln!(text, "actual malicious    {:>19}  {:>16}", matrix.true_positives, matrix.false_negatives);
+    let _ = writeln!(text, "actual benign       {:>19}  {:>16}", matrix.false_positives, matrix.true_negatives);
+    let _ = writeln!(text, "Precision: {:.4}", matrix.precision());
+    let _ = writeln!(text, "Recall:    {:.4}", matrix.recall());
+    let _ = writeln!(text, "F1:        {:.4}", matrix.f1());
+    let _ = writeln!(text, "Accuracy:  {:.4}", matrix.accuracy());
+    let _ = writeln!(text, "Misclassified entries: {}", report.misclassifications.len());
+
+    for misclassification in &report.misclassifications
+    {
+        let (kind, set) = if misclassification.actual_malicious
+        {
+            ("false negative", "malicious")
+        }
+        else
+        {
+            ("false positive", "benign")
+        };
+
+        let _ = writeln!(
+            text,
+            "  {} ({} set, line {}, {} {:.4}): {}",
+            kind,
+            set,
+            misclassification.line_number,
+            misclassification.criticality,
+            misclassification.composite_score,
+            misclassification.log_entry
+        );
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::log_format::RequestLineFormat;
+
+    fn strings(values: &[&str]) -> Vec<String>
+    {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn metrics_of_a_known_confusion_matrix()
+    {
+        let mut matrix = ConfusionMatrix::default();
+        for (actual_malicious, predicted_malicious, count) in [(true, true, 3), (false, true, 1), (false, false, 4), (true, false, 2)]
+        {
+            for _ in 0..count
+            {
+                matrix.record(actual_malicious, predicted_malicious);
+            }
+        }
+
+        assert_eq!(matrix, ConfusionMatrix { true_positives: 3, false_positives: 1, true_negatives: 4, false_negatives: 2 });
+        assert_eq!(matrix.total(), 10);
+        assert!((matrix.precision() - 0.75).abs() < 1e-12);
+        assert!((matrix.recall() - 0.6).abs() < 1e-12);
+        assert!((matrix.false_positive_rate() - 0.2).abs() < 1e-12);
+        assert!((matrix.accuracy() - 0.7).abs() < 1e-12);
+        // 2 * 0.75 * 0.6 / (0.75 + 0.6)
+        assert!((matrix.f1() - 2.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn empty_denominators_give_zero()
+    {
+        let matrix = ConfusionMatrix::default();
+        assert_eq!((matrix.precision(), matrix.recall(), matrix.false_positive_rate(), matrix.accuracy(), matrix.f1()), (0.0, 0.0, 0.0, 0.0, 0.0));
+
+        let missed = ConfusionMatrix { false_negatives: 2, ..ConfusionMatrix::default() };
+        assert_eq!(missed.f1(), 0.0);
+    }
+
+    #[test]
+    fn evaluation_agrees_with_scan()
+    {
+        let dictionary = DictionaryIndex::new(
+            strings(&["etc", "passwd", "<script>alert(1)<", "script>", "union", "select"]),
+            TextUnit::Char,
+            NormalizationConfig::default(),
+        );
+        let delimiters = strings(&["/", "?", "&", "=", "+"]);
+        let benign = strings(&["GET /index.html HTTP/1.1", "GET /api/invoice?id=42 HTTP/1.1", "GET /etc/passwd HTTP/1.1"]);
+        let malicious = strings(&["GET /../../etc/passwd HTTP/1.1", "GET /?q=<script>alert(1)</script> HTTP/1.1", "GET /images/logo.png HTTP/1.1"]);
+        let scoring = ScoringConfig::default();
+
+        let report = evaluate(&benign, &malicious, &RequestLineFormat, &delimiters, &dictionary, &scoring);
+
+        let logs: Vec<String> = benign.iter().chain(malicious.iter()).cloned().collect();
+        let scanned: Vec<usize> = log_ops::find_malicious_logs(&logs, &RequestLineFormat, &delimiters, &dictionary, None, &scoring, Some(2))
+            .unwrap()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+
+        // An entry is flagged by the evaluation if it is a benign entry it got wrong or a malicious entry it got right
+        let evaluated: Vec<usize> = (0..logs.len())
+            .filter(|&index| {
+                let actual_malicious = index >= benign.len();
+                let line_number = if actual_malicious { index - benign.len() + 1 } else { index + 1 };
+                let misclassified = report.misclassifications.iter()
+                    .any(|m| m.actual_malicious == actual_malicious && m.line_number == line_number);
+                actual_malicious != misclassified
+            })
+            .collect();
+
+        assert_eq!(evaluated, scanned);
+        assert_eq!(report.matrix.true_positives + report.matrix.false_positives, scanned.len());
+        assert_eq!(report.matrix.total(), logs.len());
+        assert!(!scanned.is_empty() && scanned.len() < logs.len(), "{:?}", scanned);
+    }
+}
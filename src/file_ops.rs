@@ -5,26 +5,36 @@
 ///
 /// - `read_file`: Reads the entire contents of a file into a String.
 /// - `read_file_line_by_line`: Reads a file line by line and returns a vector of strings.
+/// - `read_first_lines`: Reads at most a given number of lines from the start of a file.
 /// - `load_files_into_vector`: Loads multiple files into a vector of strings.
 /// - `load_files_into_vector_ref`: Loads multiple files into a vector of strings (alternative version).
 /// - `export_vector_to_file`: Writes a vector of strings to a file, each string on a new line.
 /// - `file_exists`: Checks if a file exists.
-/// - `get_filenames_with_prefix`: Retrieves filenames in a directory that start with a specified prefix.
-/// - `save_string_in_file`: Saves a string in a file within a specific folder.
-/// - `read_string_from_file`: Reads a string from a file in a specific folder.
+/// - `get_filenames_with_prefix`: Retrieves the files in the workspace directory that start with a specified prefix, except the tool's own outputs.
+/// - `save_string_in_file`: Saves a string in a file within the workspace hashes folder.
+/// - `read_string_from_file`: Reads a string from a file in the workspace hashes folder.
 /// - `read_file_from_specific_line`: Reads a file starting at a specific line.
 /// - `calculate_number_of_lines`: Calculates the number of lines in a file.
 /// - `append_to_file`: Appends a vector of strings to a file, each string on a new line.
 /// - `create_dir_if_not_exists`: Creates a directory if it does not exist.
+/// - `write_file_atomically`: Writes a file through a temporary file renamed into place.
+/// - `rotate_file`: Moves a file aside to the next free `<file>.<n>` name.
 ///
 /// Author: Lazar Marinkovic
 /// Date: July 7th, 2024
-
-
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Read, Write};
 use std::path::Path;
-const DIR_PATH: &str = "C:\\Users\\Lazar\\Desktop\\Projects\\fs\\files";
+use tracing::{debug, error, warn};
+
+use crate::workspace::{self, Workspace};
+
+/// Opens a file for reading; the error names the file, so a missing input is reported with its path.
+fn open_file(file_name: &str) -> io::Result<File>
+{
+    File::open(file_name).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file_name, e)))
+}
+
 /// Reads the entire contents of a file into a String.
 ///
 /// # Parameters
@@ -34,7 +44,7 @@ const DIR_PATH: &str = "C:\\Users\\Lazar\\Desktop\\Projects\\fs\\files";
 /// A result containing the file contents as a String or an error.
 pub fn read_file(file_name: &str) -> io::Result<String>
 {
-    let mut file = File::open(file_name)?;
+    let mut file = open_file(file_name)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     Ok(contents)
@@ -46,14 +56,28 @@ pub fn read_file(file_name: &str) -> io::Result<String>
 /// - `file_name`: A reference to a string slice holding the name of the file.
 ///
 /// # Returns
-/// A result containing a vector of strings, each representing a line in the file, or an error.
+/// A result containing a vector of strings, each representing a line in the file, or an error naming the file.
 pub fn read_file_line_by_line(file_name: &str) -> io::Result<Vec<String>>
 {
-    let file = File::open(file_name)?;
+    let file = open_file(file_name)?;
     let reader = BufReader::new(file);
     reader.lines().collect()
 }
 
+/// Reads at most a given number of lines from the start of a file.
+///
+/// # Parameters
+/// - `file_name`: A reference to a string slice holding the name of the file.
+/// - `count`: The maximum number of lines to read.
+///
+/// # Returns
+/// A result containing the lines or an error.
+pub fn read_first_lines(file_name: &str, count: usize) -> io::Result<Vec<String>>
+{
+    let file = open_file(file_name)?;
+    BufReader::new(file).lines().take(count).collect()
+}
+
 /// Loads multiple files into a vector of strings.
 ///
 /// # Parameters
@@ -80,6 +104,7 @@ pub fn load_files_into_vector(vector: &mut Vec<String>, file_names: Vec<String>)
 ///
 /// # Returns
 /// A result indicating success or failure.
+#[allow(dead_code)]
 pub fn load_files_into_vector_ref(v: &mut Vec<String>, file_names: &Vec<String>) -> io::Result<()>
 {
     for file_name in file_names
@@ -100,12 +125,12 @@ pub fn load_files_into_vector_ref(v: &mut Vec<String>, file_names: &Vec<String>)
 /// A result indicating success or failure.
 pub fn export_vector_to_file(v: &[String], file_name: &str) -> io::Result<()>
 {
-    let mut file = File::create(file_name)?;
+    let mut contents: Vec<u8> = Vec::new();
     for line in v
     {
-        writeln!(file, "{}", line)?;
+        writeln!(contents, "{}", line)?;
     }
-    Ok(())
+    write_file_atomically(file_name, &contents)
 }
 
 /// Checks if a file exists.
@@ -120,63 +145,91 @@ pub fn file_exists(file_name: &str) -> bool
     Path::new(file_name).exists()
 }
 
-/// Retrieves filenames in a directory that start with a specified prefix.
+/// Retrieves the files in the workspace directory whose names start with a specified prefix.
+///
+/// # Summary
+/// Files written by the tool (see `Workspace::is_output`) are skipped, so a report saved in the data
+/// directory is never loaded back as training data or as logs to check.
 ///
 /// # Parameters
+/// - `workspace`: The workspace whose root directory is searched.
 /// - `filename_prefix`: A string holding the prefix of filenames to retrieve.
 ///
 /// # Returns
-/// A vector of filenames.
-pub fn get_filenames_with_prefix(filename_prefix: String) -> Vec<String>
+/// A sorted vector of paths, resolved against the workspace root.
+pub fn get_filenames_with_prefix(workspace: &Workspace, filename_prefix: String) -> Vec<String>
 {
     let mut filenames: Vec<String> = Vec::new();
-    
-    if let Err(e) = std::env::set_current_dir(DIR_PATH)
+
+    let entries = match fs::read_dir(workspace.root())
     {
-        eprintln!("Failed to set current directory: {}", e);
-    }
-    
-    for entry in fs::read_dir(".").unwrap()
+        Ok(entries) => entries,
+        Err(e) =>
+        {
+            error!("Failed to read directory {}: {}", workspace.root().display(), e);
+            return filenames;
+        }
+    };
+
+    for entry in entries.flatten()
     {
-        let entry = entry.unwrap();
         let path = entry.path();
-        let filename = path.file_name().unwrap().to_str().unwrap();
-        
-        if filename.starts_with(&filename_prefix)
+        if !path.is_file()
         {
-            filenames.push(filename.to_string());
+            continue;
+        }
+
+        if let Some(filename) = path.file_name().and_then(|name| name.to_str())
+        {
+            if filename.starts_with(&filename_prefix)
+            {
+                if workspace.is_output(&path)
+                {
+                    debug!("Skipping output file {}", path.display());
+                    continue;
+                }
+
+                filenames.push(path.to_string_lossy().into_owned());
+            }
         }
     }
+
+    filenames.sort();
     filenames
 }
 
-/// Saves a string in a file within a specific folder.
+/// Saves a string in a file within the workspace hashes folder.
 ///
 /// # Parameters
+/// - `workspace`: The workspace whose hashes folder is written to.
 /// - `string_to_save`: A string to save.
 /// - `file_name`: A string holding the name of the file to save to.
-pub fn save_string_in_file(string_to_save: String, file_name: String)
+///
+/// # Returns
+/// A result indicating failure to create the folder or to write the file.
+pub fn save_string_in_file(workspace: &Workspace, string_to_save: String, file_name: String) -> io::Result<()>
 {
-    const DIR_PATH: &str = "hashes";
-    
-    fs::create_dir_all(DIR_PATH).unwrap();
-    let mut file = File::create(format!("{}/{}", DIR_PATH, file_name)).unwrap();
-    file.write_all(string_to_save.as_bytes()).unwrap();
+    let dir_path = workspace.hashes_dir();
+
+    fs::create_dir_all(&dir_path)?;
+    let mut file = File::create(dir_path.join(file_name))?;
+    file.write_all(string_to_save.as_bytes())
 }
 
-/// Reads a string from a file in a specific folder.
+/// Reads a string from a file in the workspace hashes folder.
 ///
 /// # Parameters
+/// - `workspace`: The workspace whose hashes folder is read from.
 /// - `file_name`: A string holding the name of the file to read from.
 ///
 /// # Returns
-/// The contents of the file as a string.
-pub fn read_string_from_file(file_name: String) -> String
+/// A result containing the contents of the file as a string, or an error if it cannot be read.
+pub fn read_string_from_file(workspace: &Workspace, file_name: String) -> io::Result<String>
 {
-    let mut file = File::open(format!("hashes/{}", file_name)).unwrap();
+    let mut file = File::open(workspace.hashes_dir().join(file_name))?;
     let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    contents
+    file.read_to_string(&mut contents)?;
+    Ok(contents)
 }
 
 /// Reads a file starting at a specific line.
@@ -187,6 +240,7 @@ pub fn read_string_from_file(file_name: String) -> String
 ///
 /// # Returns
 /// A vector of strings from the specified line to the end of the file.
+#[allow(dead_code)]
 pub fn read_file_from_specific_line(file_name: String, line_number: usize) -> Vec<String>
 {
     let file = File::open(file_name).unwrap();
@@ -216,6 +270,7 @@ pub fn read_file_from_specific_line(file_name: String, line_number: usize) -> Ve
 ///
 /// # Returns
 /// The number of lines in the file.
+#[allow(dead_code)]
 pub fn calculate_number_of_lines(file_name: String) -> usize
 {
     let file = match File::open(&file_name)
@@ -223,7 +278,7 @@ pub fn calculate_number_of_lines(file_name: String) -> usize
         Ok(file) => file,
         Err(err) =>
         {
-            println!("Failed to open file {}: {}", file_name, err);
+            warn!("Failed to open file {}: {}", file_name, err);
             return 0;
         }
     };
@@ -240,9 +295,10 @@ pub fn calculate_number_of_lines(file_name: String) -> usize
 ///
 /// # Returns
 /// A result indicating success or failure.
+#[allow(dead_code)]
 pub fn append_to_file(v: &[String], file_name: &str) -> io::Result<()>
 {
-    let mut file = File::options().append(true).open(file_name)?;
+    let mut file = File::options().create(true).append(true).open(file_name)?;
     for line in v
     {
         writeln!(file, "{}", line)?;
@@ -257,6 +313,7 @@ pub fn append_to_file(v: &[String], file_name: &str) -> io::Result<()>
 ///
 /// # Returns
 /// A result indicating success or failure.
+#[allow(dead_code)]
 pub fn create_dir_if_not_exists(dir: &str) -> io::Result<()>
 {
     if !Path::new(dir).exists()
@@ -265,3 +322,132 @@ pub fn create_dir_if_not_exists(dir: &str) -> io::Result<()>
     }
     Ok(())
 }
+
+/// Writes a file through a temporary file renamed into place.
+///
+/// # Summary
+/// The contents are written to a hidden temporary file next to the target and flushed to disk before
+/// the temporary file replaces the target, so readers see either the old or the new file, never a
+/// partially written one.
+///
+/// # Parameters
+/// - `file_name`: A reference to a string slice holding the name of the file.
+/// - `contents`: The new contents of the file.
+///
+/// # Returns
+/// A result indicating success or failure; the target is left untouched on failure.
+pub fn write_file_atomically(file_name: &str, contents: &[u8]) -> io::Result<()>
+{
+    let path = Path::new(file_name);
+    let name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file name", file_name)))?;
+    let temp_path = path.with_file_name(format!(".{}.{}.{}", name.to_string_lossy(), std::process::id(), workspace::TEMP_FILE_EXTENSION));
+
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err()
+    {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Moves a file aside to the next free `<file>.<n>` name, starting at 1.
+///
+/// # Parameters
+/// - `file_name`: A reference to a string slice holding the name of the file.
+///
+/// # Returns
+/// A result containing the new name of the file, or `None` if the file does not exist.
+pub fn rotate_file(file_name: &str) -> io::Result<Option<String>>
+{
+    if !file_exists(file_name)
+    {
+        return Ok(None);
+    }
+
+    let rotated_name = (1..)
+        .map(|number| format!("{}.{}", file_name, number))
+        .find(|name| !file_exists(name))
+        .expect("some rotation number is free");
+
+    fs::rename(file_name, &rotated_name)?;
+    Ok(Some(rotated_name))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::test_dir;
+
+    fn file_names(directory: &Path) -> Vec<String>
+    {
+        let mut names: Vec<String> = fs::read_dir(directory).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn atomic_write_replaces_the_file()
+    {
+        let directory = test_dir("file-ops-atomic");
+        let file_name = directory.join("report.txt").to_string_lossy().into_owned();
+
+        write_file_atomically(&file_name, b"first\n").unwrap();
+        write_file_atomically(&file_name, b"second\n").unwrap();
+
+        assert_eq!(read_file(&file_name).unwrap(), "second\n");
+        assert_eq!(file_names(&directory), ["report.txt"]);
+    }
+
+    #[test]
+    fn failed_atomic_write_leaves_no_temporary_file()
+    {
+        let directory = test_dir("file-ops-atomic-failure");
+        // A directory cannot be replaced by a file
+        fs::create_dir(directory.join("report.txt")).unwrap();
+
+        assert!(write_file_atomically(&directory.join("report.txt").to_string_lossy(), b"lost\n").is_err());
+        assert!(directory.join("report.txt").is_dir());
+        assert_eq!(file_names(&directory), ["report.txt"]);
+
+        assert_eq!(write_file_atomically("", b"").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rotation_uses_the_next_free_number()
+    {
+        let directory = test_dir("file-ops-rotate");
+        let file_name = directory.join("malicious_logs.txt").to_string_lossy().into_owned();
+
+        assert_eq!(rotate_file(&file_name).unwrap(), None);
+
+        fs::write(&file_name, "first").unwrap();
+        assert_eq!(rotate_file(&file_name).unwrap(), Some(format!("{}.1", file_name)));
+
+        fs::write(&file_name, "second").unwrap();
+        assert_eq!(rotate_file(&file_name).unwrap(), Some(format!("{}.2", file_name)));
+
+        assert_eq!(file_names(&directory), ["malicious_logs.txt.1", "malicious_logs.txt.2"]);
+        assert_eq!(read_file(&format!("{}.1", file_name)).unwrap(), "first");
+        assert_eq!(read_file(&format!("{}.2", file_name)).unwrap(), "second");
+    }
+
+    #[test]
+    fn open_errors_name_the_file()
+    {
+        let file_name = test_dir("file-ops-missing").join("missing.txt").to_string_lossy().into_owned();
+        let error = read_file_line_by_line(&file_name).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().starts_with(&file_name));
+    }
+}
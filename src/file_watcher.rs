@@ -0,0 +1,481 @@
+/// File: file_watcher.rs
+///
+/// This file contains the file system watching layer of the live watcher. Watching is abstracted behind
+/// the `FileWatcher` trait, so the watcher threads only see created, modified, removed and renamed paths.
+/// Two backends are available: the native one, which receives the notifications of the operating system
+/// (inotify on Linux) through `notify`, and a polling one, which compares the size, modification time and
+/// inode of the files at an interval. Polling works where native notifications do not fire, such as
+/// network file systems and bind mounts in containers, and its scans can be driven by hand with
+/// `PollingWatcher::poll`, which makes its event sequences deterministic.
+/// The file includes the following items:
+///
+/// - `FileEvent`: A change of a watched path.
+/// - `WatcherKind`: The available watching backends.
+/// - `FileWatcher`: The trait of the watching backends.
+/// - `NativeWatcher`: Watches paths through the notifications of the operating system.
+/// - `PollingWatcher`: Watches paths by comparing their metadata at an interval.
+/// - `create_file_watcher`: Creates a watcher of the given kind.
+///
+/// Modules Required:
+/// - `checkpoint`: Provides the inode of the files, which pairs the two sides of a rename when polling.
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{debug, warn};
+
+use crate::checkpoint;
+
+/// A change of a watched path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileEvent
+{
+    Created(PathBuf),
+    Modified(PathBuf),
+    Removed(PathBuf),
+    /// A file moved from the first path to the second, both watched.
+    Renamed(PathBuf, PathBuf),
+}
+
+impl FileEvent
+{
+    /// Returns the paths the event is about.
+    pub fn paths(&self) -> Vec<&Path>
+    {
+        match self
+        {
+            FileEvent::Created(path) | FileEvent::Modified(path) | FileEvent::Removed(path) => vec![path.as_path()],
+            // Both names matter: the old one may be a followed file being rotated, the new one a file to follow
+            FileEvent::Renamed(from, to) => vec![from.as_path(), to.as_path()],
+        }
+    }
+}
+
+/// The available watching backends.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WatcherKind
+{
+    /// The notifications of the operating system.
+    #[default]
+    Native,
+    /// Comparing the metadata of the files at an interval.
+    Poll,
+}
+
+impl fmt::Display for WatcherKind
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let name = match self
+        {
+            WatcherKind::Native => "native",
+            WatcherKind::Poll => "poll",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for WatcherKind
+{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        match name.to_ascii_lowercase().as_str()
+        {
+            "native" | "inotify" => Ok(WatcherKind::Native),
+            "poll" | "polling" => Ok(WatcherKind::Poll),
+            _ => Err(format!("unknown watcher '{}' (expected native or poll)", name)),
+        }
+    }
+}
+
+/// The trait of the watching backends.
+pub trait FileWatcher: Send
+{
+    /// Starts watching a directory.
+    ///
+    /// # Parameters
+    /// - `directory`: The directory to watch.
+    /// - `recursive`: Whether to watch its subdirectories too.
+    ///
+    /// # Returns
+    /// A result indicating failure to watch the directory.
+    fn watch(&mut self, directory: &Path, recursive: bool) -> io::Result<()>;
+
+    /// Waits for the next change of a watched path.
+    ///
+    /// # Parameters
+    /// - `timeout`: The longest time to wait.
+    ///
+    /// # Returns
+    /// A result containing the change, `None` if nothing changed before the timeout, or an error if the
+    /// watcher stopped working.
+    fn next_event(&mut self, timeout: Duration) -> io::Result<Option<FileEvent>>;
+}
+
+/// Watches paths through the notifications of the operating system.
+pub struct NativeWatcher
+{
+    /// Kept alive for the notifications to be sent.
+    watcher: RecommendedWatcher,
+    notifications: Receiver<notify::Result<Event>>,
+    pending: VecDeque<FileEvent>,
+}
+
+impl NativeWatcher
+{
+    /// Creates a watcher that watches nothing yet.
+    ///
+    /// # Returns
+    /// A result containing the watcher, or an error if the operating system refuses to create it.
+    pub fn new() -> io::Result<NativeWatcher>
+    {
+        let (sender, notifications) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+        Ok(NativeWatcher { watcher, notifications, pending: VecDeque::new() })
+    }
+}
+
+/// Converts a notification of the operating system into the changes it reports.
+fn file_events(event: Event) -> Vec<FileEvent>
+{
+    let mut paths = event.paths.into_iter();
+
+    match event.kind
+    {
+        EventKind::Create(_) => paths.map(FileEvent::Created).collect(),
+        EventKind::Remove(_) => paths.map(FileEvent::Removed).collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => match (paths.next(), paths.next())
+        {
+            (Some(from), Some(to)) => vec![FileEvent::Renamed(from, to)],
+            (Some(path), None) => vec![FileEvent::Modified(path)],
+            _ => Vec::new(),
+        },
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => paths.map(FileEvent::Removed).collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => paths.map(FileEvent::Created).collect(),
+        EventKind::Modify(_) | EventKind::Any | EventKind::Other => paths.map(FileEvent::Modified).collect(),
+        EventKind::Access(_) => Vec::new(),
+    }
+}
+
+impl FileWatcher for NativeWatcher
+{
+    fn watch(&mut self, directory: &Path, recursive: bool) -> io::Result<()>
+    {
+        let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        self.watcher.watch(directory, mode).map_err(io::Error::other)
+    }
+
+    fn next_event(&mut self, timeout: Duration) -> io::Result<Option<FileEvent>>
+    {
+        let deadline = Instant::now() + timeout;
+
+        while self.pending.is_empty()
+        {
+            match self.notifications.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(Ok(event)) => self.pending.extend(file_events(event)),
+                Ok(Err(e)) => warn!("File system notification error: {}", e),
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => return Err(io::Error::other("the file system watcher stopped")),
+            }
+        }
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// The metadata of a file compared between two scans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileState
+{
+    inode: u64,
+    length: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Watches paths by comparing their metadata at an interval.
+#[derive(Debug)]
+pub struct PollingWatcher
+{
+    interval: Duration,
+    directories: Vec<(PathBuf, bool)>,
+    /// The files found by the last scan.
+    files: BTreeMap<PathBuf, FileState>,
+    last_scan: Option<Instant>,
+    pending: VecDeque<FileEvent>,
+}
+
+impl PollingWatcher
+{
+    /// Creates a watcher that watches nothing yet.
+    ///
+    /// # Parameters
+    /// - `interval`: The time between two scans done by `next_event`.
+    pub fn new(interval: Duration) -> PollingWatcher
+    {
+        PollingWatcher { interval, directories: Vec::new(), files: BTreeMap::new(), last_scan: None, pending: VecDeque::new() }
+    }
+
+    /// Scans the watched directories now and returns the changes since the previous scan.
+    ///
+    /// # Summary
+    /// A path that disappeared and a path that appeared with the same inode are reported as one rename.
+    /// A path that now refers to another file, e.g. after logrotate renamed it and created it again, is
+    /// reported as created, and its old file can be part of a rename.
+    /// The changes are ordered by path, so the same file operations always produce the same events.
+    ///
+    /// # Returns
+    /// A result containing the changes, or an error if a watched directory cannot be read.
+    pub fn poll(&mut self) -> io::Result<Vec<FileEvent>>
+    {
+        let mut files = BTreeMap::new();
+        for (directory, recursive) in &self.directories
+        {
+            scan_directory(directory, *recursive, &mut files)?;
+        }
+        self.last_scan = Some(Instant::now());
+
+        let replaced = |path: &PathBuf, old: &FileState| files.get(path).is_some_and(|state| state.inode != old.inode);
+        let mut removed: Vec<(&PathBuf, &FileState)> = self.files.iter()
+            .filter(|(path, old)| !files.contains_key(*path) || replaced(path, old))
+            .collect();
+        let mut events = Vec::new();
+
+        for (path, state) in &files
+        {
+            match self.files.get(path)
+            {
+                Some(old) if old.inode == state.inode =>
+                {
+                    if old != state
+                    {
+                        events.push(FileEvent::Modified(path.clone()));
+                    }
+                },
+                _ =>
+                {
+                    match removed.iter().position(|(_, old)| old.inode == state.inode && state.inode != 0)
+                    {
+                        Some(index) => events.push(FileEvent::Renamed(removed.remove(index).0.clone(), path.clone())),
+                        None => events.push(FileEvent::Created(path.clone())),
+                    }
+                },
+            }
+        }
+        // A replaced file that was not renamed is gone, but its path was already reported as created
+        events.extend(removed.into_iter()
+            .filter(|(path, _)| !files.contains_key(*path))
+            .map(|(path, _)| FileEvent::Removed(path.clone())));
+
+        self.files = files;
+        Ok(events)
+    }
+}
+
+/// Records the metadata of the files in a directory.
+fn scan_directory(directory: &Path, recursive: bool, files: &mut BTreeMap<PathBuf, FileState>) -> io::Result<()>
+{
+    for entry in fs::read_dir(directory)?
+    {
+        let entry = entry?;
+        // A file removed during the scan is simply not seen
+        let Ok(metadata) = entry.metadata() else
+        {
+            continue;
+        };
+
+        if metadata.is_dir()
+        {
+            if recursive
+            {
+                if let Err(e) = scan_directory(&entry.path(), recursive, files)
+                {
+                    debug!("Cannot scan {}: {}", entry.path().display(), e);
+                }
+            }
+            continue;
+        }
+
+        files.insert(entry.path(), FileState {
+            inode: checkpoint::file_inode(&metadata),
+            length: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    Ok(())
+}
+
+impl FileWatcher for PollingWatcher
+{
+    fn watch(&mut self, directory: &Path, recursive: bool) -> io::Result<()>
+    {
+        // The first scan of the directory only records the existing files
+        scan_directory(directory, recursive, &mut self.files)?;
+        self.directories.push((directory.to_path_buf(), recursive));
+        self.last_scan = Some(Instant::now());
+        Ok(())
+    }
+
+    fn next_event(&mut self, timeout: Duration) -> io::Result<Option<FileEvent>>
+    {
+        let deadline = Instant::now() + timeout;
+
+        while self.pending.is_empty()
+        {
+            let next_scan = self.last_scan.map_or_else(Instant::now, |last_scan| last_scan + self.interval);
+            if next_scan > deadline
+            {
+                thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                return Ok(None);
+            }
+
+            thread::sleep(next_scan.saturating_duration_since(Instant::now()));
+            let events = self.poll()?;
+            self.pending.extend(events);
+        }
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// Creates a watcher of the given kind.
+///
+/// # Parameters
+/// - `kind`: The watching backend.
+/// - `poll_interval`: The time between two scans of the polling backend.
+///
+/// # Returns
+/// A result containing the watcher, or an error if the native watcher cannot be created.
+pub fn create_file_watcher(kind: WatcherKind, poll_interval: Duration) -> io::Result<Box<dyn FileWatcher>>
+{
+    match kind
+    {
+        WatcherKind::Native => Ok(Box::new(NativeWatcher::new()?)),
+        WatcherKind::Poll => Ok(Box::new(PollingWatcher::new(poll_interval))),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::{append, test_dir};
+
+    fn watcher_of(directory: &Path, recursive: bool) -> PollingWatcher
+    {
+        let mut watcher = PollingWatcher::new(Duration::from_millis(10));
+        watcher.watch(directory, recursive).unwrap();
+        watcher
+    }
+
+    #[test]
+    fn existing_files_produce_no_events()
+    {
+        let directory = test_dir("watcher-existing");
+        append(&directory.join("app.log"), "a\n");
+
+        let mut watcher = watcher_of(&directory, false);
+        assert_eq!(watcher.poll().unwrap(), []);
+    }
+
+    #[test]
+    fn reports_create_append_and_remove()
+    {
+        let directory = test_dir("watcher-lifecycle");
+        let log = directory.join("app.log");
+        let mut watcher = watcher_of(&directory, false);
+
+        append(&log, "a\n");
+        assert_eq!(watcher.poll().unwrap(), [FileEvent::Created(log.clone())]);
+
+        append(&log, "b\n");
+        assert_eq!(watcher.poll().unwrap(), [FileEvent::Modified(log.clone())]);
+        assert_eq!(watcher.poll().unwrap(), []);
+
+        fs::remove_file(&log).unwrap();
+        assert_eq!(watcher.poll().unwrap(), [FileEvent::Removed(log)]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn pairs_a_rename_by_inode_and_orders_events_by_path()
+    {
+        let directory = test_dir("watcher-rename");
+        let log = directory.join("app.log");
+        let rotated = directory.join("app.log.1");
+        append(&log, "a\n");
+        let mut watcher = watcher_of(&directory, false);
+
+        fs::rename(&log, &rotated).unwrap();
+        append(&log, "b\n");
+        append(&directory.join("other.log"), "c\n");
+
+        assert_eq!(watcher.poll().unwrap(), [
+            FileEvent::Created(log.clone()),
+            FileEvent::Renamed(log, rotated),
+            FileEvent::Created(directory.join("other.log")),
+        ]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reports_a_plain_rename_and_a_recreated_path()
+    {
+        let directory = test_dir("watcher-recreate");
+        let log = directory.join("app.log");
+        let moved = directory.join("moved.log");
+        append(&log, "a\n");
+        let mut watcher = watcher_of(&directory, false);
+
+        fs::rename(&log, &moved).unwrap();
+        assert_eq!(watcher.poll().unwrap(), [FileEvent::Renamed(log, moved.clone())]);
+
+        // Keep the removed file open so the new one cannot reuse its inode
+        let _removed = fs::File::open(&moved).unwrap();
+        fs::remove_file(&moved).unwrap();
+        append(&moved, "b\n");
+        assert_eq!(watcher.poll().unwrap(), [FileEvent::Created(moved)]);
+    }
+
+    #[test]
+    fn scans_subdirectories_only_when_recursive()
+    {
+        let directory = test_dir("watcher-recursive");
+        let nested = directory.join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        let mut flat = watcher_of(&directory, false);
+        let mut recursive = watcher_of(&directory, true);
+
+        append(&nested.join("app.log"), "a\n");
+        assert_eq!(flat.poll().unwrap(), []);
+        assert_eq!(recursive.poll().unwrap(), [FileEvent::Created(nested.join("app.log"))]);
+    }
+
+    #[test]
+    fn next_event_returns_the_events_in_order_then_times_out()
+    {
+        let directory = test_dir("watcher-next-event");
+        let first = directory.join("a.log");
+        let second = directory.join("b.log");
+        let mut watcher = watcher_of(&directory, false);
+
+        append(&second, "b\n");
+        append(&first, "a\n");
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(watcher.next_event(timeout).unwrap(), Some(FileEvent::Created(first)));
+        assert_eq!(watcher.next_event(timeout).unwrap(), Some(FileEvent::Created(second)));
+        assert_eq!(watcher.next_event(Duration::from_millis(50)).unwrap(), None);
+    }
+}
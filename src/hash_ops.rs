@@ -4,65 +4,125 @@
 /// These hashes are used to identify and compare content, particularly for detecting changes or ensuring integrity.
 /// The file includes the following functions:
 ///
-/// - `calculate_hash_from_object`: Calculates a hash for a given input using the `DefaultHasher`.
-/// - `calculate_file_hash`: Calculates the hash of a file's content.
+/// - `calculate_stable_hash`: Calculates the FNV-1a hash of bytes, which is stable across Rust releases.
+/// - `calculate_file_hash`: Calculates the stable hash of a file's content.
+/// - `hash_file_name`: Returns the name under which the hash of a file is stored.
 /// - `calculate_and_save_file_hashes`: Calculates and saves the hashes of files that start with a given prefix.
 ///
 /// Modules Required:
 /// - `file_ops`: Provides file handling utilities.
+/// - `workspace`: Provides the data directory files are resolved against.
 ///
 /// Author: Lazar Marinkovic
 /// Date: July 7th, 2024
+use std::io;
+use std::path::Path;
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::Hash;
-use std::hash::Hasher;
-mod file_ops;
+use crate::file_ops;
+use crate::workspace::Workspace;
 
-/// Calculates a hash for a given input using the `DefaultHasher`.
+/// FNV-1a offset basis for 64-bit hashes.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+/// FNV-1a prime for 64-bit hashes.
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Calculates the 64-bit FNV-1a hash of bytes.
+///
+/// # Summary
+/// Unlike the standard library's `DefaultHasher`, the result does not depend on the Rust release, so it
+/// can be persisted, e.g. in checkpoint files and stored file hashes.
 ///
 /// # Parameters
-/// - `input`: A reference to the input to hash, which implements the `Hash` trait.
+/// - `bytes`: The bytes to hash.
 ///
 /// # Returns
 /// The calculated hash as a `u64`.
-pub fn calculate_hash_from_object<T: Hash>(input: &T) -> u64
+pub fn calculate_stable_hash(bytes: &[u8]) -> u64
 {
-    let mut hasher = DefaultHasher::new();
-    input.hash(&mut hasher);
-    hasher.finish()
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME))
 }
 
 /// Calculates the hash of a file's content.
 ///
+/// # Summary
+/// The stable hash is used, so the stored hashes still match after the tool is rebuilt with another
+/// Rust release and the dictionary is not rebuilt needlessly.
+///
 /// # Parameters
 /// - `file_name`: A string holding the name of the file.
 ///
 /// # Returns
-/// The calculated hash as a string.
-pub fn calculate_file_hash(file_name: String) -> String
+/// A result containing the calculated hash as a string, or an error if the file cannot be read.
+pub fn calculate_file_hash(file_name: String) -> io::Result<String>
+{
+    let content = file_ops::read_file(&file_name)?;
+    Ok(calculate_stable_hash(content.as_bytes()).to_string())
+}
+
+/// Returns the name under which the hash of a file is stored in the workspace hashes folder.
+///
+/// # Parameters
+/// - `file_path`: A string slice holding the path of the hashed file.
+///
+/// # Returns
+/// The file name component of the path.
+pub fn hash_file_name(file_path: &str) -> String
 {
-    let content = file_ops::read_file(&file_name).expect("Failed to read file");
-    let hash_value = calculate_hash_from_object(&content);
-    hash_value.to_string()
+    Path::new(file_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| file_path.to_string())
 }
 
 /// Calculates and saves the hashes of files that start with a given prefix.
 ///
 /// # Parameters
+/// - `workspace`: The workspace the files are looked up in and the hashes are saved to.
 /// - `filename_prefix`: A string representing the prefix of filenames to hash and save.
-pub fn calculate_and_save_file_hashes(filename_prefix: String)
+///
+/// # Returns
+/// A result indicating failure to read a file or to save its hash.
+pub fn calculate_and_save_file_hashes(workspace: &Workspace, filename_prefix: String) -> io::Result<()>
 {
-    let matching_filenames = file_ops::get_filenames_with_prefix(filename_prefix);
+    let matching_filenames = file_ops::get_filenames_with_prefix(workspace, filename_prefix);
     let mut file_hashes: Vec<String> = Vec::new();
     
     for filename in &matching_filenames
     {
-        file_hashes.push(calculate_file_hash(filename.clone()));
+        file_hashes.push(calculate_file_hash(filename.clone())?);
     }
     
     for (file_hash, filename) in file_hashes.iter().zip(matching_filenames.iter())
     {
-        file_ops::save_string_in_file(file_hash.to_string(), filename.to_string());
+        file_ops::save_string_in_file(workspace, file_hash.to_string(), hash_file_name(filename))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn stable_hash_matches_fnv1a_test_vectors()
+    {
+        assert_eq!(calculate_stable_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(calculate_stable_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(calculate_stable_hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn file_hash_is_the_stable_hash_of_the_content()
+    {
+        let file_name = std::env::temp_dir().join(format!("fs-hash-ops-{}.txt", std::process::id()));
+        std::fs::write(&file_name, "foobar").unwrap();
+
+        assert_eq!(calculate_file_hash(file_name.to_string_lossy().into_owned()).unwrap(), 0x8594_4171_f739_67e8_u64.to_string());
+        std::fs::remove_file(&file_name).unwrap();
+
+        // A missing file is an error rather than a panic
+        assert!(calculate_file_hash(file_name.to_string_lossy().into_owned()).is_err());
     }
 }
@@ -0,0 +1,224 @@
+/// File: json_format.rs
+///
+/// This file contains the parser of access logs shipped as JSON Lines (one JSON object per line), as
+/// written by Envoy, Traefik and cloud load balancers. The fields holding the method, URI, headers and
+/// body are selected with JSON pointers (RFC 6901, e.g. `/request/uri`), so any record layout can be read.
+/// The file includes the following items:
+///
+/// - `JsonFields`: The JSON pointers of the fields read from every record.
+/// - `JsonLinesFormat`: The JSON Lines log format.
+///
+/// Modules Required:
+/// - `log_format`: Provides the log format trait and the request record.
+use serde_json::Value;
+
+use crate::log_format::{LogFormat, RequestRecord};
+
+/// The JSON pointers of the fields read from every record; an empty pointer selects the whole record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonFields
+{
+    /// Pointer to the HTTP method.
+    pub method: Option<String>,
+    /// Pointer to the request URI (path and query string).
+    pub uri: String,
+    /// Pointer to the request headers: an object, an array of `name: value` strings or
+    /// `{"name", "value"}` objects, or a string with one header per line.
+    pub headers: Option<String>,
+    /// Pointer to the request body.
+    pub body: Option<String>,
+}
+
+impl Default for JsonFields
+{
+    fn default() -> Self
+    {
+        JsonFields {
+            method: Some("/method".to_string()),
+            uri: "/uri".to_string(),
+            headers: None,
+            body: None,
+        }
+    }
+}
+
+/// The JSON Lines log format.
+#[derive(Debug, Clone, Default)]
+pub struct JsonLinesFormat
+{
+    fields: JsonFields,
+}
+
+impl JsonLinesFormat
+{
+    /// Creates the format reading the given fields.
+    pub fn new(fields: JsonFields) -> JsonLinesFormat
+    {
+        JsonLinesFormat { fields }
+    }
+}
+
+/// Returns the text of a JSON value: strings without their quotes, other values serialized.
+fn value_text(value: &Value) -> Option<String>
+{
+    match value
+    {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Splits a `name: value` header line.
+fn split_header(line: &str) -> Option<(String, String)>
+{
+    let (name, value) = line.split_once(':')?;
+    Some((name.trim().to_string(), value.trim().to_string()))
+}
+
+/// Collects the headers of a record from the value selected by the headers pointer.
+fn collect_headers(value: &Value) -> Vec<(String, String)>
+{
+    match value
+    {
+        Value::Object(map) => map.iter()
+            .filter_map(|(name, value)| value_text(value).map(|text| (name.clone(), text)))
+            .collect(),
+        Value::Array(items) => items.iter()
+            .filter_map(|item| match item
+            {
+                Value::String(line) => split_header(line),
+                Value::Object(map) =>
+                {
+                    let name = map.get("name").and_then(value_text)?;
+                    let value = map.get("value").and_then(value_text)?;
+                    Some((name, value))
+                },
+                _ => None,
+            })
+            .collect(),
+        Value::String(block) => block.lines().filter_map(split_header).collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl LogFormat for JsonLinesFormat
+{
+    fn name(&self) -> &'static str
+    {
+        "json"
+    }
+
+    fn parse(&self, line: &str) -> Option<RequestRecord>
+    {
+        let record: Value = serde_json::from_str(line.trim()).ok()?;
+        let uri = record.pointer(&self.fields.uri).and_then(value_text)?;
+
+        let mut request = RequestRecord {
+            method: self.fields.method.as_ref()
+                .and_then(|pointer| record.pointer(pointer))
+                .and_then(value_text),
+            body: self.fields.body.as_ref()
+                .and_then(|pointer| record.pointer(pointer))
+                .and_then(value_text),
+            ..RequestRecord::default()
+        };
+        request.set_target(&uri);
+
+        if let Some(headers) = self.fields.headers.as_ref().and_then(|pointer| record.pointer(pointer))
+        {
+            request.headers = collect_headers(headers);
+        }
+
+        Some(request)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn format(headers: Option<&str>, body: Option<&str>) -> JsonLinesFormat
+    {
+        JsonLinesFormat::new(JsonFields {
+            headers: headers.map(str::to_string),
+            body: body.map(str::to_string),
+            ..JsonFields::default()
+        })
+    }
+
+    fn sorted_headers(record: &RequestRecord) -> Vec<(&str, &str)>
+    {
+        let mut headers: Vec<(&str, &str)> = record.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        headers.sort();
+        headers
+    }
+
+    #[test]
+    fn default_fields_read_method_and_uri()
+    {
+        let record = JsonLinesFormat::default().parse(r#"{"method": "GET", "uri": "/a?q=\"x\"", "status": 200}"#).unwrap();
+
+        assert_eq!(record.method.as_deref(), Some("GET"));
+        assert_eq!(record.path, "/a");
+        assert_eq!(record.query.as_deref(), Some(r#"q="x""#));
+        assert!(record.headers.is_empty());
+        assert_eq!(record.body, None);
+    }
+
+    #[test]
+    fn pointers_select_nested_fields()
+    {
+        let fields = JsonFields {
+            method: Some("/request/method".to_string()),
+            uri: "/request/path".to_string(),
+            headers: None,
+            body: Some("/request/body".to_string()),
+        };
+        let record = JsonLinesFormat::new(fields)
+            .parse(r#"{"request": {"method": null, "path": "/upload", "body": {"name": "<script>"}}}"#)
+            .unwrap();
+
+        assert_eq!(record.method, None);
+        assert_eq!(record.path, "/upload");
+        // Non-string values are analyzed in their JSON form
+        assert_eq!(record.body.as_deref(), Some(r#"{"name":"<script>"}"#));
+    }
+
+    #[test]
+    fn headers_are_read_in_every_layout()
+    {
+        let expected = vec![("Referer", "http://x"), ("User-Agent", "curl")];
+
+        let object = format(Some("/headers"), None).parse(r#"{"uri": "/", "headers": {"User-Agent": "curl", "Referer": "http://x", "X-Empty": null}}"#).unwrap();
+        assert_eq!(sorted_headers(&object), expected);
+
+        let strings = format(Some("/headers"), None).parse(r#"{"uri": "/", "headers": ["User-Agent: curl", "Referer: http://x", "malformed"]}"#).unwrap();
+        assert_eq!(sorted_headers(&strings), expected);
+
+        let pairs = format(Some("/headers"), None).parse(r#"{"uri": "/", "headers": [{"name": "User-Agent", "value": "curl"}, {"name": "Referer", "value": "http://x"}, {"name": "X"}]}"#).unwrap();
+        assert_eq!(sorted_headers(&pairs), expected);
+
+        let block = format(Some("/headers"), None).parse(r#"{"uri": "/", "headers": "User-Agent: curl\nReferer: http://x"}"#).unwrap();
+        assert_eq!(sorted_headers(&block), expected);
+
+        assert_eq!(block.analyzed_fields().len(), 3);
+    }
+
+    #[test]
+    fn records_without_a_uri_are_rejected()
+    {
+        let format = format(Some("/headers"), Some("/body"));
+
+        assert_eq!(format.parse(r#"{"method": "GET"}"#), None);
+        assert_eq!(format.parse(r#"{"uri": null}"#), None);
+        assert_eq!(format.parse(r#"{"uri": "/a""#), None);
+        assert_eq!(format.parse("GET /a HTTP/1.1"), None);
+        assert_eq!(format.parse(""), None);
+
+        // A missing optional field leaves it empty
+        let record = format.parse(r#"{"uri": "/a"}"#).unwrap();
+        assert!(record.headers.is_empty() && record.body.is_none());
+    }
+}
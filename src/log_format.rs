@@ -0,0 +1,678 @@
+/// File: log_format.rs
+///
+/// This file contains the parsers turning access log lines of the supported web server formats into a
+/// typed request record, so the detector analyzes the request target whatever the source format is.
+/// The file includes the following items:
+///
+/// - `RequestRecord`: The fields of one request (client IP, timestamp, method, path, query, protocol, status,
+///   size, referer, user agent, headers and body).
+/// - `LogFormat`: The trait implemented by every log format parser.
+/// - `RequestLineFormat`: Bare request lines (`GET /path HTTP/1.1`), as in the bundled datasets.
+/// - `CombinedLogFormat`: The Apache and nginx Combined Log Format, and the Common Log Format it extends.
+/// - `W3cExtendedFormat`: The W3C Extended Log File Format written by IIS.
+/// - `AutoFormat`: Recognizes the format of every line among the formats above and JSON Lines.
+/// - `LogFormatKind`: The name of a log format, as selected on the command line.
+/// - `create_log_format`: Creates the parser of a log format.
+/// - `request_fields`: Returns the parts of a log line the detector analyzes.
+///
+/// Modules Required:
+/// - `json_format`: Provides the JSON Lines log format.
+use std::fmt;
+use std::str::FromStr;
+
+use crate::json_format::{JsonFields, JsonLinesFormat};
+
+/// The fields of one request; fields missing from the log format are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestRecord
+{
+    pub client_ip: Option<String>,
+    pub timestamp: Option<String>,
+    pub method: Option<String>,
+    /// The path of the request target, without the query string.
+    pub path: String,
+    /// The query string of the request target, without the leading `?`.
+    pub query: Option<String>,
+    pub protocol: Option<String>,
+    pub status: Option<u16>,
+    pub size: Option<u64>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    /// The request headers, for formats that record them.
+    pub headers: Vec<(String, String)>,
+    /// The request body, for formats that record it.
+    pub body: Option<String>,
+}
+
+impl RequestRecord
+{
+    /// Returns the request target (the path followed by the query string, if any).
+    pub fn target(&self) -> String
+    {
+        match &self.query
+        {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        }
+    }
+
+    /// Returns the parts of the request the detector analyzes: the target, the header values and the body.
+    pub fn analyzed_fields(&self) -> Vec<String>
+    {
+        let mut fields = vec![self.target()];
+        fields.extend(self.headers.iter().map(|(_, value)| value.clone()));
+        fields.extend(self.body.iter().cloned());
+        fields
+    }
+
+    /// Sets the path and query string from a request target.
+    pub(crate) fn set_target(&mut self, target: &str)
+    {
+        match target.split_once('?')
+        {
+            Some((path, query)) =>
+            {
+                self.path = path.to_string();
+                self.query = Some(query.to_string());
+            },
+            None =>
+            {
+                self.path = target.to_string();
+                self.query = None;
+            },
+        }
+    }
+}
+
+/// A parser turning the lines of one access log format into request records.
+pub trait LogFormat: Send + Sync
+{
+    /// Returns the name of the format.
+    fn name(&self) -> &'static str;
+
+    /// Parses one log line.
+    ///
+    /// # Parameters
+    /// - `line`: The log line.
+    ///
+    /// # Returns
+    /// The request record, or `None` if the line is not a request of this format (e.g. a directive).
+    fn parse(&self, line: &str) -> Option<RequestRecord>;
+
+    /// Returns whether a line is a directive of the format (e.g. a W3C `#Fields` line) rather than a request.
+    fn is_directive(&self, _line: &str) -> bool
+    {
+        false
+    }
+}
+
+/// Returns whether a word looks like an HTTP method (e.g. GET, POST, PROPFIND).
+fn is_method(word: &str) -> bool
+{
+    word.starts_with(|c: char| c.is_ascii_uppercase())
+        && word.bytes().all(|b| b.is_ascii_uppercase() || b == b'-' || b == b'_')
+}
+
+/// Returns `None` for the `-` placeholder of an empty field, and the field otherwise.
+fn optional_field(field: &str) -> Option<String>
+{
+    if field.is_empty() || field == "-"
+    {
+        None
+    }
+    else
+    {
+        Some(field.to_string())
+    }
+}
+
+/// Parses a request line (`METHOD target PROTOCOL`) into the method, target and protocol of a record.
+///
+/// # Summary
+/// The method and protocol are optional; everything between them is the target, so targets containing
+/// spaces are kept whole.
+fn parse_request_line(request_line: &str, record: &mut RequestRecord) -> bool
+{
+    let request_line = request_line.trim();
+    if request_line.is_empty()
+    {
+        return false;
+    }
+
+    let rest = match request_line.split_once(' ')
+    {
+        Some((method, rest)) if is_method(method) =>
+        {
+            record.method = Some(method.to_string());
+            rest.trim_start()
+        },
+        _ if is_method(request_line) =>
+        {
+            record.method = Some(request_line.to_string());
+            ""
+        },
+        _ => request_line,
+    };
+
+    let target = match rest.rsplit_once(' ')
+    {
+        Some((target, protocol)) if protocol.starts_with("HTTP/") =>
+        {
+            record.protocol = Some(protocol.to_string());
+            target.trim_end()
+        },
+        _ => rest,
+    };
+
+    record.set_target(target);
+    true
+}
+
+/// Bare request lines (`GET /path HTTP/1.1`); the method and protocol are optional.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestLineFormat;
+
+impl LogFormat for RequestLineFormat
+{
+    fn name(&self) -> &'static str
+    {
+        "request"
+    }
+
+    fn parse(&self, line: &str) -> Option<RequestRecord>
+    {
+        let mut record = RequestRecord::default();
+        if parse_request_line(line, &mut record)
+        {
+            Some(record)
+        }
+        else
+        {
+            None
+        }
+    }
+}
+
+/// The Apache and nginx Combined Log Format:
+/// `%h %l %u [%t] "%r" %>s %b "%{Referer}i" "%{User-agent}i"`.
+///
+/// Lines without the referer and user agent (the Common Log Format) are accepted too, and fields
+/// following the user agent (e.g. nginx's `$http_x_forwarded_for`) are ignored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CombinedLogFormat;
+
+/// A cursor over the fields of a Combined Log Format line.
+struct FieldCursor<'a>
+{
+    rest: &'a str,
+}
+
+impl<'a> FieldCursor<'a>
+{
+    /// Returns the next space-separated field.
+    fn word(&mut self) -> Option<&'a str>
+    {
+        let rest = self.rest.trim_start();
+        if rest.is_empty()
+        {
+            return None;
+        }
+
+        let end = rest.find(' ').unwrap_or(rest.len());
+        self.rest = &rest[end..];
+        Some(&rest[..end])
+    }
+
+    /// Returns the next field enclosed in the given delimiters, without them.
+    fn enclosed(&mut self, open: char, close: char) -> Option<&'a str>
+    {
+        let rest = self.rest.trim_start().strip_prefix(open)?;
+
+        // Quotes inside a quoted field are escaped with a backslash
+        let mut escaped = false;
+        for (index, c) in rest.char_indices()
+        {
+            if escaped
+            {
+                escaped = false;
+            }
+            else if c == '\\'
+            {
+                escaped = true;
+            }
+            else if c == close
+            {
+                self.rest = &rest[index + c.len_utf8()..];
+                return Some(&rest[..index]);
+            }
+        }
+
+        None
+    }
+}
+
+impl LogFormat for CombinedLogFormat
+{
+    fn name(&self) -> &'static str
+    {
+        "combined"
+    }
+
+    fn parse(&self, line: &str) -> Option<RequestRecord>
+    {
+        let mut cursor = FieldCursor { rest: line.trim() };
+
+        let client_ip = optional_field(cursor.word()?);
+        cursor.word()?; // Identity of the client
+        cursor.word()?; // Authenticated user
+        let timestamp = optional_field(cursor.enclosed('[', ']')?);
+        let mut record = RequestRecord { client_ip, timestamp, ..RequestRecord::default() };
+
+        // A request line of "-" means the client sent no request
+        let request_line = cursor.enclosed('"', '"')?.replace("\\\"", "\"");
+        if request_line != "-"
+        {
+            parse_request_line(&request_line, &mut record);
+        }
+
+        record.status = cursor.word().and_then(|status| status.parse().ok());
+        record.size = cursor.word().and_then(|size| size.parse().ok());
+        record.referer = cursor.enclosed('"', '"').and_then(optional_field);
+        record.user_agent = cursor.enclosed('"', '"').and_then(optional_field);
+
+        Some(record)
+    }
+}
+
+/// The fields IIS writes when no `#Fields` directive has been seen.
+pub const DEFAULT_W3C_FIELDS: &str = "date time s-ip cs-method cs-uri-stem cs-uri-query s-port cs-username c-ip cs(User-Agent) cs(Referer) sc-status sc-substatus sc-win32-status time-taken";
+
+/// The W3C Extended Log File Format written by IIS; the columns are named by a `#Fields` directive.
+#[derive(Debug, Clone)]
+pub struct W3cExtendedFormat
+{
+    fields: Vec<String>,
+}
+
+impl Default for W3cExtendedFormat
+{
+    fn default() -> Self
+    {
+        W3cExtendedFormat::new(DEFAULT_W3C_FIELDS)
+    }
+}
+
+impl W3cExtendedFormat
+{
+    /// Creates the format for the given space-separated field names.
+    pub fn new(fields: &str) -> W3cExtendedFormat
+    {
+        W3cExtendedFormat {
+            fields: fields.split_whitespace().map(|field| field.to_string()).collect(),
+        }
+    }
+
+    /// Creates the format from the first `#Fields` directive of the lines, or with the IIS defaults.
+    pub fn from_lines(lines: &[String]) -> W3cExtendedFormat
+    {
+        lines.iter()
+            .find_map(|line| line.trim().strip_prefix("#Fields:"))
+            .map(W3cExtendedFormat::new)
+            .unwrap_or_default()
+    }
+}
+
+impl LogFormat for W3cExtendedFormat
+{
+    fn name(&self) -> &'static str
+    {
+        "w3c"
+    }
+
+    fn is_directive(&self, line: &str) -> bool
+    {
+        line.trim_start().starts_with('#')
+    }
+
+    fn parse(&self, line: &str) -> Option<RequestRecord>
+    {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#')
+        {
+            return None;
+        }
+
+        let values: Vec<&str> = line.split_whitespace().collect();
+        if values.len() != self.fields.len()
+        {
+            return None;
+        }
+
+        let mut record = RequestRecord::default();
+        let mut date: Option<&str> = None;
+        let mut time: Option<&str> = None;
+        let mut has_target = false;
+
+        for (field, &value) in self.fields.iter().zip(values.iter())
+        {
+            match field.as_str()
+            {
+                "date" => date = Some(value),
+                "time" => time = Some(value),
+                "c-ip" => record.client_ip = optional_field(value),
+                "cs-method" => record.method = optional_field(value),
+                "cs-uri-stem" =>
+                {
+                    record.path = value.to_string();
+                    has_target = true;
+                },
+                "cs-uri-query" => record.query = optional_field(value),
+                "cs-version" => record.protocol = optional_field(value),
+                "sc-status" => record.status = value.parse().ok(),
+                "sc-bytes" => record.size = value.parse().ok(),
+                // Spaces in these fields are written as '+'
+                "cs(Referer)" => record.referer = optional_field(value).map(|v| v.replace('+', " ")),
+                "cs(User-Agent)" => record.user_agent = optional_field(value).map(|v| v.replace('+', " ")),
+                _ => {},
+            }
+        }
+
+        if !has_target
+        {
+            return None;
+        }
+
+        record.timestamp = match (date, time)
+        {
+            (Some(date), Some(time)) => Some(format!("{} {}", date, time)),
+            (Some(value), None) | (None, Some(value)) => Some(value.to_string()),
+            (None, None) => None,
+        };
+
+        Some(record)
+    }
+}
+
+/// Recognizes the format of every line: JSON records, W3C directives and entries, Combined/Common Log
+/// Format lines, and bare request lines otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct AutoFormat
+{
+    w3c: W3cExtendedFormat,
+    json: JsonLinesFormat,
+}
+
+impl AutoFormat
+{
+    /// Creates the format, taking the W3C columns from the first `#Fields` directive of the lines.
+    pub fn from_lines(lines: &[String], json_fields: &JsonFields) -> AutoFormat
+    {
+        AutoFormat {
+            w3c: W3cExtendedFormat::from_lines(lines),
+            json: JsonLinesFormat::new(json_fields.clone()),
+        }
+    }
+}
+
+impl LogFormat for AutoFormat
+{
+    fn name(&self) -> &'static str
+    {
+        "auto"
+    }
+
+    fn is_directive(&self, line: &str) -> bool
+    {
+        self.w3c.is_directive(line)
+    }
+
+    fn parse(&self, line: &str) -> Option<RequestRecord>
+    {
+        let trimmed = line.trim();
+        if trimmed.starts_with('#')
+        {
+            return None;
+        }
+
+        if trimmed.starts_with('{')
+        {
+            if let Some(record) = self.json.parse(trimmed)
+            {
+                return Some(record);
+            }
+        }
+
+        // W3C entries start with the date, e.g. 2024-05-01
+        let starts_with_date = trimmed.len() >= 10
+            && trimmed.as_bytes()[..10].iter().enumerate()
+                .all(|(i, b)| if i == 4 || i == 7 { *b == b'-' } else { b.is_ascii_digit() });
+        if starts_with_date
+        {
+            if let Some(record) = self.w3c.parse(trimmed)
+            {
+                return Some(record);
+            }
+        }
+
+        if trimmed.contains(" [") && trimmed.contains('"')
+        {
+            if let Some(record) = CombinedLogFormat.parse(trimmed)
+            {
+                return Some(record);
+            }
+        }
+
+        RequestLineFormat.parse(trimmed)
+    }
+}
+
+/// The name of a log format, as selected on the command line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormatKind
+{
+    #[default]
+    Auto,
+    Request,
+    Combined,
+    W3c,
+    Json,
+}
+
+impl fmt::Display for LogFormatKind
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let name = match self
+        {
+            LogFormatKind::Auto => "auto",
+            LogFormatKind::Request => "request",
+            LogFormatKind::Combined => "combined",
+            LogFormatKind::W3c => "w3c",
+            LogFormatKind::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for LogFormatKind
+{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        match name.to_ascii_lowercase().as_str()
+        {
+            "auto" => Ok(LogFormatKind::Auto),
+            "request" => Ok(LogFormatKind::Request),
+            "combined" | "common" | "nginx" => Ok(LogFormatKind::Combined),
+            "w3c" | "iis" => Ok(LogFormatKind::W3c),
+            "json" | "jsonl" => Ok(LogFormatKind::Json),
+            _ => Err(format!("unknown log format '{}' (expected auto, request, combined, common, nginx, w3c, iis or json)", name)),
+        }
+    }
+}
+
+/// Creates the parser of a log format.
+///
+/// # Parameters
+/// - `kind`: The log format.
+/// - `json_fields`: The fields read from JSON records.
+/// - `lines`: The log lines that will be parsed; W3C columns are taken from their first `#Fields` directive.
+///
+/// # Returns
+/// The parser.
+pub fn create_log_format(kind: LogFormatKind, json_fields: &JsonFields, lines: &[String]) -> Box<dyn LogFormat>
+{
+    match kind
+    {
+        LogFormatKind::Auto => Box::new(AutoFormat::from_lines(lines, json_fields)),
+        LogFormatKind::Request => Box::new(RequestLineFormat),
+        LogFormatKind::Combined => Box::new(CombinedLogFormat),
+        LogFormatKind::W3c => Box::new(W3cExtendedFormat::from_lines(lines)),
+        LogFormatKind::Json => Box::new(JsonLinesFormat::new(json_fields.clone())),
+    }
+}
+
+/// Returns the parts of a log line the detector analyzes.
+///
+/// # Parameters
+/// - `format`: The format of the log line.
+/// - `line`: The log line.
+///
+/// # Returns
+/// The request target, header values and body of the line, nothing for a directive, or the whole line
+/// if it cannot be parsed so nothing escapes analysis.
+pub fn request_fields(format: &dyn LogFormat, line: &str) -> Vec<String>
+{
+    if format.is_directive(line)
+    {
+        return Vec::new();
+    }
+
+    match format.parse(line)
+    {
+        Some(record) => record.analyzed_fields(),
+        None => vec![line.to_string()],
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn lines(values: &[&str]) -> Vec<String>
+    {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn request_lines_keep_targets_with_spaces()
+    {
+        let record = RequestLineFormat.parse("GET /search?q=a b HTTP/1.1").unwrap();
+        assert_eq!(record.method.as_deref(), Some("GET"));
+        assert_eq!(record.path, "/search");
+        assert_eq!(record.query.as_deref(), Some("q=a b"));
+        assert_eq!(record.protocol.as_deref(), Some("HTTP/1.1"));
+
+        let bare = RequestLineFormat.parse("/index.html").unwrap();
+        assert_eq!((bare.method.as_deref(), bare.protocol.as_deref(), bare.target().as_str()), (None, None, "/index.html"));
+
+        assert_eq!(RequestLineFormat.parse("   "), None);
+    }
+
+    #[test]
+    fn combined_lines_are_split_into_fields()
+    {
+        let line = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif?x=1 HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08 [en]""#;
+        let record = CombinedLogFormat.parse(line).unwrap();
+
+        assert_eq!(record.client_ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(record.timestamp.as_deref(), Some("10/Oct/2000:13:55:36 -0700"));
+        assert_eq!(record.method.as_deref(), Some("GET"));
+        assert_eq!(record.target(), "/apache_pb.gif?x=1");
+        assert_eq!(record.status, Some(200));
+        assert_eq!(record.size, Some(2326));
+        assert_eq!(record.referer.as_deref(), Some("http://www.example.com/start.html"));
+        assert_eq!(record.user_agent.as_deref(), Some("Mozilla/4.08 [en]"));
+    }
+
+    #[test]
+    fn combined_lines_accept_common_format_and_escaped_quotes()
+    {
+        let common = CombinedLogFormat.parse(r#"10.0.0.1 - - [01/May/2024:12:00:00 +0000] "POST /login HTTP/1.1" 302 -"#).unwrap();
+        assert_eq!((common.status, common.size, common.referer, common.user_agent), (Some(302), None, None, None));
+
+        let escaped = CombinedLogFormat.parse(r#"10.0.0.1 - - [t] "GET /a?q=\"<x>\" HTTP/1.1" 200 5 "-" "curl""#).unwrap();
+        assert_eq!(escaped.query.as_deref(), Some(r#"q="<x>""#));
+        assert_eq!(escaped.referer, None);
+        assert_eq!(escaped.user_agent.as_deref(), Some("curl"));
+
+        let no_request = CombinedLogFormat.parse(r#"10.0.0.1 - - [t] "-" 408 0"#).unwrap();
+        assert_eq!((no_request.method.as_deref(), no_request.target().as_str()), (None, ""));
+    }
+
+    #[test]
+    fn malformed_combined_lines_are_rejected()
+    {
+        assert_eq!(CombinedLogFormat.parse(r#"10.0.0.1 - - [t] "GET /a HTTP/1.1 200 5"#), None);
+        assert_eq!(CombinedLogFormat.parse(r#"10.0.0.1 - - t "GET /a HTTP/1.1" 200 5"#), None);
+        assert_eq!(CombinedLogFormat.parse("10.0.0.1 -"), None);
+
+        // Lines that cannot be parsed are analyzed whole
+        let line = r#"10.0.0.1 - - [t] "GET /a"#;
+        assert_eq!(request_fields(&CombinedLogFormat, line), lines(&[line]));
+    }
+
+    #[test]
+    fn w3c_lines_follow_the_fields_directive()
+    {
+        let default = W3cExtendedFormat::default();
+        let record = default.parse("2024-05-01 12:00:00 10.0.0.1 GET /default.aspx id=1 80 - 192.168.1.5 Mozilla/5.0+(Windows) - 200 0 0 15").unwrap();
+        assert_eq!(record.timestamp.as_deref(), Some("2024-05-01 12:00:00"));
+        assert_eq!(record.client_ip.as_deref(), Some("192.168.1.5"));
+        assert_eq!(record.target(), "/default.aspx?id=1");
+        assert_eq!(record.user_agent.as_deref(), Some("Mozilla/5.0 (Windows)"));
+        assert_eq!((record.referer, record.status), (None, Some(200)));
+
+        let custom = W3cExtendedFormat::from_lines(&lines(&["#Software: Microsoft IIS", "#Fields: cs-method cs-uri-stem sc-bytes"]));
+        let record = custom.parse("POST /upload 512").unwrap();
+        assert_eq!((record.method.as_deref(), record.path.as_str(), record.size), (Some("POST"), "/upload", Some(512)));
+
+        assert!(custom.is_directive("#Fields: date"));
+        assert!(request_fields(&custom, "#Date: 2024-05-01").is_empty());
+        assert_eq!(custom.parse("POST /upload"), None);
+        assert_eq!(W3cExtendedFormat::new("date cs-method").parse("2024-05-01 GET"), None);
+    }
+
+    #[test]
+    fn auto_format_recognizes_every_format()
+    {
+        let auto = AutoFormat::from_lines(&lines(&["#Fields: date cs-uri-stem"]), &JsonFields::default());
+
+        assert_eq!(auto.parse(r#"{"method": "PUT", "uri": "/json?a=1"}"#).unwrap().target(), "/json?a=1");
+        assert_eq!(auto.parse("2024-05-01 /w3c").unwrap().path, "/w3c");
+        assert_eq!(auto.parse(r#"1.2.3.4 - - [t] "GET /combined HTTP/1.1" 200 1"#).unwrap().path, "/combined");
+        assert_eq!(auto.parse("GET /request HTTP/1.1").unwrap().path, "/request");
+        // A line that is not valid JSON falls through to the other formats
+        assert_eq!(auto.parse("{not json").unwrap().path, "{not json");
+        assert_eq!(auto.parse("#Version: 1.0"), None);
+    }
+
+    #[test]
+    fn format_names_parse_with_aliases()
+    {
+        for (name, kind) in [("auto", LogFormatKind::Auto), ("Request", LogFormatKind::Request), ("common", LogFormatKind::Combined),
+            ("nginx", LogFormatKind::Combined), ("IIS", LogFormatKind::W3c), ("jsonl", LogFormatKind::Json)]
+        {
+            assert_eq!(name.parse::<LogFormatKind>(), Ok(kind));
+        }
+        assert!("apache".parse::<LogFormatKind>().unwrap_err().contains("unknown log format 'apache'"));
+
+        for kind in [LogFormatKind::Auto, LogFormatKind::Request, LogFormatKind::Combined, LogFormatKind::W3c, LogFormatKind::Json]
+        {
+            assert_eq!(kind.to_string().parse::<LogFormatKind>(), Ok(kind));
+            assert_eq!(create_log_format(kind, &JsonFields::default(), &[]).name(), kind.to_string());
+        }
+    }
+}
@@ -4,340 +4,829 @@
 /// These functions are used to compare log entries against a dictionary of known patterns.
 /// The file includes the following functions:
 ///
+/// - `Criticality`: The criticality level of a request (Low, Medium, High).
+/// - `AnalysisResult`: The criticality, scores and per-token dictionary matches of one analyzed request.
+/// - `SimilarityScores::composite_score`: Weights the averaged similarity of a request, additional metrics included, into a composite score.
+/// - `calculate_composite_score`: Weights a Levenshtein distance and Dice coefficient into a composite score.
+/// - `criticality_from_score`: Maps a composite score to a criticality level.
+/// - `calculate_similarity_scores`: Calculates the averaged similarity of a single log entry to the dictionary.
+/// - `normalize_log_entry`: Normalizes the request target, header values and body of a log entry.
+/// - `tokenize_log_entry`: Normalizes and splits the request target, header values and body of a log entry into tokens.
 /// - `analyze_log_and_determine_criticality`: Analyzes a single log entry and calculates its total Levenshtein distance and Dice coefficient, then determines the criticality of the request.
 /// - `analyze_logs`: Analyzes multiple log entries from the beginning with an optional limit.
 /// - `analyze_logs_from_index`: Analyzes multiple log entries starting from a specified index with an optional limit.
-/// - `remove_request_type_from_log`: Removes the request type (e.g., GET, POST) from a log entry.
+/// - `analyze_logs_and_collect_malicious_parallel`: Collects the malicious log entries on a pool of worker threads (`parallel` feature).
+/// - `find_malicious_logs`: Collects the malicious log entries, in parallel when the `parallel` feature is enabled.
 ///
 /// Modules Required:
+/// - `dict_index`: Provides the indexed lookup of the closest dictionary entries.
+/// - `log_format`: Provides the extraction of the request target from a log entry.
+/// - `normalize`: Provides the normalization applied to the request before it is split.
+/// - `similarity`: Provides the additional similarity metrics weighted into the composite score.
 /// - `string_utils`: Provides string manipulation utilities including Levenshtein distance and Dice coefficient calculation.
 ///
 /// Author: Lazar Marinkovic
 /// Date: July 7th, 2024
-
-use crate::string_utils;
+use crate::dict_index::DictionaryIndex;
+use crate::log_format::{self, LogFormat};
+use crate::normalize::{NormalizationConfig, NormalizationStep, Normalized};
+use crate::similarity::{MetricScore, WeightedMetric};
+use crate::string_utils::{self, TextUnit};
+use std::fmt;
+use std::io;
+use tracing::{debug, info, trace};
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
-use std::sync::{Arc, Mutex};
 
-/// Determines the criticality of a request based on Levenshtein distance and Dice coefficient.
+/// Weights and thresholds used to turn the similarity of a request into a criticality level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoringConfig
+{
+    /// Weight of the normalized Levenshtein similarity in the composite score.
+    pub alpha: f64,
+    /// Weight of the Dice coefficient in the composite score.
+    pub beta: f64,
+    /// Composite score from which a request is rated "Medium".
+    pub medium_threshold: f64,
+    /// Composite score from which a request is rated "High" (malicious).
+    pub high_threshold: f64,
+    /// Levenshtein distance used to normalize distances; the length of the longest dictionary entry when `None`.
+    pub max_levenshtein_distance: Option<usize>,
+    /// Additional similarity metrics and their weights, added to the weighted Levenshtein and Dice terms.
+    pub metrics: Vec<WeightedMetric>,
+}
+
+impl ScoringConfig
+{
+    /// Checks that the weights and thresholds describe a usable scoring.
+    ///
+    /// # Summary
+    /// The weights must be finite and non-negative with a positive sum for alpha and beta, and the
+    /// thresholds must lie between 0 and 1 with the "Medium" threshold not above the "High" one, since
+    /// the composite score is a weighted average between 0 and 1. A maximum Levenshtein distance, if
+    /// set, must be positive since the distances are normalized with it.
+    ///
+    /// # Returns
+    /// A result describing the first invalid setting.
+    pub fn validate(&self) -> Result<(), String>
+    {
+        let weights = [("alpha", self.alpha), ("beta", self.beta)].into_iter()
+            .chain(self.metrics.iter().map(|metric| ("metric weight", metric.weight)));
+        for (name, weight) in weights
+        {
+            if !weight.is_finite() || weight < 0.0
+            {
+                return Err(format!("{} must be a finite, non-negative number (got {})", name, weight));
+            }
+        }
+
+        if self.alpha + self.beta <= 0.0
+        {
+            return Err("alpha and beta must not both be 0".to_string());
+        }
+
+        if self.max_levenshtein_distance == Some(0)
+        {
+            return Err("max Levenshtein distance must be positive".to_string());
+        }
+
+        for (name, threshold) in [("medium threshold", self.medium_threshold), ("high threshold", self.high_threshold)]
+        {
+            if !(0.0..=1.0).contains(&threshold)
+            {
+                return Err(format!("{} must be between 0 and 1 (got {})", name, threshold));
+            }
+        }
+
+        if self.medium_threshold > self.high_threshold
+        {
+            return Err(format!(
+                "medium threshold {} must not be above high threshold {}",
+                self.medium_threshold, self.high_threshold
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ScoringConfig
+{
+    fn default() -> Self
+    {
+        ScoringConfig {
+            alpha: 0.5,
+            beta: 0.5,
+            medium_threshold: 0.3,
+            high_threshold: 0.7,
+            max_levenshtein_distance: None,
+            metrics: Vec::new(),
+        }
+    }
+}
+
+/// Averaged similarity of a request to the dictionary, before it is weighted into a composite score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimilarityScores
+{
+    /// Average, over the request tokens, of the minimum Levenshtein distance to a dictionary entry.
+    pub average_levenshtein: usize,
+    /// Average, over the request tokens, of the maximum Dice coefficient with a dictionary entry.
+    pub average_dice_coefficient: f64,
+    /// The Levenshtein distance the average distance is normalized with.
+    pub max_levenshtein_distance: usize,
+    /// The averaged similarity according to each additional metric, with its weight.
+    pub metrics: Vec<MetricScore>,
+}
+
+impl SimilarityScores
+{
+    /// Weights the averaged similarity into a composite score.
+    ///
+    /// # Parameters
+    /// - `alpha`: The weight of the normalized Levenshtein similarity.
+    /// - `beta`: The weight of the Dice coefficient.
+    ///
+    /// # Summary
+    /// The weighted sum is divided by the sum of the weights, so the score stays between 0 and 1 and
+    /// adding metrics does not move requests across the criticality thresholds. The Levenshtein
+    /// similarity is clamped between 0 and 1, as an average distance can exceed a configured maximum,
+    /// and is 0 if the maximum is 0. Every composite score of the detector is calculated here.
+    ///
+    /// # Returns
+    /// The weighted average of the normalized Levenshtein similarity, the Dice coefficient and the
+    /// similarity of every additional metric; 0 if all weights are 0.
+    pub fn composite_score(&self, alpha: f64, beta: f64) -> f64
+    {
+        // Normalize Levenshtein distance
+        let normalized_levenshtein = if self.max_levenshtein_distance > 0
+        {
+            (1.0 - (self.average_levenshtein as f64 / self.max_levenshtein_distance as f64)).clamp(0.0, 1.0)
+        }
+        else
+        {
+            0.0
+        };
+
+        let weighted_sum = alpha * normalized_levenshtein
+            + beta * self.average_dice_coefficient
+            + self.metrics.iter().map(|metric| metric.weight * metric.similarity).sum::<f64>();
+        let total_weight = alpha + beta + self.metrics.iter().map(|metric| metric.weight).sum::<f64>();
+
+        if total_weight > 0.0 { weighted_sum / total_weight } else { 0.0 }
+    }
+}
+
+/// Criticality level of a request; only `High` requests are considered malicious.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Criticality
+{
+    Low,
+    Medium,
+    High,
+}
+
+impl Criticality
+{
+    /// Returns the name of the criticality level.
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Criticality::Low => "Low",
+            Criticality::Medium => "Medium",
+            Criticality::High => "High",
+        }
+    }
+}
+
+impl fmt::Display for Criticality
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The closest dictionary entry found for one token of a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenMatch
+{
+    /// The token, as split from the request.
+    pub token: String,
+    /// The dictionary entry with the smallest Levenshtein distance, or `None` if the dictionary is empty.
+    pub best_pattern: Option<String>,
+    /// The Levenshtein distance to `best_pattern`.
+    pub levenshtein_distance: usize,
+    /// The highest Dice coefficient with any dictionary entry.
+    pub dice_coefficient: f64,
+}
+
+/// The outcome of analyzing one request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalysisResult
+{
+    /// The criticality level the composite score falls into.
+    pub criticality: Criticality,
+    /// The weighted combination of the normalized Levenshtein similarity and the Dice coefficient.
+    pub composite_score: f64,
+    /// Average, over the tokens, of the minimum Levenshtein distance to a dictionary entry.
+    pub average_levenshtein: usize,
+    /// Average, over the tokens, of the maximum Dice coefficient with a dictionary entry.
+    pub average_dice_coefficient: f64,
+    /// The Levenshtein distance the average distance was normalized with.
+    pub max_levenshtein_distance: usize,
+    /// The averaged similarity according to each additional metric, with its weight.
+    pub metric_scores: Vec<MetricScore>,
+    /// The tokens of the request with their best dictionary matches.
+    pub tokens: Vec<TokenMatch>,
+    /// The analyzed fields of the request as they were compared, with the normalization steps that changed them.
+    pub normalized_fields: Vec<Normalized>,
+}
+
+impl AnalysisResult
+{
+    /// Returns whether the request is considered malicious (its criticality is `High`).
+    pub fn is_malicious(&self) -> bool
+    {
+        self.criticality == Criticality::High
+    }
+
+    /// Returns the distinct dictionary entries that best matched the tokens of the request.
+    pub fn matched_patterns(&self) -> Vec<&str>
+    {
+        let mut patterns: Vec<&str> = Vec::new();
+        for token in &self.tokens
+        {
+            if let Some(pattern) = &token.best_pattern
+            {
+                if !patterns.contains(&pattern.as_str())
+                {
+                    patterns.push(pattern);
+                }
+            }
+        }
+        patterns
+    }
+
+    /// Returns the distinct normalization steps that changed a field of the request, in pipeline order.
+    pub fn normalization_steps(&self) -> Vec<NormalizationStep>
+    {
+        let mut steps: Vec<NormalizationStep> = self.normalized_fields.iter()
+            .flat_map(|field| field.applied.iter().copied())
+            .collect();
+        steps.sort_unstable();
+        steps.dedup();
+        steps
+    }
+}
+
+/// Calculates the composite score of a request from its Levenshtein distance and Dice coefficient.
 ///
 /// # Parameters
 /// - `levenshtein_distance`: The Levenshtein distance between the request and the dictionary entry.
 /// - `dice_coefficient`: The Dice coefficient between the request and the dictionary entry.
 /// - `max_levenshtein_distance`: The maximum possible Levenshtein distance (e.g., the length of the longest string).
+/// - `alpha`: The weight of the normalized Levenshtein similarity.
+/// - `beta`: The weight of the Dice coefficient.
 ///
 /// # Returns
-/// A string indicating the criticality level (Low, Medium, High).
-pub fn determine_criticality(levenshtein_distance: usize, dice_coefficient: f64, max_levenshtein_distance: usize) -> String
+/// The composite score, as calculated by `SimilarityScores::composite_score` without additional metrics;
+/// higher means closer to the dictionary.
+pub fn calculate_composite_score(levenshtein_distance: usize, dice_coefficient: f64, max_levenshtein_distance: usize, alpha: f64, beta: f64) -> f64
 {
-    // Normalize Levenshtein distance
-    let normalized_levenshtein = 1.0 - (levenshtein_distance as f64 / max_levenshtein_distance as f64);
+    let scores = SimilarityScores {
+        average_levenshtein: levenshtein_distance,
+        average_dice_coefficient: dice_coefficient,
+        max_levenshtein_distance,
+        metrics: Vec::new(),
+    };
+    scores.composite_score(alpha, beta)
+}
 
-    // Weights for composite score (these can be adjusted based on your preference)
-    let alpha = 0.5;
-    let beta = 0.5;
+/// Maps a composite score to a criticality level.
+///
+/// # Parameters
+/// - `composite_score`: The composite score of the request.
+/// - `scoring`: The thresholds separating the criticality levels.
+///
+/// # Returns
+/// The criticality level (Low, Medium, High).
+pub fn criticality_from_score(composite_score: f64, scoring: &ScoringConfig) -> Criticality
+{
+    if composite_score < scoring.medium_threshold {
+        Criticality::Low
+    } else if composite_score < scoring.high_threshold {
+        Criticality::Medium
+    } else {
+        Criticality::High
+    }
+}
 
+/// Determines the criticality of a request based on Levenshtein distance and Dice coefficient.
+///
+/// # Parameters
+/// - `levenshtein_distance`: The Levenshtein distance between the request and the dictionary entry.
+/// - `dice_coefficient`: The Dice coefficient between the request and the dictionary entry.
+/// - `max_levenshtein_distance`: The maximum possible Levenshtein distance (e.g., the length of the longest string).
+/// - `scoring`: The weights and thresholds separating the criticality levels.
+///
+/// # Returns
+/// The criticality level (Low, Medium, High).
+#[allow(dead_code)]
+pub fn determine_criticality(levenshtein_distance: usize, dice_coefficient: f64, max_levenshtein_distance: usize, scoring: &ScoringConfig) -> Criticality
+{
     // Calculate composite score
-    let composite_score = alpha * normalized_levenshtein + beta * dice_coefficient;
+    let composite_score = calculate_composite_score(
+        levenshtein_distance,
+        dice_coefficient,
+        max_levenshtein_distance,
+        scoring.alpha,
+        scoring.beta
+    );
 
-    println!("Request Criticality: {}\n\n", composite_score);
+    // Determine criticality based on composite score
+    criticality_from_score(composite_score, scoring)
+}
 
+/// Normalizes the analyzed parts of a log entry.
+///
+/// # Description
+/// The request target, header values and body are extracted from the log entry according to its
+/// format (which leaves out the request type, e.g. GET or POST) and each goes through the normalization steps.
+///
+/// # Parameters
+/// - `log_entry`: The log entry.
+/// - `format`: The format of the log entry.
+/// - `normalization`: The normalization steps to apply.
+///
+/// # Returns
+/// The normalized fields, in order.
+pub fn normalize_log_entry(log_entry: &str, format: &dyn LogFormat, normalization: &NormalizationConfig) -> Vec<Normalized>
+{
+    log_format::request_fields(format, log_entry).iter()
+        .map(|field| normalization.normalize(field))
+        .collect()
+}
 
-    // Determine criticality based on composite score
-    if composite_score < 0.3 {
-        "Low".to_string()
-    } else if composite_score < 0.7 {
-        "Medium".to_string()
-    } else {
-        "High".to_string()
+/// Splits normalized fields into tokens.
+fn split_fields(fields: &[Normalized], delimiters: &[String], text_unit: TextUnit) -> Vec<String>
+{
+    let mut tokens = Vec::new();
+    for field in fields
+    {
+        trace!("Analyzed field: {} (normalized by: {:?})", field.text, field.applied);
+        tokens.extend(string_utils::split_by_multiple_delimiters(&field.text, delimiters, text_unit));
     }
+    tokens
 }
 
-/// Analyzes a single log entry and calculates its total Levenshtein distance and Dice coefficient,
-/// then determines the criticality of the request.
+/// Splits the analyzed parts of a log entry into tokens.
+///
+/// # Description
+/// The fields returned by `normalize_log_entry` are split by the delimiters.
 ///
 /// # Parameters
-/// - `log_entry`: A mutable reference to the log entry string to be analyzed.
-/// - `delimiters`: A mutable reference to a vector of delimiter strings used for splitting the log entry.
-/// - `dictionary`: A mutable reference to a vector of known patterns to compare against.
-/// - `max_levenshtein_distance`: The maximum possible Levenshtein distance.
-pub fn analyze_log_and_determine_criticality(log_entry: &mut String, delimiters: &mut Vec<String>, dictionary: &mut Vec<String>) -> String
+/// - `log_entry`: The log entry.
+/// - `format`: The format of the log entry.
+/// - `delimiters`: The delimiter strings used for splitting the log entry.
+/// - `normalization`: The normalization applied to the fields before they are split.
+/// - `text_unit`: The text unit whose boundaries the delimiters have to match at.
+///
+/// # Returns
+/// The tokens of all analyzed parts, in order.
+pub fn tokenize_log_entry(log_entry: &str, format: &dyn LogFormat, delimiters: &[String], normalization: &NormalizationConfig, text_unit: TextUnit) -> Vec<String>
+{
+    split_fields(&normalize_log_entry(log_entry, format, normalization), delimiters, text_unit)
+}
+
+/// Compares every token of a log entry against the dictionary.
+///
+/// # Description
+/// The log entry is normalized and split into tokens as in `tokenize_log_entry`, and for every
+/// token the minimum Levenshtein distance and maximum Dice coefficient over the dictionary are looked
+/// up in the dictionary index. The per-token values are then averaged.
+///
+/// # Returns
+/// The averaged similarity scores, the best match of every token and the normalized fields.
+fn match_tokens(log_entry: &str, format: &dyn LogFormat, delimiters: &[String], dictionary: &DictionaryIndex, scoring: &ScoringConfig) -> (SimilarityScores, Vec<TokenMatch>, Vec<Normalized>)
 {
     let mut total_levenshtein = 0;
     let mut total_dice_coefficient = 0.0;
     let mut num_entries = 0;
-    let mut max_levenshtein_distance = find_max_levenshtein_distance(dictionary);
+    let mut token_matches: Vec<TokenMatch> = Vec::new();
+    let mut total_metric_similarities = vec![0.0; dictionary.metrics().len()];
+    let max_levenshtein_distance = scoring.max_levenshtein_distance
+        .unwrap_or_else(|| dictionary.max_length());
 
-    print!("Analyzing log {}\n", log_entry);
-    remove_request_type_from_log(log_entry); // Exclude request types like GET, POST, etc.
-    let split_log_entries: Vec<String> = string_utils::split_by_multiple_delimiters(log_entry, delimiters);
+    debug!("Analyzing log {}", log_entry);
+    let normalized_fields = normalize_log_entry(log_entry, format, dictionary.normalization());
+    let split_log_entries: Vec<String> = split_fields(&normalized_fields, delimiters, dictionary.text_unit());
 
 
     for entry in split_log_entries.iter()
     {
-        let mut min_levenshtein = usize::MAX;
-        let mut max_dice_coefficient = 0.0;
-        
-
-        for pattern in dictionary.iter()
-        {
-            let levenshtein_distance = string_utils::levenshtein(entry, pattern);
-            let dice_coefficient = string_utils::dice_coefficient(entry, pattern);
-
-            println!("Levenshtein distance: {}", levenshtein_distance);
+        let token_scores = dictionary.best_matches(entry);
 
-            if levenshtein_distance < min_levenshtein
-            {
-                min_levenshtein = levenshtein_distance;
-            }
+        trace!("Minimum Levenshtein distance: {}", token_scores.min_levenshtein);
+        total_levenshtein += token_scores.min_levenshtein;
+        total_dice_coefficient += token_scores.max_dice_coefficient;
+        num_entries += 1;
 
-            if dice_coefficient > max_dice_coefficient
-            {
-                max_dice_coefficient = dice_coefficient;
-            }
+        for (total, similarity) in total_metric_similarities.iter_mut().zip(dictionary.metric_similarities(entry))
+        {
+            *total += similarity;
         }
 
-        println!("Minimum Levenshtein distance: {}", min_levenshtein);
-        total_levenshtein += min_levenshtein;
-        total_dice_coefficient += max_dice_coefficient;
-        num_entries += 1;
+        token_matches.push(TokenMatch {
+            token: entry.clone(),
+            best_pattern: token_scores.best_entry.map(|index| dictionary.entry(index).to_string()),
+            levenshtein_distance: token_scores.min_levenshtein,
+            dice_coefficient: token_scores.max_dice_coefficient,
+        });
     }
 
-    println!("Total Levenshtein distance: {}", total_levenshtein);
+    trace!("Total Levenshtein distance: {}", total_levenshtein);
 
     // Average the scores
-    if num_entries > 0 {
-        total_levenshtein /= num_entries;
+    if let Some(average_levenshtein) = total_levenshtein.checked_div(num_entries) {
+        total_levenshtein = average_levenshtein;
         total_dice_coefficient /= num_entries as f64;
+        for total in total_metric_similarities.iter_mut()
+        {
+            *total /= num_entries as f64;
+        }
     }
 
-    println!("Number of entries: {}", num_entries);
-    println!("Average Levenshtein distance: {}", total_levenshtein);
-    println!("Total Dice coefficient: {}", total_dice_coefficient);
+    trace!("Number of entries: {}", num_entries);
+    trace!("Average Levenshtein distance: {}", total_levenshtein);
+    trace!("Total Dice coefficient: {}", total_dice_coefficient);
+
+    let scores = SimilarityScores {
+        average_levenshtein: total_levenshtein,
+        average_dice_coefficient: total_dice_coefficient,
+        max_levenshtein_distance,
+        metrics: dictionary.metrics().iter()
+            .zip(total_metric_similarities)
+            .map(|((metric, built), similarity)| MetricScore { name: built.name(), weight: metric.weight, similarity })
+            .collect(),
+    };
+    (scores, token_matches, normalized_fields)
+}
 
-    let criticality = determine_criticality(total_levenshtein, total_dice_coefficient, max_levenshtein_distance);
-    println!("Request Criticality: {}\n\n", criticality);
-    criticality
+/// Calculates the averaged similarity of a single log entry to the dictionary.
+///
+/// # Parameters
+/// - `log_entry`: The log entry to be analyzed; it is left untouched.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting the log entry.
+/// - `dictionary`: The indexed dictionary of known patterns to compare against.
+/// - `scoring`: The normalization used for the Levenshtein distance.
+///
+/// # Returns
+/// The averaged similarity scores.
+pub fn calculate_similarity_scores(log_entry: &str, format: &dyn LogFormat, delimiters: &[String], dictionary: &DictionaryIndex, scoring: &ScoringConfig) -> SimilarityScores
+{
+    match_tokens(log_entry, format, delimiters, dictionary, scoring).0
+}
+
+/// Analyzes a single log entry and calculates its total Levenshtein distance and Dice coefficient,
+/// then determines the criticality of the request.
+///
+/// # Parameters
+/// - `log_entry`: The log entry to be analyzed; it is left untouched.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting the log entry.
+/// - `dictionary`: The indexed dictionary of known patterns to compare against.
+/// - `scoring`: The weights, thresholds and normalization used to determine the criticality.
+///
+/// # Returns
+/// The analysis result with the criticality, the composite score and the per-token matches.
+pub fn analyze_log_and_determine_criticality(log_entry: &str, format: &dyn LogFormat, delimiters: &[String], dictionary: &DictionaryIndex, scoring: &ScoringConfig) -> AnalysisResult
+{
+    let (scores, tokens, normalized_fields) = match_tokens(log_entry, format, delimiters, dictionary, scoring);
+
+    let composite_score = scores.composite_score(scoring.alpha, scoring.beta);
+    let criticality = criticality_from_score(composite_score, scoring);
+
+    debug!("Request Criticality: {} ({})", criticality, composite_score);
+
+    AnalysisResult {
+        criticality,
+        composite_score,
+        average_levenshtein: scores.average_levenshtein,
+        average_dice_coefficient: scores.average_dice_coefficient,
+        max_levenshtein_distance: scores.max_levenshtein_distance,
+        metric_scores: scores.metrics,
+        tokens,
+        normalized_fields,
+    }
 }
 
 /// Analyzes multiple log entries from the beginning with an optional limit.
 ///
 /// # Parameters
-/// - `logs`: A mutable reference to a vector of log entry strings to be analyzed.
-/// - `delimiters`: A mutable reference to a vector of delimiter strings used for splitting log entries.
-/// - `dictionary`: A mutable reference to a vector of known patterns to compare against.
+/// - `logs`: The log entries to be analyzed; they are left untouched.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `dictionary`: The indexed dictionary of known patterns to compare against.
 /// - `limit`: An optional limit on the number of log entries to analyze.
-/// - `max_levenshtein_distance`: The maximum possible Levenshtein distance.
-pub fn analyze_logs(logs: &mut Vec<String>, delimiters: &mut Vec<String>, dictionary: &mut Vec<String>, limit: Option<usize>)
+/// - `scoring`: The thresholds and normalization used to determine the criticality.
+///
+/// # Returns
+/// The analysis result of every analyzed log entry, in input order.
+pub fn analyze_logs(logs: &[String], format: &dyn LogFormat, delimiters: &[String], dictionary: &DictionaryIndex, limit: Option<usize>, scoring: &ScoringConfig) -> Vec<AnalysisResult>
 {
-    let max_entries = limit.unwrap_or(logs.len());
-    for (index, log) in logs.iter_mut().enumerate().take(max_entries)
-    {
-        analyze_log_and_determine_criticality(log, delimiters, dictionary);
-    }
+    analyze_logs_from_index(logs, 0, format, delimiters, dictionary, limit, scoring)
 }
 
 /// Analyzes multiple log entries starting from a specified index with an optional limit.
 ///
 /// # Parameters
-/// - `logs`: A mutable reference to a vector of log entry strings to be analyzed.
+/// - `logs`: The log entries to be analyzed; they are left untouched.
 /// - `start_index`: The index from which to start analyzing log entries.
-/// - `delimiters`: A mutable reference to a vector of delimiter strings used for splitting log entries.
-/// - `dictionary`: A mutable reference to a vector of known patterns to compare against.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `dictionary`: The indexed dictionary of known patterns to compare against.
 /// - `limit`: An optional limit on the number of log entries to analyze.
-/// - `max_levenshtein_distance`: The maximum possible Levenshtein distance.
-pub fn analyze_logs_from_index(logs: &mut Vec<String>, start_index: usize, delimiters: &mut Vec<String>, dictionary: &mut Vec<String>, limit: Option<usize>,)
+/// - `scoring`: The thresholds and normalization used to determine the criticality.
+///
+/// # Returns
+/// The analysis result of every analyzed log entry, in input order; nothing if `start_index` is past the end.
+pub fn analyze_logs_from_index(logs: &[String], start_index: usize, format: &dyn LogFormat, delimiters: &[String], dictionary: &DictionaryIndex, limit: Option<usize>, scoring: &ScoringConfig) -> Vec<AnalysisResult>
 {
-    let max_entries = limit.unwrap_or(logs.len() - start_index) + start_index;
-    for index in start_index..max_entries
-    {
-        if index < logs.len()
-        {
-            analyze_log_and_determine_criticality(&mut logs[index], delimiters, dictionary);
-        }
-        else
-        {
-            break;
-        }
-    }
+    let remaining = logs.get(start_index..).unwrap_or_default();
+    let max_entries = limit.unwrap_or(remaining.len());
+    remaining.iter()
+        .take(max_entries)
+        .map(|log| analyze_log_and_determine_criticality(log, format, delimiters, dictionary, scoring))
+        .collect()
 }
 
-/// Analyzes multiple log entries and returns a list of malicious logs.
+/// Analyzes multiple log entries and returns the malicious ones.
 ///
 /// # Description
-/// This function iterates through a vector of log entries, analyzes each one using the `is_malicious`
-/// function, and collects all logs that are determined to be malicious into a list. The function returns
-/// this list of malicious logs at the end.
+/// This function iterates through a vector of log entries, analyzes each one using the
+/// `analyze_log_and_determine_criticality` function, and collects the analysis of all logs that are
+/// determined to be malicious into a list. The function returns this list at the end.
 ///
 /// # Parameters
-/// - `logs`: A mutable reference to a vector of log entry strings to be analyzed.
-/// - `delimiters`: A mutable reference to a vector of delimiter strings used for splitting log entries.
-/// - `dictionary`: A mutable reference to a vector of known patterns to compare against.
+/// - `logs`: The log entries to be analyzed; they are left untouched.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `dictionary`: The indexed dictionary of known patterns to compare against.
 /// - `limit`: An optional limit on the number of log entries to analyze.
-/// - `max_levenshtein_distance`: The maximum possible Levenshtein distance.
+/// - `scoring`: The thresholds and normalization used to determine the criticality.
 ///
 /// # Returns
-/// - A vector containing all malicious log entries.
+/// - The index in `logs` and the analysis result of every malicious log entry, in input order.
 pub fn analyze_logs_and_collect_malicious(
-    logs: &mut Vec<String>,
-    delimiters: &mut Vec<String>,
-    dictionary: &mut Vec<String>,
-    limit: Option<usize>
-) -> Vec<String> {
-    let mut malicious_logs = Vec::new();
-    let max_entries = limit.unwrap_or(logs.len());
-
-    for (index, log) in logs.iter_mut().enumerate().take(max_entries) {
-        if is_malicious(log, delimiters, dictionary) {
-            malicious_logs.push(log.clone());
-        }
-    }
-
-    malicious_logs
+    logs: &[String],
+    format: &dyn LogFormat,
+    delimiters: &[String],
+    dictionary: &DictionaryIndex,
+    limit: Option<usize>,
+    scoring: &ScoringConfig
+) -> Vec<(usize, AnalysisResult)> {
+    let max_entries = limit.unwrap_or(logs.len()).min(logs.len());
+
+    logs[..max_entries].iter()
+        .enumerate()
+        .map(|(index, log)| (index, analyze_log_and_determine_criticality(log, format, delimiters, dictionary, scoring)))
+        .filter(|(_, result)| result.is_malicious())
+        .collect()
 }
 
-use std::fs::{self, OpenOptions};
-use std::io::{self, Write};
-use std::path::Path;
-
-/// Analyzes logs and saves malicious ones to the file named `malicious_logs.txt`.
+/// Analyzes multiple log entries on a pool of worker threads and returns the malicious ones.
 ///
 /// # Description
-/// This function iterates through a vector of log entries, analyzes each one using the `is_malicious`
-/// function, and appends all malicious logs to a file named `malicious_logs.txt`. If the file does not exist,
-/// it will be created.
+/// The log entries are spread over the worker threads, which share the delimiters and the dictionary
+/// without copying them. The result is the same as that of `analyze_logs_and_collect_malicious`.
 ///
 /// # Parameters
-/// - `logs`: A mutable reference to a vector of log entry strings to be analyzed.
-/// - `delimiters`: A mutable reference to a vector of delimiter strings used for splitting log entries.
-/// - `dictionary`: A mutable reference to a vector of known patterns to compare against.
+/// - `logs`: The log entries to be analyzed; they are left untouched.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `dictionary`: The indexed dictionary of known patterns to compare against.
 /// - `limit`: An optional limit on the number of log entries to analyze.
-/// - `max_levenshtein_distance`: The maximum possible Levenshtein distance.
+/// - `scoring`: The thresholds and normalization used to determine the criticality.
+/// - `threads`: The number of worker threads; one per CPU when `None`.
 ///
 /// # Returns
-/// - `io::Result<()>`: The result of the file operation.
-pub fn analyze_logs_and_save_malicious(
-    logs: &mut Vec<String>,
-    delimiters: &mut Vec<String>,
-    dictionary: &mut Vec<String>,
-    limit: Option<usize>
-) -> io::Result<()> {
-    let malicious_logs = analyze_logs_and_collect_malicious(
-        logs,
-        delimiters,
-        dictionary,
-        limit
-    );
-
-    if malicious_logs.is_empty() {
-        println!("No malicious logs found.");
-        return Ok(());
-    }
-
-    // Save the malicious logs to the file
-    save_logs_to_file(&malicious_logs, "malicious_logs.txt")
+/// - The index and analysis result of every malicious log entry in input order, or an error if the
+///   thread pool cannot be created.
+#[cfg(feature = "parallel")]
+pub fn analyze_logs_and_collect_malicious_parallel(
+    logs: &[String],
+    format: &dyn LogFormat,
+    delimiters: &[String],
+    dictionary: &DictionaryIndex,
+    limit: Option<usize>,
+    scoring: &ScoringConfig,
+    threads: Option<usize>
+) -> io::Result<Vec<(usize, AnalysisResult)>> {
+    let max_entries = limit.unwrap_or(logs.len()).min(logs.len());
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(0))
+        .build()
+        .map_err(io::Error::other)?;
+
+    // Collecting a parallel iterator into a vector keeps the input order
+    let malicious_logs = pool.install(|| {
+        logs[..max_entries].par_iter()
+            .enumerate()
+            .map(|(index, log)| (index, analyze_log_and_determine_criticality(log, format, delimiters, dictionary, scoring)))
+            .filter(|(_, result)| result.is_malicious())
+            .collect()
+    });
+
+    Ok(malicious_logs)
 }
 
-use rayon::prelude::*;
-
-pub fn analyze_logs_and_save_malicious_parallel(
-    logs: &mut Vec<String>,
-    delimiters: &mut Vec<String>,  // Keep as mutable reference
-    dictionary: &mut Vec<String>  // Keep as mutable reference
-) -> io::Result<()> {
-    let malicious_logs: Vec<String> = logs.par_iter_mut()
-        .filter_map(|log| {
-            let mut delimiters_clone = delimiters.clone(); // Clone for each log entry
-            let mut dictionary_clone = dictionary.clone(); // Clone for each log entry
-            if is_malicious(log, &mut delimiters_clone, &mut dictionary_clone) {
-                Some(log.clone())
-            } else {
-                None
-            }
-        })
-        .collect();
-
-    save_logs_to_file(&malicious_logs, "malicious_logs.txt")
-}
-
-/// Saves the provided logs to a file named `malicious_logs.txt`.
+/// Analyzes multiple log entries, on a pool of worker threads when the `parallel` feature is enabled,
+/// and returns the malicious ones.
 ///
 /// # Parameters
-/// - `logs`: A reference to a vector of log entries to be saved.
-/// - `filename`: The name of the file to save the logs to (in this case, `malicious_logs.txt`).
+/// - `logs`: The log entries to be analyzed; they are left untouched.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `dictionary`: The indexed dictionary of known patterns to compare against.
+/// - `limit`: An optional limit on the number of log entries to analyze.
+/// - `scoring`: The thresholds and normalization used to determine the criticality.
+/// - `threads`: The number of worker threads; one per CPU when `None`. Ignored without the `parallel` feature.
 ///
 /// # Returns
-/// - `io::Result<()>`: The result of the file operation.
-fn save_logs_to_file(logs: &[String], filename: &str) -> io::Result<()> {
-    let file_path = Path::new(filename);
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&file_path)?;
-
-    for log in logs {
-        writeln!(file, "{}", log)?;
-    }
+/// - The index and analysis result of every malicious log entry in input order, or an error if the
+///   thread pool cannot be created.
+pub fn find_malicious_logs(
+    logs: &[String],
+    format: &dyn LogFormat,
+    delimiters: &[String],
+    dictionary: &DictionaryIndex,
+    limit: Option<usize>,
+    scoring: &ScoringConfig,
+    threads: Option<usize>
+) -> io::Result<Vec<(usize, AnalysisResult)>> {
+    #[cfg(feature = "parallel")]
+    let malicious_logs = analyze_logs_and_collect_malicious_parallel(logs, format, delimiters, dictionary, limit, scoring, threads)?;
+
+    #[cfg(not(feature = "parallel"))]
+    let malicious_logs = {
+        if threads.is_some() {
+            tracing::warn!("Built without the `parallel` feature; analyzing on a single thread.");
+        }
+        analyze_logs_and_collect_malicious(logs, format, delimiters, dictionary, limit, scoring)
+    };
 
-    println!("Malicious logs saved to {}", filename);
-    Ok(())
+    info!("Found {} malicious logs.", malicious_logs.len());
+    Ok(malicious_logs)
 }
 
 /// Determines if a log entry is malicious based on its criticality value.
 ///
 /// # Description
 /// This function analyzes the log entry using the `analyze_log_and_determine_criticality` function,
-/// and then checks if the resulting criticality is "High," which defines whether the request
+/// and then checks if the resulting criticality is `Criticality::High`, which defines whether the request
 /// is malicious or not.
 ///
 /// # Parameters
-/// - log_entry: A mutable reference to the log entry string to be analyzed.
-/// - delimiters: A mutable reference to a vector of delimiter strings used for splitting the log entry.
-/// - dictionary: A mutable reference to a vector of known patterns to compare against.
-/// - max_levenshtein_distance: The maximum possible Levenshtein distance.
+/// - log_entry: The log entry to be analyzed; it is left untouched.
+/// - format: The format of the log entry, used to extract its request target.
+/// - delimiters: The delimiter strings used for splitting the log entry.
+/// - dictionary: The indexed dictionary of known patterns to compare against.
+/// - scoring: The thresholds and normalization used to determine the criticality.
 ///
 /// # Returns
-/// - `true` if the request is malicious (i.e., if the criticality is "High"), otherwise `false`.
+/// - `true` if the request is malicious (i.e., if the criticality is `High`), otherwise `false`.
 pub fn is_malicious(
-    log_entry: &mut String,
-    delimiters: &mut Vec<String>,
-    dictionary: &mut Vec<String>
+    log_entry: &str,
+    format: &dyn LogFormat,
+    delimiters: &[String],
+    dictionary: &DictionaryIndex,
+    scoring: &ScoringConfig
 ) -> bool {
     // Analyze the log entry and determine its criticality
-    let criticality = analyze_log_and_determine_criticality(
+    let result = analyze_log_and_determine_criticality(
         log_entry,
+        format,
         delimiters,
-        dictionary
+        dictionary,
+        scoring
     );
 
     // Check if the criticality is "High"
-    if criticality == "High" {
-        println!("The request is determined to be malicious.");
-        return true;
+    if result.is_malicious() {
+        debug!("The request is determined to be malicious.");
+        true
     } else {
-        println!("The request is not malicious.");
-        return false;
+        debug!("The request is not malicious.");
+        false
     }
 }
 
-/// Removes the request type (e.g., GET, POST) from a log entry.
-///
-/// # Parameters
-/// - `log_entry`: A mutable reference to the log entry string.
-pub fn remove_request_type_from_log(log_entry: &mut String)
+#[cfg(test)]
+mod tests
 {
-    let mut parts: Vec<String> = log_entry.split(" ").map(|s| s.to_string()).collect();
-    parts.remove(0);
-    *log_entry = parts.join(" ");
-}
+    use super::*;
 
-/// Finds the maximum length of strings in the dictionary.
-///
-/// # Parameters
-/// - `dictionary`: A reference to a vector of known patterns.
-///
-/// # Returns
-/// The maximum length of the strings in the dictionary.
-fn find_max_levenshtein_distance(dictionary: &Vec<String>) -> usize {
-    dictionary.iter().map(|s| s.len()).max().unwrap_or(0)
-}
\ No newline at end of file
+    fn scores(metrics: Vec<MetricScore>) -> SimilarityScores
+    {
+        SimilarityScores { average_levenshtein: 2, average_dice_coefficient: 0.6, max_levenshtein_distance: 10, metrics }
+    }
+
+    fn metric(weight: f64, similarity: f64) -> MetricScore
+    {
+        MetricScore { name: "lcs".to_string(), weight, similarity }
+    }
+
+    #[test]
+    fn default_weights_keep_the_two_term_score()
+    {
+        let expected = calculate_composite_score(2, 0.6, 10, 0.5, 0.5);
+        assert!((scores(Vec::new()).composite_score(0.5, 0.5) - expected).abs() < 1e-12);
+        assert!((expected - 0.7).abs() < 1e-12);
+    }
+
+    #[test]
+    fn composite_score_is_a_weighted_average()
+    {
+        // (1 * 0.8 + 1 * 0.6) / 2
+        assert!((scores(Vec::new()).composite_score(1.0, 1.0) - 0.7).abs() < 1e-12);
+
+        // A metric as similar as the two-term score does not change it, whatever its weight
+        for weight in [0.1, 1.0, 5.0]
+        {
+            assert!((scores(vec![metric(weight, 0.7)]).composite_score(0.5, 0.5) - 0.7).abs() < 1e-12);
+        }
+
+        // (0.5 * 0.8 + 0.5 * 0.6 + 1 * 1.0) / 2
+        assert!((scores(vec![metric(1.0, 1.0)]).composite_score(0.5, 0.5) - 0.85).abs() < 1e-12);
+        assert!(scores(vec![metric(3.0, 1.0), metric(3.0, 1.0)]).composite_score(0.5, 0.5) <= 1.0);
+    }
+
+    #[test]
+    fn free_function_and_method_agree()
+    {
+        for (alpha, beta) in [(0.5, 0.5), (1.0, 1.0), (0.9, 0.1), (2.0, 0.0)]
+        {
+            let expected = scores(Vec::new()).composite_score(alpha, beta);
+            assert!((calculate_composite_score(2, 0.6, 10, alpha, beta) - expected).abs() < 1e-12);
+        }
+        assert!((calculate_composite_score(2, 0.6, 10, 1.0, 1.0) - 0.7).abs() < 1e-12);
+    }
+
+    #[test]
+    fn invalid_scoring_is_rejected()
+    {
+        assert_eq!(ScoringConfig::default().validate(), Ok(()));
+
+        let invalid = [
+            ScoringConfig { alpha: 0.0, beta: 0.0, ..ScoringConfig::default() },
+            ScoringConfig { alpha: f64::NAN, ..ScoringConfig::default() },
+            ScoringConfig { beta: -0.5, ..ScoringConfig::default() },
+            ScoringConfig { high_threshold: 1.5, ..ScoringConfig::default() },
+            ScoringConfig { medium_threshold: -0.1, ..ScoringConfig::default() },
+            ScoringConfig { medium_threshold: 0.8, high_threshold: 0.7, ..ScoringConfig::default() },
+            ScoringConfig { max_levenshtein_distance: Some(0), ..ScoringConfig::default() },
+        ];
+        for scoring in invalid
+        {
+            assert!(scoring.validate().is_err(), "{:?}", scoring);
+        }
+    }
+
+    #[test]
+    fn analysis_past_the_end_is_empty()
+    {
+        let dictionary = DictionaryIndex::new(vec!["passwd".to_string()], TextUnit::Char, NormalizationConfig::default());
+        let logs = vec!["GET /a HTTP/1.1".to_string(), "GET /b HTTP/1.1".to_string()];
+        let format = log_format::RequestLineFormat;
+        let scoring = ScoringConfig::default();
+
+        assert!(analyze_logs_from_index(&logs, 5, &format, &[], &dictionary, None, &scoring).is_empty());
+        assert_eq!(analyze_logs_from_index(&logs, 1, &format, &[], &dictionary, None, &scoring).len(), 1);
+        assert_eq!(analyze_logs_from_index(&logs, 0, &format, &[], &dictionary, Some(1), &scoring).len(), 1);
+        assert_eq!(analyze_logs(&logs, &format, &[], &dictionary, Some(5), &scoring).len(), 2);
+    }
+
+    #[test]
+    fn zero_weights_give_a_zero_score()
+    {
+        assert_eq!(scores(vec![metric(0.0, 1.0)]).composite_score(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn levenshtein_similarity_stays_between_0_and_1()
+    {
+        // An average distance above the configured maximum counts as no similarity, not a negative one
+        let distant = SimilarityScores { average_levenshtein: 25, ..scores(Vec::new()) };
+        assert!((distant.composite_score(1.0, 1.0) - 0.3).abs() < 1e-12);
+
+        // A zero maximum gives no Levenshtein similarity instead of a NaN score
+        let zero_maximum = SimilarityScores { average_levenshtein: 0, max_levenshtein_distance: 0, ..scores(Vec::new()) };
+        assert!((zero_maximum.composite_score(1.0, 1.0) - 0.3).abs() < 1e-12);
+        let zero_maximum = SimilarityScores { max_levenshtein_distance: 0, ..scores(Vec::new()) };
+        assert!((zero_maximum.composite_score(1.0, 1.0) - 0.3).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,91 @@
+/// File: logging.rs
+///
+/// This file contains the setup of the leveled diagnostic logging used across the modules.
+/// Only warnings and errors are shown by default; the verbosity can be raised globally with `-v`
+/// or per module with a filter such as `fs::log_ops=trace,fs::thread_utils=info`. Events can
+/// additionally be written to a file as JSON lines. The file includes the following items:
+///
+/// - `LoggingOptions`: The verbosity, module filter and JSON output file.
+/// - `init`: Installs the global logger for the given options.
+use std::env;
+use std::fs::File;
+use std::io;
+use std::sync::Mutex;
+
+use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+/// Environment variable holding the module filter, used when none is given on the command line.
+pub const LOG_FILTER_ENV_VAR: &str = "FS_LOG";
+
+/// The verbosity, module filter and JSON output file of the logger.
+#[derive(Debug, Clone, Default)]
+pub struct LoggingOptions
+{
+    /// Number of `-v` flags: 0 shows warnings, 1 info, 2 debug, 3 or more trace.
+    pub verbosity: u8,
+    /// Per-module filter directives (e.g. `warn,fs::log_ops=debug`); overrides the verbosity.
+    pub filter: Option<String>,
+    /// File the events are additionally written to as JSON lines.
+    pub json_file: Option<String>,
+}
+
+impl LoggingOptions
+{
+    /// Returns the filter directives for the options.
+    fn directives(&self) -> String
+    {
+        if let Some(filter) = &self.filter
+        {
+            return filter.clone();
+        }
+
+        if let Ok(filter) = env::var(LOG_FILTER_ENV_VAR)
+        {
+            return filter;
+        }
+
+        match self.verbosity
+        {
+            0 => "warn".to_string(),
+            1 => "info".to_string(),
+            2 => "debug".to_string(),
+            _ => "trace".to_string(),
+        }
+    }
+}
+
+/// Installs the global logger for the given options.
+///
+/// # Summary
+/// Human readable events go to standard error; when a JSON file is given, the same events are also
+/// written to it, one JSON object per line.
+///
+/// # Parameters
+/// - `options`: The verbosity, module filter and JSON output file.
+///
+/// # Returns
+/// A result indicating failure to parse the filter or to create the JSON file.
+pub fn init(options: &LoggingOptions) -> io::Result<()>
+{
+    let filter = EnvFilter::try_new(options.directives())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid log filter: {}", e)))?;
+
+    let json_layer = match &options.json_file
+    {
+        Some(file_name) =>
+        {
+            let file = File::create(file_name)?;
+            Some(fmt::layer().json().with_writer(Mutex::new(file)))
+        },
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(io::stderr))
+        .with(json_layer)
+        .try_init()
+        .map_err(|e| io::Error::other(e.to_string()))
+}
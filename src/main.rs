@@ -1,60 +1,81 @@
 
-//Import HashMap
-use std::collections::HashMap;
-
+mod benchmark;
+mod checkpoint;
+mod cli;
+mod commands;
+mod config;
+mod dict_index;
+mod dict_ops;
+mod evaluation;
 mod file_ops;
-mod thread_utils;
+mod file_watcher;
+mod hash_ops;
+mod json_format;
+mod log_format;
 mod log_ops;
+mod logging;
+mod normalize;
+mod report;
+mod similarity;
 mod string_utils;
+mod supervisor;
+mod tailer;
+#[cfg(test)]
+mod test_support;
+mod thread_utils;
+mod threshold_sweep;
+mod watch_set;
+mod workspace;
 
-use std::env;
+use clap::Parser;
+
+use cli::{Cli, Command, DictCommand, ScanArgs};
+use config::Config;
+use workspace::Workspace;
 
 fn main() {
 
     //read program parameters
-    let _args: Vec<String> = env::args().collect();
-
-    let mut malicious_logs: Vec<String> = Vec::new();
-    let mut separating_strings: Vec<String> = Vec::new();
-    let mut logs_to_check: Vec<String> = Vec::new();
+    let cli = Cli::parse();
 
-    // Loading malicious logs
-    let mut _malicious_logs_filenames:Vec<String> = file_ops::get_filenames_with_prefix("malicious_logs".to_string());
-    if let Err(e) = file_ops::load_files_into_vector_ref(&mut malicious_logs, &_malicious_logs_filenames)
-    {
-        eprintln!("Error loading malicious logs: {}", e);
-    }
-    
-    // Loading separating strings
-    let separating_strings_filenames = file_ops::get_filenames_with_prefix("special_strings".to_string());
-    if let Err(e) = file_ops::load_files_into_vector(&mut separating_strings, separating_strings_filenames)
-    {
-        eprintln!("Error loading separating strings: {}", e);
+    if let Err(e) = logging::init(&cli.logging_options()) {
+        eprintln!("Error setting up logging: {}", e);
+        std::process::exit(1);
     }
 
-    // Loading logs that need to be checked
-    let logs_to_check_filenames = file_ops::get_filenames_with_prefix("logs_to_check".to_string());
-    if let Err(e) = file_ops::load_files_into_vector(&mut logs_to_check, logs_to_check_filenames)
-    {
-        eprintln!("Error loading logs to check: {}", e);
-    }
-
-    let max_levenshtein_distance = 3;
+    let config = match Config::locate_and_load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error loading config file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let workspace = Workspace::resolve(cli.data_dir.as_deref(), &config);
 
-    // Analyze the loaded logs and save malicious ones to `malicious_logs.txt`
-    match log_ops::analyze_logs_and_save_malicious(
-        &mut logs_to_check,
-        &mut separating_strings,
-        &mut malicious_logs,
-        None) {
-        Ok(_) => println!("Malicious logs analysis complete and saved."),
-        Err(e) => eprintln!("Error during malicious log analysis: {}", e),
+    let mut command = cli.command.unwrap_or_else(|| Command::Scan(ScanArgs::default()));
+    if let Some(scoring) = command.scoring_args_mut() {
+        if let Err(e) = scoring.apply_config(&config) {
+            eprintln!("Error loading config file: {}", e);
+            std::process::exit(1);
+        }
+        if let Err(e) = scoring.to_scoring_config().validate() {
+            eprintln!("Invalid scoring configuration: {}", e);
+            std::process::exit(1);
+        }
     }
 
+    let result = match command {
+        Command::Scan(args) => commands::run_scan(&workspace, &args),
+        Command::Watch(args) => commands::run_watch(&workspace, &args),
+        Command::Dict(DictCommand::Build(args)) => commands::run_dict_build(&workspace, &args),
+        Command::Hash(args) => commands::run_hash(&workspace, &args),
+        Command::Evaluate(args) => commands::run_evaluate(&workspace, &args),
+        Command::Sweep(args) => commands::run_sweep(&workspace, &args),
+        Command::Bench(args) => commands::run_bench(&workspace, &args),
+    };
 
-    // this will be a video demo in my presentation:
-    // Starting a live thread, that will wait for new logs (changes in log files)
-    //thread_utils::watch_for_new_log_entries("logs_to_check.txt".to_string(), &mut logs_to_check, &mut separating_strings, &mut malicious_logs);
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
-
-
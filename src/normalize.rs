@@ -0,0 +1,452 @@
+/// File: normalize.rs
+///
+/// This file contains the normalization pipeline requests go through before they are split into tokens.
+/// Attackers hide known patterns behind encodings (`%2e%2e%2f`, `&#47;`, `\u002f`), case changes, null
+/// bytes, backslashes and redundant path segments; normalizing undoes these so the request is compared
+/// in the form the server would interpret it. The dictionary entries go through the same pipeline, so
+/// both sides are compared in the same form. Every step can be turned on or off, and the steps that
+/// changed a request are recorded, so the analysis shows what the detector actually compared.
+/// The file includes the following items:
+///
+/// - `NormalizationStep`: One step of the pipeline; the steps run in the order they are declared.
+/// - `NormalizationConfig`: The enabled steps and the options of the percent decoder.
+/// - `Normalized`: A normalized request field and the steps that changed it.
+///
+/// Modules Required:
+/// - `string_utils`: Provides the percent decoder.
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::string_utils::{self, DecodeOptions};
+
+/// One step of the normalization pipeline; the steps run in the order they are declared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NormalizationStep
+{
+    /// Decodes `%XX`, `%uXXXX` and `+`, repeatedly for multiply encoded input.
+    PercentDecode,
+    /// Decodes HTML entities: `&lt;`, `&gt;`, `&amp;`, `&quot;`, `&apos;`, `&nbsp;` (as a space), `&#NN;` and `&#xHH;`.
+    HtmlEntities,
+    /// Decodes `\uXXXX`, `\u{X}` and `\xHH` escapes.
+    UnicodeEscapes,
+    /// Removes null characters.
+    NullBytes,
+    /// Turns backslashes into slashes.
+    Backslashes,
+    /// Resolves `//`, `/./` and `/../` in the path of fields starting with `/`, keeping the `..` segments
+    /// that climb above the root; the query is left untouched.
+    PathCanonicalization,
+    /// Folds the field to lower case.
+    CaseFolding,
+    /// Collapses runs of whitespace into one space and trims the field.
+    Whitespace,
+}
+
+impl NormalizationStep
+{
+    /// Every step, in pipeline order.
+    pub const ALL: [NormalizationStep; 8] = [
+        NormalizationStep::PercentDecode,
+        NormalizationStep::HtmlEntities,
+        NormalizationStep::UnicodeEscapes,
+        NormalizationStep::NullBytes,
+        NormalizationStep::Backslashes,
+        NormalizationStep::PathCanonicalization,
+        NormalizationStep::CaseFolding,
+        NormalizationStep::Whitespace,
+    ];
+
+    /// Returns the name of the step.
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            NormalizationStep::PercentDecode => "percent",
+            NormalizationStep::HtmlEntities => "html",
+            NormalizationStep::UnicodeEscapes => "unicode",
+            NormalizationStep::NullBytes => "null",
+            NormalizationStep::Backslashes => "backslash",
+            NormalizationStep::PathCanonicalization => "path",
+            NormalizationStep::CaseFolding => "case",
+            NormalizationStep::Whitespace => "whitespace",
+        }
+    }
+}
+
+impl fmt::Display for NormalizationStep
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for NormalizationStep
+{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        NormalizationStep::ALL.into_iter()
+            .find(|step| step.as_str().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| format!("unknown normalization step '{}' (expected percent, html, unicode, null, backslash, path, case or whitespace)", name))
+    }
+}
+
+/// The enabled steps of the normalization pipeline and the options of the percent decoder.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NormalizationConfig
+{
+    steps: BTreeSet<NormalizationStep>,
+    pub decode: DecodeOptions,
+}
+
+impl fmt::Display for NormalizationConfig
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        if self.steps.is_empty()
+        {
+            return f.write_str("none");
+        }
+        f.write_str(&self.steps().map(|step| step.as_str()).collect::<Vec<&str>>().join(","))
+    }
+}
+
+impl FromStr for NormalizationConfig
+{
+    type Err = String;
+
+    /// Parses a comma-separated list of steps, `all` or `none`.
+    fn from_str(list: &str) -> Result<Self, Self::Err>
+    {
+        match list.trim().to_ascii_lowercase().as_str()
+        {
+            "all" => Ok(NormalizationConfig::all()),
+            "none" | "" => Ok(NormalizationConfig::default()),
+            _ => Ok(NormalizationConfig::new(list.split(',').map(str::parse).collect::<Result<Vec<_>, _>>()?)),
+        }
+    }
+}
+
+impl NormalizationConfig
+{
+    /// Creates a pipeline running the given steps, in pipeline order.
+    pub fn new(steps: impl IntoIterator<Item = NormalizationStep>) -> NormalizationConfig
+    {
+        NormalizationConfig { steps: steps.into_iter().collect(), decode: DecodeOptions::default() }
+    }
+
+    /// Creates a pipeline running every step.
+    pub fn all() -> NormalizationConfig
+    {
+        NormalizationConfig::new(NormalizationStep::ALL)
+    }
+
+    /// Turns a step on or off.
+    #[allow(dead_code)]
+    pub fn set_enabled(&mut self, step: NormalizationStep, enabled: bool)
+    {
+        if enabled
+        {
+            self.steps.insert(step);
+        }
+        else
+        {
+            self.steps.remove(&step);
+        }
+    }
+
+    /// Returns whether a step is enabled.
+    #[allow(dead_code)]
+    pub fn is_enabled(&self, step: NormalizationStep) -> bool
+    {
+        self.steps.contains(&step)
+    }
+
+    /// Returns the enabled steps, in pipeline order.
+    pub fn steps(&self) -> impl Iterator<Item = NormalizationStep> + '_
+    {
+        self.steps.iter().copied()
+    }
+
+    /// Normalizes a request field.
+    ///
+    /// # Parameters
+    /// - `field`: The request target, a header value or the body of a request.
+    ///
+    /// # Returns
+    /// The normalized field with the steps that changed it.
+    pub fn normalize(&self, field: &str) -> Normalized
+    {
+        let mut normalized = Normalized { text: field.to_string(), applied: Vec::new(), decode_passes: 0 };
+
+        for step in self.steps()
+        {
+            let text = match step
+            {
+                NormalizationStep::PercentDecode =>
+                {
+                    let decoded = string_utils::percent_decode(&normalized.text, &self.decode);
+                    normalized.decode_passes = decoded.passes;
+                    decoded.text
+                },
+                NormalizationStep::HtmlEntities => decode_html_entities(&normalized.text),
+                NormalizationStep::UnicodeEscapes => decode_unicode_escapes(&normalized.text),
+                NormalizationStep::NullBytes => normalized.text.replace('\0', ""),
+                NormalizationStep::Backslashes => normalized.text.replace('\\', "/"),
+                NormalizationStep::PathCanonicalization => canonicalize_path(&normalized.text),
+                NormalizationStep::CaseFolding => normalized.text.to_lowercase(),
+                NormalizationStep::Whitespace => normalized.text.split_whitespace().collect::<Vec<&str>>().join(" "),
+            };
+
+            if text != normalized.text
+            {
+                normalized.text = text;
+                normalized.applied.push(step);
+            }
+        }
+
+        normalized
+    }
+}
+
+/// A normalized request field and the steps that changed it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalized
+{
+    /// The field as it was compared with the dictionary.
+    pub text: String,
+    /// The steps that changed the field, in the order they ran.
+    pub applied: Vec<NormalizationStep>,
+    /// The number of percent-decoding passes that changed the field; more than 1 means multiple encoding.
+    pub decode_passes: usize,
+}
+
+/// The longest HTML entity recognized, including `&` and `;`.
+const MAX_ENTITY_LENGTH: usize = 12;
+
+/// Decodes one HTML entity, given without its `&` and `;`.
+fn decode_html_entity(entity: &str) -> Option<char>
+{
+    match entity
+    {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ =>
+        {
+            let number = entity.strip_prefix('#')?;
+            let code_point = match number.strip_prefix(['x', 'X'])
+            {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code_point)
+        },
+    }
+}
+
+/// Decodes the HTML entities of a text; unknown and unterminated entities are kept as they are.
+fn decode_html_entities(text: &str) -> String
+{
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&')
+    {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..].find(';')
+            .filter(|&end| end + 2 <= MAX_ENTITY_LENGTH)
+            .and_then(|end| decode_html_entity(&rest[1..=end]).map(|character| (character, end + 2)));
+
+        match entity
+        {
+            Some((character, length)) =>
+            {
+                decoded.push(character);
+                rest = &rest[length..];
+            },
+            None =>
+            {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Parses the hexadecimal digits at the start of a text, up to a maximum count.
+fn leading_hex(text: &str, max_digits: usize) -> (u32, usize)
+{
+    let digits = text.bytes().take(max_digits).take_while(u8::is_ascii_hexdigit).count();
+    (u32::from_str_radix(&text[..digits], 16).unwrap_or(0), digits)
+}
+
+/// Decodes the `\uXXXX`, `\u{X}` and `\xHH` escapes of a text.
+///
+/// # Summary
+/// Consecutive `\xHH` escapes are assembled as UTF-8 bytes, and `\uXXXX` escapes as UTF-16 code units,
+/// so surrogate pairs combine; what does not decode becomes U+FFFD. Malformed escapes are kept as they are.
+fn decode_unicode_escapes(text: &str) -> String
+{
+    let mut decoded = String::with_capacity(text.len());
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut code_units: Vec<u16> = Vec::new();
+    let mut rest = text;
+
+    let flush = |decoded: &mut String, bytes: &mut Vec<u8>, code_units: &mut Vec<u16>| {
+        decoded.push_str(&String::from_utf8_lossy(bytes));
+        bytes.clear();
+        decoded.extend(char::decode_utf16(code_units.drain(..)).map(|character| character.unwrap_or(char::REPLACEMENT_CHARACTER)));
+    };
+
+    while let Some(start) = rest.find('\\')
+    {
+        if start > 0
+        {
+            flush(&mut decoded, &mut bytes, &mut code_units);
+            decoded.push_str(&rest[..start]);
+        }
+        rest = &rest[start..];
+        let escape = &rest[1..];
+
+        if let Some(braced) = escape.strip_prefix("u{")
+        {
+            let (code_point, digits) = leading_hex(braced, 6);
+            if digits > 0 && braced[digits..].starts_with('}')
+            {
+                flush(&mut decoded, &mut bytes, &mut code_units);
+                decoded.push(char::from_u32(code_point).unwrap_or(char::REPLACEMENT_CHARACTER));
+                rest = &braced[digits + 1..];
+                continue;
+            }
+        }
+        else if let Some(hex) = escape.strip_prefix('u')
+        {
+            let (code_unit, digits) = leading_hex(hex, 4);
+            if digits == 4
+            {
+                decoded.push_str(&String::from_utf8_lossy(&bytes));
+                bytes.clear();
+                code_units.push(code_unit as u16);
+                rest = &hex[4..];
+                continue;
+            }
+        }
+        else if let Some(hex) = escape.strip_prefix('x')
+        {
+            let (byte, digits) = leading_hex(hex, 2);
+            if digits == 2
+            {
+                decoded.extend(char::decode_utf16(code_units.drain(..)).map(|character| character.unwrap_or(char::REPLACEMENT_CHARACTER)));
+                bytes.push(byte as u8);
+                rest = &hex[2..];
+                continue;
+            }
+        }
+
+        flush(&mut decoded, &mut bytes, &mut code_units);
+        decoded.push('\\');
+        rest = escape;
+    }
+
+    flush(&mut decoded, &mut bytes, &mut code_units);
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Resolves `//`, `/./` and `/../` in the path of a field starting with `/`, like the server would.
+///
+/// # Summary
+/// A `..` segment removes the segment before it; the `..` segments that would climb above the root are
+/// kept, since they are the evidence of a path traversal attempt (`/../../etc/passwd` stays as it is
+/// instead of becoming `/etc/passwd`). A trailing slash, or a trailing `.` or resolved `..` segment,
+/// leaves the path ending with a slash. The query string and fragment are left untouched.
+fn canonicalize_path(field: &str) -> String
+{
+    if !field.starts_with('/')
+    {
+        return field.to_string();
+    }
+
+    let path_end = field.find(['?', '#']).unwrap_or(field.len());
+    let (path, query) = field.split_at(path_end);
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut ends_with_slash = false;
+    for segment in path.split('/').skip(1)
+    {
+        ends_with_slash = matches!(segment, "" | "." | "..");
+        match segment
+        {
+            "" | "." => {},
+            ".." if segments.last().is_some_and(|&last| last != "..") =>
+            {
+                segments.pop();
+            },
+            ".." =>
+            {
+                segments.push(segment);
+                ends_with_slash = false;
+            },
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut canonical = format!("/{}", segments.join("/"));
+    if ends_with_slash && !segments.is_empty()
+    {
+        canonical.push('/');
+    }
+    canonical.push_str(query);
+    canonical
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn path_canonicalization_resolves_redundant_segments()
+    {
+        assert_eq!(canonicalize_path("/a/./b//c/../d?x=/../y"), "/a/b/d?x=/../y");
+        assert_eq!(canonicalize_path("/a/b/.."), "/a/");
+        assert_eq!(canonicalize_path("/a/b/"), "/a/b/");
+        assert_eq!(canonicalize_path("a/../b"), "a/../b");
+    }
+
+    #[test]
+    fn path_canonicalization_keeps_traversal_above_the_root()
+    {
+        assert_eq!(canonicalize_path("/../../etc/passwd"), "/../../etc/passwd");
+        assert_eq!(canonicalize_path("/a/b/../../../etc/passwd"), "/../etc/passwd");
+        assert_eq!(canonicalize_path("/.."), "/..");
+
+        let normalized = NormalizationConfig::all().normalize("/%2e%2e/%2E%2E/etc/passwd");
+        assert_eq!(normalized.text, "/../../etc/passwd");
+        assert_eq!(normalized.applied, vec![NormalizationStep::PercentDecode]);
+    }
+
+    #[test]
+    fn steps_run_in_pipeline_order()
+    {
+        let normalization: NormalizationConfig = "case,percent,path".parse().unwrap();
+        let normalized = normalization.normalize("/A/%2E/B");
+
+        assert_eq!(normalized.text, "/a/b");
+        assert_eq!(normalized.applied, vec![NormalizationStep::PercentDecode, NormalizationStep::PathCanonicalization, NormalizationStep::CaseFolding]);
+        assert_eq!(normalization.to_string(), "percent,path,case");
+        assert!("percent,bogus".parse::<NormalizationConfig>().is_err());
+    }
+}
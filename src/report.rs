@@ -0,0 +1,633 @@
+/// File: report.rs
+///
+/// This file contains the report writers used to save the detected malicious requests in a format
+/// other tools can ingest: bare log lines (the historical `malicious_logs.txt`), JSON Lines for SIEMs,
+/// CSV for spreadsheets, and SARIF for code-scanning dashboards.
+/// The file includes the following items:
+///
+/// - `DETECTOR_NAME`, `DETECTOR_VERSION`: The name and version recorded in every finding.
+/// - `SourceLocation`: The file and line number a log entry was read from.
+/// - `Finding`: One detected malicious request with its location, scores and matched patterns.
+/// - `ReportFormat`: The available report formats.
+/// - `OutputMode`: How an existing report file is treated.
+/// - `write_findings`: Writes findings to a writer in a report format.
+/// - `save_findings`: Writes findings to a report file.
+///
+/// Modules Required:
+/// - `file_ops`: Provides atomic writes and rotation of the report file.
+/// - `log_ops`: Provides the analysis result the findings are built from.
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, MAIN_SEPARATOR};
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::file_ops;
+use crate::log_ops::{AnalysisResult, Criticality};
+
+/// The name of the detector, as recorded in the reports.
+pub const DETECTOR_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// The version of the detector, as recorded in the reports.
+pub const DETECTOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The identifier of the SARIF rule every finding is reported under.
+const SARIF_RULE_ID: &str = "malicious-request";
+
+/// The file and 1-based line number a log entry was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation
+{
+    pub file: String,
+    pub line_number: usize,
+}
+
+/// One detected malicious request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding
+{
+    pub source_file: String,
+    pub line_number: usize,
+    /// The log line as it appears in the source file.
+    pub line: String,
+    pub criticality: Criticality,
+    pub composite_score: f64,
+    /// The distinct dictionary entries that best matched the tokens of the request.
+    pub matched_patterns: Vec<String>,
+    /// The analyzed fields of the request as the detector compared them, after normalization.
+    pub normalized_fields: Vec<String>,
+    /// The distinct normalization steps that changed a field, in pipeline order.
+    pub normalization_steps: Vec<String>,
+    pub detector_version: String,
+}
+
+impl Finding
+{
+    /// Builds the finding of an analyzed log line.
+    ///
+    /// # Parameters
+    /// - `source`: The file and line number the log line was read from.
+    /// - `line`: The log line.
+    /// - `result`: The analysis result of the log line.
+    ///
+    /// # Returns
+    /// The finding.
+    pub fn new(source: &SourceLocation, line: &str, result: &AnalysisResult) -> Finding
+    {
+        Finding {
+            source_file: source.file.clone(),
+            line_number: source.line_number,
+            line: line.to_string(),
+            criticality: result.criticality,
+            composite_score: result.composite_score,
+            matched_patterns: result.matched_patterns().into_iter().map(|pattern| pattern.to_string()).collect(),
+            normalized_fields: result.normalized_fields.iter().map(|field| field.text.clone()).collect(),
+            normalization_steps: result.normalization_steps().into_iter().map(|step| step.to_string()).collect(),
+            detector_version: DETECTOR_VERSION.to_string(),
+        }
+    }
+
+    /// Returns the finding as a JSON object.
+    fn to_json(&self) -> Value
+    {
+        json!({
+            "source_file": self.source_file,
+            "line_number": self.line_number,
+            "line": self.line,
+            "criticality": self.criticality.as_str(),
+            "composite_score": self.composite_score,
+            "matched_patterns": self.matched_patterns,
+            "normalized_fields": self.normalized_fields,
+            "normalization_steps": self.normalization_steps,
+            "detector_version": self.detector_version,
+        })
+    }
+}
+
+/// The available report formats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportFormat
+{
+    /// The bare log lines, one per line.
+    #[default]
+    Text,
+    /// One JSON object per finding and line.
+    Jsonl,
+    /// One CSV row per finding, with a header row; the matched patterns, normalized fields and
+    /// normalization steps are each written as a JSON array of strings.
+    Csv,
+    /// A SARIF 2.1.0 log with one result per finding.
+    Sarif,
+}
+
+impl ReportFormat
+{
+    /// Returns whether reports of this format can be extended by appending to the file.
+    pub fn is_appendable(&self) -> bool
+    {
+        *self != ReportFormat::Sarif
+    }
+}
+
+impl fmt::Display for ReportFormat
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let name = match self
+        {
+            ReportFormat::Text => "text",
+            ReportFormat::Jsonl => "jsonl",
+            ReportFormat::Csv => "csv",
+            ReportFormat::Sarif => "sarif",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ReportFormat
+{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        match name.to_ascii_lowercase().as_str()
+        {
+            "text" => Ok(ReportFormat::Text),
+            "jsonl" | "json" => Ok(ReportFormat::Jsonl),
+            "csv" => Ok(ReportFormat::Csv),
+            "sarif" => Ok(ReportFormat::Sarif),
+            _ => Err(format!("unknown report format '{}' (expected text, jsonl, csv or sarif)", name)),
+        }
+    }
+}
+
+/// How an existing report file is treated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode
+{
+    /// The report replaces the existing file.
+    #[default]
+    Overwrite,
+    /// The findings are added to the end of the existing file; not available for SARIF reports.
+    Append,
+    /// The existing file is moved aside to `<file>.<n>` and a new report is written.
+    Rotate,
+}
+
+impl fmt::Display for OutputMode
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let name = match self
+        {
+            OutputMode::Overwrite => "overwrite",
+            OutputMode::Append => "append",
+            OutputMode::Rotate => "rotate",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for OutputMode
+{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        match name.to_ascii_lowercase().as_str()
+        {
+            "overwrite" => Ok(OutputMode::Overwrite),
+            "append" => Ok(OutputMode::Append),
+            "rotate" => Ok(OutputMode::Rotate),
+            _ => Err(format!("unknown output mode '{}' (expected overwrite, append or rotate)", name)),
+        }
+    }
+}
+
+/// The columns of the CSV report.
+const CSV_HEADER: &str = "source_file,line_number,line,criticality,composite_score,matched_patterns,detector_version,normalized_fields,normalization_steps";
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break.
+fn csv_field(field: &str) -> String
+{
+    if field.contains([',', '"', '\n', '\r'])
+    {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else
+    {
+        field.to_string()
+    }
+}
+
+/// Returns a list of strings as a CSV field holding a JSON array, so no separator can be confused
+/// with the contents of an element.
+fn csv_list(items: &[String]) -> String
+{
+    csv_field(&Value::from(items).to_string())
+}
+
+/// Percent-encodes the characters of a path that are not allowed in a URI path.
+fn percent_encode_path(path: &str, keep_colons: bool) -> String
+{
+    let mut encoded = String::with_capacity(path.len());
+    for &byte in path.as_bytes()
+    {
+        match byte
+        {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            b':' if keep_colons => encoded.push(':'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Returns the SARIF artifact URI of a source file.
+///
+/// # Summary
+/// A relative path becomes a relative URI reference with `/` separators; an absolute path becomes a
+/// `file://` URI. Characters that are not allowed in a URI are percent-encoded, including the colons of
+/// a relative path, which would otherwise read as a URI scheme.
+///
+/// # Parameters
+/// - `file_name`: The path of the source file, as it was opened.
+///
+/// # Returns
+/// The URI reference of the file.
+fn artifact_uri(file_name: &str) -> String
+{
+    let mut path = file_name.to_string();
+    if MAIN_SEPARATOR != '/'
+    {
+        path = path.replace(MAIN_SEPARATOR, "/");
+    }
+
+    if !Path::new(file_name).is_absolute()
+    {
+        return percent_encode_path(&path, false);
+    }
+
+    // A Windows path starts with its drive letter, which takes a leading slash in the URI path
+    if !path.starts_with('/')
+    {
+        path.insert(0, '/');
+    }
+    format!("file://{}", percent_encode_path(&path, true))
+}
+
+/// Builds the SARIF 2.1.0 log of the findings.
+fn sarif_log(findings: &[Finding]) -> Value
+{
+    let results: Vec<Value> = findings.iter()
+        .map(|finding| json!({
+            "ruleId": SARIF_RULE_ID,
+            "level": match finding.criticality
+            {
+                Criticality::High => "error",
+                Criticality::Medium => "warning",
+                Criticality::Low => "note",
+            },
+            "message": {
+                "text": format!("{} criticality request (composite score {:.4})", finding.criticality, finding.composite_score),
+            },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": artifact_uri(&finding.source_file) },
+                    "region": {
+                        "startLine": finding.line_number,
+                        "snippet": { "text": finding.line },
+                    },
+                },
+            }],
+            "properties": {
+                "criticality": finding.criticality.as_str(),
+                "compositeScore": finding.composite_score,
+                "matchedPatterns": finding.matched_patterns,
+                "normalizedFields": finding.normalized_fields,
+                "normalizationSteps": finding.normalization_steps,
+                "detectorVersion": finding.detector_version,
+            },
+        }))
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": DETECTOR_NAME,
+                    "version": DETECTOR_VERSION,
+                    "rules": [{
+                        "id": SARIF_RULE_ID,
+                        "name": "MaliciousRequest",
+                        "shortDescription": { "text": "Request similar to known malicious requests" },
+                    }],
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// Writes findings to a writer in a report format.
+///
+/// # Parameters
+/// - `writer`: The writer the report is written to.
+/// - `findings`: The findings to write.
+/// - `format`: The report format.
+/// - `include_header`: Whether to write the header row of a CSV report.
+///
+/// # Returns
+/// A result indicating failure to write.
+pub fn write_findings(writer: &mut dyn Write, findings: &[Finding], format: ReportFormat, include_header: bool) -> io::Result<()>
+{
+    match format
+    {
+        ReportFormat::Text =>
+        {
+            for finding in findings
+            {
+                writeln!(writer, "{}", finding.line)?;
+            }
+        },
+        ReportFormat::Jsonl =>
+        {
+            for finding in findings
+            {
+                writeln!(writer, "{}", finding.to_json())?;
+            }
+        },
+        ReportFormat::Csv =>
+        {
+            if include_header
+            {
+                writeln!(writer, "{}", CSV_HEADER)?;
+            }
+
+            for finding in findings
+            {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{},{}",
+                    csv_field(&finding.source_file),
+                    finding.line_number,
+                    csv_field(&finding.line),
+                    finding.criticality,
+                    finding.composite_score,
+                    csv_list(&finding.matched_patterns),
+                    csv_field(&finding.detector_version),
+                    csv_list(&finding.normalized_fields),
+                    csv_list(&finding.normalization_steps)
+                )?;
+            }
+        },
+        ReportFormat::Sarif =>
+        {
+            serde_json::to_writer_pretty(&mut *writer, &sarif_log(findings))?;
+            writeln!(writer)?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Adds findings to the end of a report file, creating it if needed.
+///
+/// # Summary
+/// The file is opened in append mode, so the existing report is never read or rewritten. A missing
+/// final line break is added first, and the CSV header is written only if the file was empty.
+///
+/// # Parameters
+/// - `findings`: The findings to add.
+/// - `output_file`: The path of the report file.
+/// - `format`: The report format, which has to be appendable.
+///
+/// # Returns
+/// A result indicating failure to write the file, or an error if the format cannot be appended to.
+fn append_findings(findings: &[Finding], output_file: &str, format: ReportFormat) -> io::Result<()>
+{
+    if !format.is_appendable()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} reports cannot be appended to; use the overwrite or rotate output mode", format),
+        ));
+    }
+
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(output_file)?;
+    let length = file.metadata()?.len();
+
+    let mut contents: Vec<u8> = Vec::new();
+    if length > 0
+    {
+        let mut last_byte = [0u8];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last_byte)?;
+        if last_byte[0] != b'\n'
+        {
+            contents.push(b'\n');
+        }
+    }
+
+    // One write, so a reader following the file never sees half a finding from this run
+    write_findings(&mut contents, findings, format, length == 0)?;
+    file.write_all(&contents)?;
+    file.sync_all()
+}
+
+/// Writes findings to a report file.
+///
+/// # Summary
+/// A new report is written to a temporary file renamed over the report file, so an interrupted run never
+/// leaves a truncated report behind. When appending, the findings are added to the end of the existing
+/// file instead.
+///
+/// # Parameters
+/// - `findings`: The findings to save.
+/// - `output_file`: The path of the report file.
+/// - `format`: The report format.
+/// - `mode`: How an existing report file is treated.
+///
+/// # Returns
+/// A result indicating failure to write the file, or an error if a SARIF report is appended to.
+pub fn save_findings(findings: &[Finding], output_file: &str, format: ReportFormat, mode: OutputMode) -> io::Result<()>
+{
+    if mode == OutputMode::Append
+    {
+        append_findings(findings, output_file, format)?;
+    }
+    else
+    {
+        if mode == OutputMode::Rotate
+        {
+            if let Some(rotated_file) = file_ops::rotate_file(output_file)?
+            {
+                info!("Previous report moved to {}", rotated_file);
+            }
+        }
+
+        let mut contents: Vec<u8> = Vec::new();
+        write_findings(&mut contents, findings, format, true)?;
+        file_ops::write_file_atomically(output_file, &contents)?;
+    }
+
+    info!("{} findings saved to {} as {}", findings.len(), output_file, format);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::fs;
+
+    fn finding(source_file: &str) -> Finding
+    {
+        Finding {
+            source_file: source_file.to_string(),
+            line_number: 3,
+            line: "GET /a%2fb,c".to_string(),
+            criticality: Criticality::High,
+            composite_score: 0.75,
+            matched_patterns: vec!["/a/b".to_string(), "c|d".to_string()],
+            normalized_fields: vec!["/a/b,c;x".to_string(), "\"y\"".to_string()],
+            normalization_steps: vec!["percent".to_string(), "path".to_string()],
+            detector_version: DETECTOR_VERSION.to_string(),
+        }
+    }
+
+    fn report(findings: &[Finding], format: ReportFormat) -> String
+    {
+        let mut output = Vec::new();
+        write_findings(&mut output, findings, format, true).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn csv_rows_include_the_normalization()
+    {
+        let output = report(&[finding("access.log")], ReportFormat::Csv);
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[0].ends_with(",normalized_fields,normalization_steps"));
+        // Separators and quotes inside the list elements stay inside their JSON strings
+        assert_eq!(
+            lines[1],
+            format!(
+                r#"access.log,3,"GET /a%2fb,c",High,0.75,"[""/a/b"",""c|d""]",{},"[""/a/b,c;x"",""\""y\""""]","[""percent"",""path""]""#,
+                DETECTOR_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn jsonl_lines_hold_one_finding_each()
+    {
+        let output = report(&[finding("access.log"), finding("other.log")], ReportFormat::Jsonl);
+        let records: Vec<Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["source_file"], "access.log");
+        assert_eq!(records[0]["line_number"], 3);
+        assert_eq!(records[0]["line"], "GET /a%2fb,c");
+        assert_eq!(records[0]["criticality"], "High");
+        assert_eq!(records[0]["composite_score"], 0.75);
+        assert_eq!(records[0]["matched_patterns"], json!(["/a/b", "c|d"]));
+        assert_eq!(records[0]["normalized_fields"], json!(["/a/b,c;x", "\"y\""]));
+        assert_eq!(records[0]["normalization_steps"], json!(["percent", "path"]));
+        assert_eq!(records[0]["detector_version"], DETECTOR_VERSION);
+        assert_eq!(records[1]["source_file"], "other.log");
+    }
+
+    #[test]
+    fn sarif_log_has_one_result_per_finding()
+    {
+        let mut low = finding("logs/access log.txt");
+        low.criticality = Criticality::Low;
+        let log: Value = serde_json::from_str(&report(&[finding("access.log"), low], ReportFormat::Sarif)).unwrap();
+
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["name"], DETECTOR_NAME);
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], SARIF_RULE_ID);
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["ruleId"], SARIF_RULE_ID);
+        assert_eq!(results[0]["level"], "error");
+        assert_eq!(results[1]["level"], "note");
+        assert_eq!(results[0]["properties"]["matchedPatterns"], json!(["/a/b", "c|d"]));
+
+        let location = &results[1]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "logs/access%20log.txt");
+        assert_eq!(location["region"]["startLine"], 3);
+        assert_eq!(location["region"]["snippet"]["text"], "GET /a%2fb,c");
+    }
+
+    #[test]
+    fn artifact_uris_are_uri_references()
+    {
+        assert_eq!(artifact_uri("access.log"), "access.log");
+        assert_eq!(artifact_uri("src/files/logs_to_check.txt"), "src/files/logs_to_check.txt");
+        assert_eq!(artifact_uri("a:b/100%#1?.log"), "a%3Ab/100%25%231%3F.log");
+        assert_eq!(artifact_uri("logs/accès.log"), "logs/acc%C3%A8s.log");
+
+        #[cfg(unix)]
+        assert_eq!(artifact_uri("/var/log/nginx/access log"), "file:///var/log/nginx/access%20log");
+        #[cfg(windows)]
+        assert_eq!(artifact_uri(r"C:\logs\access.log"), "file:///C:/logs/access.log");
+    }
+
+    #[test]
+    fn appending_adds_to_the_end_of_the_report()
+    {
+        let directory = std::env::temp_dir().join(format!("fs-report-append-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let text_file = directory.join("malicious_logs.txt").to_string_lossy().into_owned();
+        fs::write(&text_file, "earlier line").unwrap();
+        save_findings(&[finding("access.log")], &text_file, ReportFormat::Text, OutputMode::Append).unwrap();
+        assert_eq!(fs::read_to_string(&text_file).unwrap(), "earlier line\nGET /a%2fb,c\n");
+
+        let csv_file = directory.join("report.csv").to_string_lossy().into_owned();
+        save_findings(&[finding("access.log")], &csv_file, ReportFormat::Csv, OutputMode::Append).unwrap();
+        save_findings(&[finding("other.log")], &csv_file, ReportFormat::Csv, OutputMode::Append).unwrap();
+        let rows = fs::read_to_string(&csv_file).unwrap();
+        assert_eq!(rows.lines().filter(|row| *row == CSV_HEADER).count(), 1);
+        assert_eq!(rows.lines().count(), 3);
+
+        let jsonl_file = directory.join("report.jsonl").to_string_lossy().into_owned();
+        save_findings(&[finding("access.log")], &jsonl_file, ReportFormat::Jsonl, OutputMode::Append).unwrap();
+        save_findings(&[finding("other.log")], &jsonl_file, ReportFormat::Jsonl, OutputMode::Append).unwrap();
+        assert_eq!(fs::read_to_string(&jsonl_file).unwrap(), report(&[finding("access.log"), finding("other.log")], ReportFormat::Jsonl));
+
+        let sarif_file = directory.join("report.sarif").to_string_lossy().into_owned();
+        let error = save_findings(&[finding("access.log")], &sarif_file, ReportFormat::Sarif, OutputMode::Append).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(!Path::new(&sarif_file).exists());
+    }
+
+    #[test]
+    fn rewriting_replaces_or_rotates_the_report()
+    {
+        let directory = std::env::temp_dir().join(format!("fs-report-rewrite-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let sarif_file = directory.join("report.sarif").to_string_lossy().into_owned();
+        save_findings(&[finding("access.log")], &sarif_file, ReportFormat::Sarif, OutputMode::Overwrite).unwrap();
+        save_findings(&[finding("other.log")], &sarif_file, ReportFormat::Sarif, OutputMode::Overwrite).unwrap();
+        assert_eq!(fs::read_to_string(&sarif_file).unwrap(), report(&[finding("other.log")], ReportFormat::Sarif));
+
+        save_findings(&[], &sarif_file, ReportFormat::Sarif, OutputMode::Rotate).unwrap();
+        assert_eq!(fs::read_to_string(format!("{}.1", sarif_file)).unwrap(), report(&[finding("other.log")], ReportFormat::Sarif));
+        assert_eq!(fs::read_to_string(&sarif_file).unwrap(), report(&[], ReportFormat::Sarif));
+    }
+}
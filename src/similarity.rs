@@ -0,0 +1,559 @@
+/// File: similarity.rs
+///
+/// This file contains the similarity metrics that can be weighted into the composite score next to the
+/// Levenshtein distance and Dice coefficient. Every metric implements `SimilarityMetric` and scores a
+/// pair of strings between 0 (nothing in common) and 1 (identical), so their weights add up like alpha
+/// and beta. The metrics to use and their weights are given as `name=weight` pairs, e.g.
+/// `jaro-winkler=0.2,jaccard:3=0.1`; the n-gram metrics take their n-gram size after a colon.
+/// The file includes the following items:
+///
+/// - `SimilarityMetric`: The trait of the similarity metrics.
+/// - `DamerauLevenshteinMetric`: The Damerau-Levenshtein distance, normalized by the longer string.
+/// - `JaroWinklerMetric`: The Jaro-Winkler similarity.
+/// - `JaccardMetric`: The Jaccard index of the n-gram sets.
+/// - `TfIdfCosineMetric`: The cosine similarity of TF-IDF weighted n-gram vectors.
+/// - `LongestCommonSubstringMetric`: The longest common substring, normalized by the longer string.
+/// - `MetricKind`: The available metrics, as named in the configuration.
+/// - `WeightedMetric`: A metric and its weight in the composite score.
+/// - `parse_weight`: Parses a weight of the composite score, which must be finite and not negative.
+/// - `MetricScore`: The similarity of a request according to one metric.
+///
+/// Modules Required:
+/// - `string_utils`: Provides the distance calculations and n-gram extraction, in a text unit.
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::string_utils::{self, TextUnit};
+
+/// The n-gram size of `jaccard` and `cosine` when none is given.
+const DEFAULT_NGRAM_SIZE: usize = 2;
+
+/// The prefix scale of the Jaro-Winkler similarity, as proposed by Winkler.
+const JARO_WINKLER_PREFIX_SCALE: f64 = 0.1;
+
+/// The trait of the similarity metrics.
+pub trait SimilarityMetric: fmt::Debug + Send + Sync
+{
+    /// Returns the name of the metric, as given in the configuration.
+    fn name(&self) -> String;
+
+    /// Scores the similarity of two strings.
+    ///
+    /// # Parameters
+    /// - `token`: A token of the request.
+    /// - `pattern`: A dictionary entry.
+    ///
+    /// # Returns
+    /// The similarity, between 0 (nothing in common) and 1 (identical).
+    fn similarity(&self, token: &str, pattern: &str) -> f64;
+
+    /// Finds the highest similarity of a token with any of a set of dictionary entries.
+    ///
+    /// # Parameters
+    /// - `token`: A token of the request.
+    /// - `entries`: The dictionary entries to compare with.
+    ///
+    /// # Returns
+    /// The highest similarity, or 0 for no entries.
+    fn best_similarity(&self, token: &str, entries: &[&str]) -> f64
+    {
+        entries.iter().map(|pattern| self.similarity(token, pattern)).fold(0.0, f64::max)
+    }
+
+    /// Returns the highest similarity two strings can have given only their lengths, so the dictionary
+    /// index can skip entries whose length rules them out.
+    ///
+    /// # Parameters
+    /// - `token_length`: The number of text units of the token.
+    /// - `pattern_length`: The number of text units of the dictionary entry.
+    ///
+    /// # Returns
+    /// An upper bound of `similarity`; 1 if the lengths bound nothing.
+    fn upper_bound(&self, _token_length: usize, _pattern_length: usize) -> f64
+    {
+        1.0
+    }
+
+    /// Returns the size of the n-grams a token must share with an entry for a similarity above 0, so the
+    /// dictionary index can compare the token only with the entries sharing one with it.
+    ///
+    /// # Summary
+    /// Strings without any n-gram must be identical to be similar; the bigram index finds those too, as a
+    /// string shorter than a bigram is indexed as a whole.
+    ///
+    /// # Returns
+    /// The n-gram size, or `None` if unrelated strings can be similar.
+    fn required_ngram_size(&self) -> Option<usize>
+    {
+        None
+    }
+}
+
+/// Bounds the similarity of strings normalized by the longer one: at least the difference in length
+/// is not shared.
+fn length_ratio(token_length: usize, pattern_length: usize) -> f64
+{
+    let longest = token_length.max(pattern_length);
+    if longest == 0
+    {
+        return 1.0;
+    }
+    token_length.min(pattern_length) as f64 / longest as f64
+}
+
+/// Turns a distance into a similarity by normalizing it with the length of the longer string.
+fn normalized_similarity(distance: usize, s1: &str, s2: &str, unit: TextUnit) -> f64
+{
+    let longest = string_utils::unit_count(s1, unit).max(string_utils::unit_count(s2, unit));
+    if longest == 0
+    {
+        return 1.0;
+    }
+    1.0 - distance as f64 / longest as f64
+}
+
+/// The Damerau-Levenshtein distance, normalized by the longer string.
+#[derive(Debug, Clone, Copy)]
+pub struct DamerauLevenshteinMetric
+{
+    pub text_unit: TextUnit,
+}
+
+impl SimilarityMetric for DamerauLevenshteinMetric
+{
+    fn name(&self) -> String
+    {
+        MetricKind::DamerauLevenshtein.to_string()
+    }
+
+    fn similarity(&self, token: &str, pattern: &str) -> f64
+    {
+        let distance = string_utils::damerau_levenshtein(token, pattern, self.text_unit);
+        normalized_similarity(distance, token, pattern, self.text_unit)
+    }
+
+    fn upper_bound(&self, token_length: usize, pattern_length: usize) -> f64
+    {
+        // The distance is at least the difference in length
+        length_ratio(token_length, pattern_length)
+    }
+}
+
+/// The Jaro-Winkler similarity.
+#[derive(Debug, Clone, Copy)]
+pub struct JaroWinklerMetric
+{
+    pub text_unit: TextUnit,
+    /// How much a common prefix raises the Jaro similarity.
+    pub prefix_scale: f64,
+}
+
+impl SimilarityMetric for JaroWinklerMetric
+{
+    fn name(&self) -> String
+    {
+        MetricKind::JaroWinkler.to_string()
+    }
+
+    fn similarity(&self, token: &str, pattern: &str) -> f64
+    {
+        string_utils::jaro_winkler(token, pattern, self.prefix_scale, self.text_unit)
+    }
+
+    fn upper_bound(&self, token_length: usize, pattern_length: usize) -> f64
+    {
+        if token_length == 0 || pattern_length == 0
+        {
+            return if token_length == pattern_length { 1.0 } else { 0.0 };
+        }
+
+        // At most every unit of the shorter string matches, without transpositions
+        let jaro = (2.0 + length_ratio(token_length, pattern_length)) / 3.0;
+        let prefix = token_length.min(pattern_length).min(string_utils::JARO_WINKLER_MAX_PREFIX);
+        (jaro + prefix as f64 * self.prefix_scale * (1.0 - jaro)).min(1.0)
+    }
+}
+
+/// The Jaccard index of the n-gram sets.
+#[derive(Debug, Clone, Copy)]
+pub struct JaccardMetric
+{
+    pub text_unit: TextUnit,
+    pub ngram_size: usize,
+}
+
+impl SimilarityMetric for JaccardMetric
+{
+    fn name(&self) -> String
+    {
+        MetricKind::Jaccard(self.ngram_size).to_string()
+    }
+
+    fn similarity(&self, token: &str, pattern: &str) -> f64
+    {
+        string_utils::jaccard_index(token, pattern, self.ngram_size, self.text_unit)
+    }
+
+    fn required_ngram_size(&self) -> Option<usize>
+    {
+        Some(self.ngram_size)
+    }
+}
+
+/// The cosine similarity of TF-IDF weighted n-gram vectors.
+///
+/// # Summary
+/// The inverse document frequencies are taken from the dictionary, so n-grams shared by many entries
+/// (such as `/` followed by a common letter) weigh less than the rare ones that characterize an attack.
+/// N-grams the dictionary does not contain get the weight of an n-gram found in no entry.
+#[derive(Debug, Clone)]
+pub struct TfIdfCosineMetric
+{
+    text_unit: TextUnit,
+    ngram_size: usize,
+    /// The smoothed inverse document frequency of each n-gram of the dictionary.
+    inverse_frequencies: HashMap<Vec<u8>, f64>,
+    /// The inverse document frequency of n-grams found in no entry.
+    unseen_frequency: f64,
+    /// The weights and norm of every dictionary entry, calculated once.
+    entry_vectors: HashMap<String, TfIdfVector>,
+}
+
+/// The TF-IDF weights of the n-grams of a string and their Euclidean norm.
+#[derive(Debug, Clone, Default)]
+struct TfIdfVector
+{
+    weights: HashMap<Vec<u8>, f64>,
+    norm: f64,
+}
+
+impl TfIdfCosineMetric
+{
+    /// Calculates the inverse document frequencies of the n-grams of a dictionary.
+    ///
+    /// # Parameters
+    /// - `entries`: The dictionary entries, each one a document.
+    /// - `ngram_size`: The number of text units in each n-gram.
+    /// - `text_unit`: The text unit the n-grams are made of.
+    ///
+    /// # Returns
+    /// The new `TfIdfCosineMetric`.
+    pub fn new(entries: &[String], ngram_size: usize, text_unit: TextUnit) -> TfIdfCosineMetric
+    {
+        let mut document_frequencies: HashMap<Vec<u8>, usize> = HashMap::new();
+        for entry in entries
+        {
+            let mut ngrams = string_utils::tokenize_by_ngram(entry, ngram_size, text_unit);
+            ngrams.sort_unstable();
+            ngrams.dedup();
+            for ngram in ngrams
+            {
+                *document_frequencies.entry(ngram.to_vec()).or_insert(0) += 1;
+            }
+        }
+
+        let documents = entries.len() as f64;
+        let inverse_frequency = |frequency: usize| ((1.0 + documents) / (1.0 + frequency as f64)).ln() + 1.0;
+
+        let mut metric = TfIdfCosineMetric {
+            text_unit,
+            ngram_size,
+            inverse_frequencies: document_frequencies.into_iter()
+                .map(|(ngram, frequency)| (ngram, inverse_frequency(frequency)))
+                .collect(),
+            unseen_frequency: inverse_frequency(0),
+            entry_vectors: HashMap::new(),
+        };
+
+        let entry_vectors = entries.iter().map(|entry| (entry.clone(), metric.vector(entry))).collect();
+        metric.entry_vectors = entry_vectors;
+        metric
+    }
+
+    /// Calculates the TF-IDF weight of every n-gram of a string.
+    fn vector(&self, string: &str) -> TfIdfVector
+    {
+        let mut weights: HashMap<Vec<u8>, f64> = HashMap::new();
+        for ngram in string_utils::tokenize_by_ngram(string, self.ngram_size, self.text_unit)
+        {
+            *weights.entry(ngram.to_vec()).or_insert(0.0) += 1.0;
+        }
+
+        for (ngram, weight) in weights.iter_mut()
+        {
+            *weight *= self.inverse_frequencies.get(ngram).copied().unwrap_or(self.unseen_frequency);
+        }
+
+        let norm = weights.values().map(|weight| weight * weight).sum::<f64>().sqrt();
+        TfIdfVector { weights, norm }
+    }
+
+    /// Calculates the cosine similarity of a token, given its vector, with a dictionary entry.
+    fn cosine(&self, token: &str, token_vector: &TfIdfVector, pattern: &str) -> f64
+    {
+        let computed_pattern_vector;
+        let pattern_vector = match self.entry_vectors.get(pattern)
+        {
+            Some(vector) => vector,
+            None =>
+            {
+                computed_pattern_vector = self.vector(pattern);
+                &computed_pattern_vector
+            },
+        };
+
+        if token_vector.weights.is_empty() || pattern_vector.weights.is_empty()
+        {
+            return if token == pattern { 1.0 } else { 0.0 };
+        }
+
+        let dot_product: f64 = token_vector.weights.iter()
+            .filter_map(|(ngram, weight)| pattern_vector.weights.get(ngram).map(|other| weight * other))
+            .sum();
+
+        (dot_product / (token_vector.norm * pattern_vector.norm)).min(1.0)
+    }
+}
+
+impl SimilarityMetric for TfIdfCosineMetric
+{
+    fn name(&self) -> String
+    {
+        MetricKind::TfIdfCosine(self.ngram_size).to_string()
+    }
+
+    fn similarity(&self, token: &str, pattern: &str) -> f64
+    {
+        self.cosine(token, &self.vector(token), pattern)
+    }
+
+    fn best_similarity(&self, token: &str, entries: &[&str]) -> f64
+    {
+        // The vector of the token is calculated once for all entries
+        let token_vector = self.vector(token);
+        entries.iter().map(|pattern| self.cosine(token, &token_vector, pattern)).fold(0.0, f64::max)
+    }
+
+    fn required_ngram_size(&self) -> Option<usize>
+    {
+        Some(self.ngram_size)
+    }
+}
+
+/// The longest common substring, normalized by the longer string.
+#[derive(Debug, Clone, Copy)]
+pub struct LongestCommonSubstringMetric
+{
+    pub text_unit: TextUnit,
+}
+
+impl SimilarityMetric for LongestCommonSubstringMetric
+{
+    fn name(&self) -> String
+    {
+        MetricKind::LongestCommonSubstring.to_string()
+    }
+
+    fn similarity(&self, token: &str, pattern: &str) -> f64
+    {
+        let longest = string_utils::unit_count(token, self.text_unit).max(string_utils::unit_count(pattern, self.text_unit));
+        if longest == 0
+        {
+            return 1.0;
+        }
+        string_utils::longest_common_substring(token, pattern, self.text_unit) as f64 / longest as f64
+    }
+
+    fn upper_bound(&self, token_length: usize, pattern_length: usize) -> f64
+    {
+        // The common substring is at most as long as the shorter string
+        length_ratio(token_length, pattern_length)
+    }
+}
+
+/// The available metrics, as named in the configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind
+{
+    DamerauLevenshtein,
+    JaroWinkler,
+    /// The Jaccard index of n-grams of the given size.
+    Jaccard(usize),
+    /// The TF-IDF cosine similarity of n-grams of the given size.
+    TfIdfCosine(usize),
+    LongestCommonSubstring,
+}
+
+impl MetricKind
+{
+    /// Creates the metric.
+    ///
+    /// # Parameters
+    /// - `entries`: The dictionary entries the metric compares tokens with; TF-IDF takes its frequencies from them.
+    /// - `text_unit`: The text unit the metric counts in.
+    ///
+    /// # Returns
+    /// The metric.
+    pub fn build(&self, entries: &[String], text_unit: TextUnit) -> Arc<dyn SimilarityMetric>
+    {
+        match *self
+        {
+            MetricKind::DamerauLevenshtein => Arc::new(DamerauLevenshteinMetric { text_unit }),
+            MetricKind::JaroWinkler => Arc::new(JaroWinklerMetric { text_unit, prefix_scale: JARO_WINKLER_PREFIX_SCALE }),
+            MetricKind::Jaccard(ngram_size) => Arc::new(JaccardMetric { text_unit, ngram_size }),
+            MetricKind::TfIdfCosine(ngram_size) => Arc::new(TfIdfCosineMetric::new(entries, ngram_size, text_unit)),
+            MetricKind::LongestCommonSubstring => Arc::new(LongestCommonSubstringMetric { text_unit }),
+        }
+    }
+}
+
+impl fmt::Display for MetricKind
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            MetricKind::DamerauLevenshtein => write!(f, "damerau-levenshtein"),
+            MetricKind::JaroWinkler => write!(f, "jaro-winkler"),
+            MetricKind::Jaccard(ngram_size) => write!(f, "jaccard:{}", ngram_size),
+            MetricKind::TfIdfCosine(ngram_size) => write!(f, "cosine:{}", ngram_size),
+            MetricKind::LongestCommonSubstring => write!(f, "lcs"),
+        }
+    }
+}
+
+impl FromStr for MetricKind
+{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        let (name, ngram_size) = match name.trim().split_once(':')
+        {
+            Some((name, size)) => match size.trim().parse::<usize>()
+            {
+                Ok(size) if size > 0 => (name, Some(size)),
+                _ => return Err(format!("invalid n-gram size '{}' (expected a positive integer)", size)),
+            },
+            None => (name.trim(), None),
+        };
+
+        let kind = match name.to_ascii_lowercase().as_str()
+        {
+            "damerau-levenshtein" | "damerau" => MetricKind::DamerauLevenshtein,
+            "jaro-winkler" | "jaro" => MetricKind::JaroWinkler,
+            "jaccard" => MetricKind::Jaccard(ngram_size.unwrap_or(DEFAULT_NGRAM_SIZE)),
+            "cosine" | "tf-idf" | "tfidf" => MetricKind::TfIdfCosine(ngram_size.unwrap_or(DEFAULT_NGRAM_SIZE)),
+            "lcs" | "longest-common-substring" => MetricKind::LongestCommonSubstring,
+            _ => return Err(format!("unknown metric '{}' (expected damerau-levenshtein, jaro-winkler, jaccard, cosine or lcs)", name)),
+        };
+
+        if ngram_size.is_some() && !matches!(kind, MetricKind::Jaccard(_) | MetricKind::TfIdfCosine(_))
+        {
+            return Err(format!("metric '{}' takes no n-gram size", name));
+        }
+        Ok(kind)
+    }
+}
+
+/// A metric and its weight in the composite score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeightedMetric
+{
+    pub kind: MetricKind,
+    pub weight: f64,
+}
+
+impl fmt::Display for WeightedMetric
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}={}", self.kind, self.weight)
+    }
+}
+
+impl FromStr for WeightedMetric
+{
+    type Err = String;
+
+    /// Parses `name=weight`, e.g. `jaccard:3=0.2`.
+    fn from_str(spec: &str) -> Result<Self, Self::Err>
+    {
+        let (name, weight) = spec.split_once('=')
+            .ok_or_else(|| format!("expected `metric=weight`, got '{}'", spec))?;
+        let weight = parse_weight(weight).map_err(|e| format!("metric '{}': {}", name.trim(), e))?;
+
+        Ok(WeightedMetric { kind: name.parse()?, weight })
+    }
+}
+
+/// Parses a weight of the composite score.
+///
+/// # Parameters
+/// - `text`: The weight, e.g. `0.2`.
+///
+/// # Returns
+/// The weight, or an error if it is not a number, not finite (NaN or infinity) or negative.
+pub fn parse_weight(text: &str) -> Result<f64, String>
+{
+    let text = text.trim();
+    match text.parse::<f64>()
+    {
+        Ok(weight) if weight.is_finite() && weight >= 0.0 => Ok(weight),
+        Ok(_) => Err(format!("weight '{}' must be a finite number of at least 0", text)),
+        Err(_) => Err(format!("invalid weight '{}'", text)),
+    }
+}
+
+/// Parses a comma-separated list of weighted metrics, e.g. `jaro-winkler=0.2,cosine:3=0.1`.
+///
+/// # Parameters
+/// - `list`: The list, as given in the configuration file.
+///
+/// # Returns
+/// A result containing the weighted metrics, or a message describing the first invalid one.
+pub fn parse_weighted_metrics(list: &str) -> Result<Vec<WeightedMetric>, String>
+{
+    list.split(',')
+        .filter(|spec| !spec.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// The similarity of a request according to one metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricScore
+{
+    pub name: String,
+    pub weight: f64,
+    /// Average, over the tokens of the request, of the highest similarity with a dictionary entry.
+    pub similarity: f64,
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parses_weighted_metrics()
+    {
+        let metric: WeightedMetric = "jaccard:3=0.2".parse().unwrap();
+        assert_eq!(metric.kind, MetricKind::Jaccard(3));
+        assert_eq!(metric.weight, 0.2);
+        assert_eq!("lcs = 0".parse::<WeightedMetric>().unwrap().weight, 0.0);
+    }
+
+    #[test]
+    fn rejects_weights_that_are_not_finite_or_negative()
+    {
+        for weight in ["NaN", "nan", "inf", "-inf", "infinity", "-0.1", "abc", ""]
+        {
+            assert!(parse_weight(weight).is_err(), "{:?}", weight);
+            assert!(format!("lcs={}", weight).parse::<WeightedMetric>().is_err(), "{:?}", weight);
+        }
+        assert_eq!(parse_weight(" 1.5 "), Ok(1.5));
+    }
+}
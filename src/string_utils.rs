@@ -2,27 +2,132 @@
 ///
 /// This file contains functions for various string manipulation utilities including substring extraction,
 /// tokenization, replacement, and similarity calculations such as Levenshtein distance and Dice coefficient.
+/// N-grams, delimiter splitting and the similarity measures count in a `TextUnit` chosen by the caller,
+/// so multi-byte input is measured consistently.
 /// The file includes the following functions:
 ///
+/// - `TextUnit`: The units strings are split and compared in.
+/// - `text_units`: Splits a string into its text units.
+/// - `unit_count`: Counts the text units of a string.
 /// - `get_char_at`: Returns a character at a given index from a string.
 /// - `substring`: Returns a substring from a given string, starting at a specified position with a specified length.
 /// - `contains_substring`: Checks if a substring exists within a string.
-/// - `tokenize_by_ngram`: Tokenizes a string into n-grams.
+/// - `tokenize_by_ngram`: Tokenizes a string into n-grams of text units.
 /// - `extract_trigrams`: Extracts trigrams from a string.
 /// - `extract_bigrams`: Extracts bigrams from a string.
 /// - `extract_unigrams`: Extracts unigrams from a string.
 /// - `replace_substring`: Replaces a specified substring within a string with another substring.
 /// - `tokenize_by_delimiter`: Tokenizes a string by a delimiter string.
 /// - `contains_any_substring`: Checks if any substring in an array exists in a given string.
-/// - `split_by_multiple_delimiters`: Splits a string by multiple delimiters.
+/// - `split_by_multiple_delimiters`: Splits a string by multiple delimiters at text unit boundaries.
+/// - `DecodeOptions`, `DecodedString`: The options and outcome of the percent decoder.
+/// - `percent_decode`: Percent-decodes a string, repeatedly, until it no longer changes.
 /// - `process_decoded_string`: Processes a URL encoded string for decoding or removal of encoded parts.
 /// - `url_decode`: Decodes URL encoded parts of the string.
 /// - `url_remove`: Removes URL encoded parts of the string.
 /// - `levenshtein`: Calculates the Levenshtein distance between two strings.
+/// - `BigramProfile`: The bigram counts of a string, compared by the multiset Dice coefficient.
 /// - `dice_coefficient`: Calculates the Dice coefficient between two strings.
+/// - `damerau_levenshtein`: Calculates the Damerau-Levenshtein distance between two strings.
+/// - `jaro_winkler`: Calculates the Jaro-Winkler similarity between two strings.
+/// - `jaccard_index`: Calculates the Jaccard index of the n-gram sets of two strings.
+/// - `longest_common_substring`: Calculates the length of the longest common substring of two strings.
 ///
 /// Author: Lazar Marinkovic
 /// Date: July 7th, 2024
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The units strings are split and compared in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TextUnit
+{
+    /// Bytes of the UTF-8 encoding; a multi-byte character counts once per byte.
+    Byte,
+    /// Unicode scalar values (Rust `char`s).
+    #[default]
+    Char,
+    /// Extended grapheme clusters, the characters a reader perceives (e.g. a letter and its combining accent).
+    Grapheme,
+}
+
+impl fmt::Display for TextUnit
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let name = match self
+        {
+            TextUnit::Byte => "byte",
+            TextUnit::Char => "char",
+            TextUnit::Grapheme => "grapheme",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for TextUnit
+{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        match name.to_ascii_lowercase().as_str()
+        {
+            "byte" | "bytes" => Ok(TextUnit::Byte),
+            "char" | "chars" | "scalar" => Ok(TextUnit::Char),
+            "grapheme" | "graphemes" => Ok(TextUnit::Grapheme),
+            _ => Err(format!("unknown text unit '{}' (expected byte, char or grapheme)", name)),
+        }
+    }
+}
+
+/// Returns the byte offsets at which the text units of a string start, followed by the length of the string.
+fn unit_boundaries(string: &str, unit: TextUnit) -> Vec<usize>
+{
+    let mut boundaries: Vec<usize> = match unit
+    {
+        TextUnit::Byte => (0..string.len()).collect(),
+        TextUnit::Char => string.char_indices().map(|(index, _)| index).collect(),
+        TextUnit::Grapheme => string.grapheme_indices(true).map(|(index, _)| index).collect(),
+    };
+    boundaries.push(string.len());
+    boundaries
+}
+
+/// Splits a string into its text units.
+///
+/// # Parameters
+/// - `string`: The input string.
+/// - `unit`: The text unit.
+///
+/// # Returns
+/// The bytes of each unit, in order.
+pub fn text_units(string: &str, unit: TextUnit) -> Vec<&[u8]>
+{
+    let bytes = string.as_bytes();
+    unit_boundaries(string, unit).windows(2).map(|window| &bytes[window[0]..window[1]]).collect()
+}
+
+/// Counts the text units of a string.
+///
+/// # Parameters
+/// - `string`: The input string.
+/// - `unit`: The text unit.
+///
+/// # Returns
+/// The number of units.
+pub fn unit_count(string: &str, unit: TextUnit) -> usize
+{
+    match unit
+    {
+        TextUnit::Byte => string.len(),
+        TextUnit::Char => string.chars().count(),
+        TextUnit::Grapheme => string.graphemes(true).count(),
+    }
+}
 
 /// Returns a character at a given index from a string.
 ///
@@ -32,6 +137,7 @@
 ///
 /// # Returns
 /// The character at the specified index.
+#[allow(dead_code)]
 pub fn get_char_at(string: &str, index: usize) -> char
 {
     string.chars().nth(index).unwrap()
@@ -46,6 +152,7 @@ pub fn get_char_at(string: &str, index: usize) -> char
 ///
 /// # Returns
 /// The substring.
+#[allow(dead_code)]
 pub fn substring(string: &str, start: usize, length: usize) -> String
 {
     string.chars().skip(start).take(length).collect()
@@ -59,71 +166,73 @@ pub fn substring(string: &str, start: usize, length: usize) -> String
 ///
 /// # Returns
 /// A boolean indicating whether the substring exists within the string.
+#[allow(dead_code)]
 pub fn contains_substring(string: &str, substring: &str) -> bool
 {
     string.contains(substring)
 }
 
-/// Tokenizes a string into n-grams.
+/// Tokenizes a string into n-grams of text units.
 ///
 /// # Parameters
 /// - `string`: The input string.
-/// - `ngram_size`: The size of the n-grams.
+/// - `ngram_size`: The number of text units in each n-gram.
+/// - `unit`: The text unit.
 ///
 /// # Returns
-/// A vector of n-grams.
-pub fn tokenize_by_ngram(string: &str, ngram_size: usize) -> Vec<String>
+/// A vector of n-grams, as the bytes of the string they span; with byte units they may split a character.
+pub fn tokenize_by_ngram(string: &str, ngram_size: usize, unit: TextUnit) -> Vec<&[u8]>
 {
-    let mut tokens = Vec::new();
-    let length = string.chars().count();
-
-    if ngram_size > length
+    if ngram_size == 0
     {
-        return tokens;
+        return Vec::new();
     }
 
-    for i in 0..=length - ngram_size
-    {
-        tokens.push(substring(string, i, ngram_size));
-    }
-    
-    tokens
+    let bytes = string.as_bytes();
+    unit_boundaries(string, unit).windows(ngram_size + 1)
+        .map(|window| &bytes[window[0]..window[ngram_size]])
+        .collect()
 }
 
 /// Extracts trigrams from a string.
 ///
 /// # Parameters
 /// - `string`: The input string.
+/// - `unit`: The text unit.
 ///
 /// # Returns
 /// A vector of trigrams.
-pub fn extract_trigrams(string: &str) -> Vec<String>
+#[allow(dead_code)]
+pub fn extract_trigrams(string: &str, unit: TextUnit) -> Vec<&[u8]>
 {
-    tokenize_by_ngram(string, 3)
+    tokenize_by_ngram(string, 3, unit)
 }
 
 /// Extracts bigrams from a string.
 ///
 /// # Parameters
 /// - `string`: The input string.
+/// - `unit`: The text unit.
 ///
 /// # Returns
 /// A vector of bigrams.
-pub fn extract_bigrams(string: &str) -> Vec<String>
+pub fn extract_bigrams(string: &str, unit: TextUnit) -> Vec<&[u8]>
 {
-    tokenize_by_ngram(string, 2)
+    tokenize_by_ngram(string, 2, unit)
 }
 
 /// Extracts unigrams from a string.
 ///
 /// # Parameters
 /// - `string`: The input string.
+/// - `unit`: The text unit.
 ///
 /// # Returns
 /// A vector of unigrams.
-pub fn extract_unigrams(string: &str) -> Vec<String>
+#[allow(dead_code)]
+pub fn extract_unigrams(string: &str, unit: TextUnit) -> Vec<&[u8]>
 {
-    tokenize_by_ngram(string, 1)
+    tokenize_by_ngram(string, 1, unit)
 }
 
 /// Replaces a specified substring within a string with another substring.
@@ -135,6 +244,7 @@ pub fn extract_unigrams(string: &str) -> Vec<String>
 ///
 /// # Returns
 /// The resulting string after replacement.
+#[allow(dead_code)]
 pub fn replace_substring(source: &str, search: &str, replace: &str) -> String
 {
     source.replace(search, replace)
@@ -148,6 +258,7 @@ pub fn replace_substring(source: &str, search: &str, replace: &str) -> String
 ///
 /// # Returns
 /// A vector of tokens.
+#[allow(dead_code)]
 pub fn tokenize_by_delimiter(source: &str, delimiter: &str) -> Vec<String>
 {
     source.split(delimiter).map(|s| s.to_string()).collect()
@@ -161,6 +272,7 @@ pub fn tokenize_by_delimiter(source: &str, delimiter: &str) -> Vec<String>
 ///
 /// # Returns
 /// The index of the found substring in the array, or -1 if not found.
+#[allow(dead_code)]
 pub fn contains_any_substring(source: &str, substrings: &[String]) -> i32
 {
     for (index, substring) in substrings.iter().enumerate()
@@ -176,56 +288,258 @@ pub fn contains_any_substring(source: &str, substrings: &[String]) -> i32
 
 /// Splits a string by multiple delimiters.
 ///
+/// # Summary
+/// A delimiter only matches where it starts and ends on text unit boundaries, so with grapheme units a
+/// delimiter does not split a character from its combining marks. When several delimiters match at the
+/// same position, the first one in the list is used; empty delimiters are ignored.
+///
 /// # Parameters
 /// - `source`: The source string.
 /// - `delimiters`: A vector of delimiter strings.
+/// - `unit`: The text unit.
 ///
 /// # Returns
-/// A vector of tokens.
-pub fn split_by_multiple_delimiters(source: &str, delimiters: &[String]) -> Vec<String>
+/// A vector of the non-empty tokens between the delimiters.
+pub fn split_by_multiple_delimiters(source: &str, delimiters: &[String], unit: TextUnit) -> Vec<String>
 {
+    let boundaries = unit_boundaries(source, unit);
     let mut tokens = Vec::new();
-    let mut current_token = String::new();
-    let length = source.chars().count();
-    let mut i = 0;
+    let mut token_start = 0;
+    let mut k = 0;
 
-    while i < length
+    while k + 1 < boundaries.len()
     {
-        let mut matched = false;
+        let position = boundaries[k];
+        // A delimiter is valid UTF-8, so it only matches at character boundaries, even with byte units
+        let matched_end = delimiters.iter()
+            .filter(|delimiter| !delimiter.is_empty() && source.as_bytes()[position..].starts_with(delimiter.as_bytes()))
+            .find_map(|delimiter| boundaries.binary_search(&(position + delimiter.len())).ok());
 
-        for delimiter in delimiters
+        match matched_end
         {
-            if source[i..].starts_with(delimiter)
+            Some(end) =>
             {
-                if !current_token.is_empty()
+                if token_start < position
                 {
-                    tokens.push(current_token.clone());
-                    current_token.clear();
+                    tokens.push(source[token_start..position].to_string());
                 }
+                k = end;
+                token_start = boundaries[end];
+            },
+            None => k += 1,
+        }
+    }
 
-                i += delimiter.chars().count();
-                matched = true;
-                break;
-            }
+    if token_start < source.len()
+    {
+        tokens.push(source[token_start..].to_string());
+    }
+
+    tokens
+}
+
+/// Options of the percent decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions
+{
+    /// Whether `+` decodes to a space, as in form-encoded query strings; only a `+` of the input does,
+    /// not one produced by decoding `%2B`.
+    pub plus_as_space: bool,
+    /// Whether IIS-style `%uXXXX` escapes (UTF-16 code units) are decoded.
+    pub iis_unicode: bool,
+    /// The maximum number of decoding passes; each pass undoes one level of encoding.
+    pub max_passes: usize,
+}
+
+impl Default for DecodeOptions
+{
+    fn default() -> Self
+    {
+        DecodeOptions { plus_as_space: true, iis_unicode: true, max_passes: 3 }
+    }
+}
+
+/// The outcome of percent-decoding a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedString
+{
+    pub text: String,
+    /// The number of passes that changed the string; more than 1 means the input was encoded several times.
+    pub passes: usize,
+    /// The number of `%` signs in the input that do not start a valid escape; they are kept as they are.
+    pub malformed_escapes: usize,
+    /// Whether the decoded bytes were not valid UTF-8 (e.g. overlong encodings); invalid sequences are replaced by U+FFFD.
+    pub invalid_utf8: bool,
+}
+
+/// One decoding pass over a string.
+struct DecodePass
+{
+    text: String,
+    malformed_escapes: usize,
+    invalid_utf8: bool,
+}
+
+/// A percent escape: a byte (`%XX`) or a UTF-16 code unit (`%uXXXX`).
+enum Escape
+{
+    Byte(u8),
+    CodeUnit(u16),
+}
+
+/// Parses the hexadecimal digits of an escape.
+fn parse_hex(digits: &[u8]) -> Option<u32>
+{
+    digits.iter().try_fold(0, |value, &digit| Some(value * 16 + (digit as char).to_digit(16)?))
+}
+
+/// Parses the escape starting with the `%` at an offset.
+///
+/// # Returns
+/// The escape and its length in bytes, or `None` if the `%` does not start a valid escape.
+fn parse_escape(bytes: &[u8], offset: usize, iis_unicode: bool) -> Option<(Escape, usize)>
+{
+    let rest = &bytes[offset + 1..];
+
+    if iis_unicode && matches!(rest.first(), Some(b'u' | b'U'))
+    {
+        if let Some(code_unit) = rest.get(1..5).and_then(parse_hex)
+        {
+            return Some((Escape::CodeUnit(code_unit as u16), 6));
         }
+    }
+
+    let byte = rest.get(0..2).and_then(parse_hex)?;
+    Some((Escape::Byte(byte as u8), 3))
+}
 
-        if !matched
+/// Decodes the escapes of a string once.
+///
+/// # Summary
+/// The escapes are decoded to bytes, and the bytes are validated as UTF-8 at the end, so multi-byte
+/// characters encoded as several `%XX` escapes are assembled. `%uXXXX` escapes are UTF-16 code units; a
+/// surrogate pair is combined into one character, and a lone surrogate becomes U+FFFD.
+fn decode_pass(source: &str, options: &DecodeOptions) -> DecodePass
+{
+    let bytes = source.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut malformed_escapes = 0;
+    let mut lone_surrogates = false;
+    let mut i = 0;
+
+    while i < bytes.len()
+    {
+        match bytes[i]
         {
-            current_token.push(get_char_at(source, i));
-            i += 1;
+            b'%' => match parse_escape(bytes, i, options.iis_unicode)
+            {
+                Some((escape, length)) =>
+                {
+                    i += length;
+                    match escape
+                    {
+                        Escape::Byte(byte) => decoded.push(byte),
+                        Escape::CodeUnit(code_unit) =>
+                        {
+                            let mut code_units = vec![code_unit];
+                            // A high surrogate is combined with the low surrogate escaped right after it
+                            if (0xD800..0xDC00).contains(&code_unit) && bytes.get(i) == Some(&b'%')
+                            {
+                                if let Some((Escape::CodeUnit(low @ 0xDC00..=0xDFFF), length)) = parse_escape(bytes, i, true)
+                                {
+                                    code_units.push(low);
+                                    i += length;
+                                }
+                            }
+
+                            for character in char::decode_utf16(code_units)
+                            {
+                                let character = character.unwrap_or_else(|_| {
+                                    lone_surrogates = true;
+                                    char::REPLACEMENT_CHARACTER
+                                });
+                                decoded.extend_from_slice(character.encode_utf8(&mut [0; 4]).as_bytes());
+                            }
+                        },
+                    }
+                },
+                None =>
+                {
+                    malformed_escapes += 1;
+                    decoded.push(b'%');
+                    i += 1;
+                },
+            },
+            b'+' if options.plus_as_space =>
+            {
+                decoded.push(b' ');
+                i += 1;
+            },
+            byte =>
+            {
+                decoded.push(byte);
+                i += 1;
+            },
         }
     }
 
-    if !current_token.is_empty()
+    match String::from_utf8(decoded)
     {
-        tokens.push(current_token);
+        Ok(text) => DecodePass { text, malformed_escapes, invalid_utf8: lone_surrogates },
+        Err(e) => DecodePass {
+            text: String::from_utf8_lossy(e.as_bytes()).into_owned(),
+            malformed_escapes,
+            invalid_utf8: true,
+        },
     }
+}
 
-    tokens
+/// Percent-decodes a string, repeatedly, until it no longer changes.
+///
+/// # Summary
+/// Each pass undoes one level of encoding, so a double-encoded `%252e` decodes to `.` in two passes
+/// instead of stopping at `%2e`. A `+` decodes to a space in the first pass only, so the `+` an escaped
+/// `%2B` decodes to is kept. Decoding never fails: malformed escapes such as a trailing `%` or `%zz` are
+/// kept as they are, and invalid UTF-8 is replaced.
+///
+/// # Parameters
+/// - `source`: The source string.
+/// - `options`: Whether `+` and `%uXXXX` are decoded, and the maximum number of passes.
+///
+/// # Returns
+/// The decoded string, the number of passes that changed it, and what was malformed in it.
+pub fn percent_decode(source: &str, options: &DecodeOptions) -> DecodedString
+{
+    let mut result = DecodedString { text: source.to_string(), passes: 0, malformed_escapes: 0, invalid_utf8: false };
+
+    while result.passes < options.max_passes
+    {
+        // Every `+` of the input is decoded by the first pass; a later `+` was decoded from `%2B`
+        let pass_options = DecodeOptions { plus_as_space: options.plus_as_space && result.passes == 0, ..*options };
+        let pass = decode_pass(&result.text, &pass_options);
+        if result.passes == 0
+        {
+            result.malformed_escapes = pass.malformed_escapes;
+        }
+        if pass.text == result.text
+        {
+            break;
+        }
+
+        result.text = pass.text;
+        result.invalid_utf8 |= pass.invalid_utf8;
+        result.passes += 1;
+    }
+
+    result
 }
 
 /// Processes a URL encoded string for decoding or removal of encoded parts.
 ///
+/// # Summary
+/// A single pass over `%XX` escapes; malformed escapes are kept as they are (see `percent_decode` for
+/// the full decoder).
+///
 /// # Parameters
 /// - `source`: The source string.
 /// - `operation`: The operation to perform (0 for decode, 1 for remove).
@@ -234,42 +548,52 @@ pub fn split_by_multiple_delimiters(source: &str, delimiters: &[String]) -> Vec<
 /// The processed string.
 pub fn process_decoded_string(source: &str, operation: i32) -> String
 {
-    let mut result = String::new();
-    let length = source.chars().count();
+    if operation == 1
+    {
+        return remove_escapes(source);
+    }
+
+    let options = DecodeOptions { plus_as_space: false, iis_unicode: false, max_passes: 1 };
+    decode_pass(source, &options).text
+}
+
+/// Removes the `%XX` escapes of a string; malformed escapes are kept as they are.
+fn remove_escapes(source: &str) -> String
+{
+    let bytes = source.as_bytes();
+    let mut kept: Vec<u8> = Vec::with_capacity(bytes.len());
     let mut i = 0;
 
-    while i < length
+    while i < bytes.len()
     {
-        if get_char_at(source, i) == '%'
+        if bytes[i] == b'%'
         {
-            if operation == 0
+            if let Some((_, length)) = parse_escape(bytes, i, false)
             {
-                let decoded_char = u8::from_str_radix(&source[i+1..i+3], 16).unwrap() as char;
-                result.push(decoded_char);
+                i += length;
+                continue;
             }
-            
-            i += 3;
-        }
-        else
-        {
-            result.push(get_char_at(source, i));
-            i += 1;
         }
+
+        kept.push(bytes[i]);
+        i += 1;
     }
 
-    result
+    // Only ASCII escapes are removed, so the rest is still valid UTF-8
+    String::from_utf8_lossy(&kept).into_owned()
 }
 
-/// Decodes URL encoded parts of the string.
+/// Decodes URL encoded parts of the string, including `+`, `%uXXXX` and multiple levels of encoding.
 ///
 /// # Parameters
 /// - `source`: The source string.
 ///
 /// # Returns
 /// The decoded string.
+#[allow(dead_code)]
 pub fn url_decode(source: &str) -> String
 {
-    process_decoded_string(source, 0)
+    percent_decode(source, &DecodeOptions::default()).text
 }
 
 /// Removes URL encoded parts of the string.
@@ -279,74 +603,644 @@ pub fn url_decode(source: &str) -> String
 ///
 /// # Returns
 /// The string with URL encoded parts removed.
+#[allow(dead_code)]
 pub fn url_remove(source: &str) -> String
 {
     process_decoded_string(source, 1)
 }
 
+/// Calculates the edit distance between two sequences with a single row of costs.
+fn edit_distance<T: PartialEq>(s1: &[T], s2: &[T]) -> usize
+{
+    if s1.is_empty()
+    {
+        return s2.len();
+    }
+
+    if s2.is_empty()
+    {
+        return s1.len();
+    }
+
+    let mut prev_costs: Vec<usize> = (0..=s1.len()).collect();
+    let mut curr_costs = vec![0; s1.len() + 1];
+
+    for (i, s2_unit) in s2.iter().enumerate()
+    {
+        curr_costs[0] = i + 1;
+
+        for (j, s1_unit) in s1.iter().enumerate()
+        {
+            let cost = if s1_unit == s2_unit { 0 } else { 1 };
+            curr_costs[j + 1] = (curr_costs[j] + 1)
+                .min(prev_costs[j + 1] + 1)
+                .min(prev_costs[j] + cost);
+        }
+
+        std::mem::swap(&mut prev_costs, &mut curr_costs);
+    }
+
+    prev_costs[s1.len()]
+}
+
 /// Calculates the Levenshtein distance between two strings.
 ///
 /// # Parameters
 /// - `s1`: The first string.
 /// - `s2`: The second string.
+/// - `unit`: The text unit insertions, deletions and substitutions apply to.
 ///
 /// # Returns
-/// The Levenshtein distance.
-pub fn levenshtein(s1: &str, s2: &str) -> usize
+/// The Levenshtein distance, in text units.
+pub fn levenshtein(s1: &str, s2: &str, unit: TextUnit) -> usize
 {
     if s1 == s2
     {
         return 0;
     }
 
-    if s1.is_empty()
+    match unit
     {
-        return s2.chars().count();
+        TextUnit::Byte => edit_distance(s1.as_bytes(), s2.as_bytes()),
+        // Every ASCII character is one byte, which spares decoding the common case
+        TextUnit::Char if s1.is_ascii() && s2.is_ascii() => edit_distance(s1.as_bytes(), s2.as_bytes()),
+        TextUnit::Char => edit_distance(&s1.chars().collect::<Vec<char>>(), &s2.chars().collect::<Vec<char>>()),
+        TextUnit::Grapheme => edit_distance(&s1.graphemes(true).collect::<Vec<&str>>(), &s2.graphemes(true).collect::<Vec<&str>>()),
     }
+}
 
-    if s2.is_empty()
+/// The bigram counts of a string, compared with other profiles by the Dice coefficient.
+///
+/// # Summary
+/// Bigrams are counted with their repetitions, so the coefficient is the multiset Dice coefficient and
+/// never exceeds 1. A string with fewer than two text units has no bigram; its whole text stands in as
+/// its only gram, so two such strings score 1 when they are equal and 0 otherwise, and such a string
+/// scores 0 against any longer one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BigramProfile
+{
+    counts: HashMap<Vec<u8>, usize>,
+    total: usize,
+}
+
+impl BigramProfile
+{
+    /// Counts the bigrams of a string.
+    ///
+    /// # Parameters
+    /// - `string`: The input string.
+    /// - `unit`: The text unit the bigrams are made of.
+    ///
+    /// # Returns
+    /// The new `BigramProfile`.
+    pub fn new(string: &str, unit: TextUnit) -> BigramProfile
+    {
+        let mut grams = extract_bigrams(string, unit);
+        if grams.is_empty()
+        {
+            grams.push(string.as_bytes());
+        }
+
+        let mut counts: HashMap<Vec<u8>, usize> = HashMap::with_capacity(grams.len());
+        for gram in &grams
+        {
+            *counts.entry(gram.to_vec()).or_insert(0) += 1;
+        }
+
+        BigramProfile { counts, total: grams.len() }
+    }
+
+    /// Returns the number of grams, with repetitions.
+    pub fn total(&self) -> usize
     {
-        return s1.chars().count();
+        self.total
     }
 
-    let mut prev_costs: Vec<usize> = (0..=s1.len()).collect();
-    let mut curr_costs = vec![0; s1.len() + 1];
+    /// Returns the distinct grams and their counts.
+    pub fn counts(&self) -> impl Iterator<Item = (&[u8], usize)>
+    {
+        self.counts.iter().map(|(gram, count)| (gram.as_slice(), *count))
+    }
 
-    for (i, s2_char) in s2.chars().enumerate()
+    /// Returns how many times a gram occurs.
+    pub fn count(&self, gram: &[u8]) -> usize
     {
-        curr_costs[0] = i + 1;
+        self.counts.get(gram).copied().unwrap_or(0)
+    }
+
+    /// Calculates the Dice coefficient with another profile, in time linear in the smaller profile.
+    ///
+    /// # Parameters
+    /// - `other`: The profile to compare with.
+    ///
+    /// # Returns
+    /// The multiset Dice coefficient, between 0 and 1.
+    #[allow(dead_code)]
+    pub fn dice_coefficient(&self, other: &BigramProfile) -> f64
+    {
+        let (smaller, larger) = if self.counts.len() <= other.counts.len() { (self, other) } else { (other, self) };
+
+        let intersection_count: usize = smaller.counts()
+            .map(|(gram, count)| count.min(larger.count(gram)))
+            .sum();
+
+        (2 * intersection_count) as f64 / (self.total + other.total) as f64
+    }
+}
+
+/// Calculates the Dice coefficient between two strings.
+///
+/// # Parameters
+/// - `s1`: The first string.
+/// - `s2`: The second string.
+/// - `unit`: The text unit the bigrams are made of.
+///
+/// # Returns
+/// The multiset Dice coefficient of the bigram profiles, between 0 and 1 (see `BigramProfile` for strings
+/// shorter than two units).
+#[allow(dead_code)]
+pub fn dice_coefficient(s1: &str, s2: &str, unit: TextUnit) -> f64
+{
+    BigramProfile::new(s1, unit).dice_coefficient(&BigramProfile::new(s2, unit))
+}
+
+/// Calculates the Damerau-Levenshtein distance between two strings.
+///
+/// # Summary
+/// Unlike the optimal string alignment distance, a transposed pair may be edited again afterwards,
+/// so the result is a metric (Lowrance-Wagner algorithm).
+///
+/// # Parameters
+/// - `s1`: The first string.
+/// - `s2`: The second string.
+/// - `unit`: The text unit insertions, deletions, substitutions and transpositions apply to.
+///
+/// # Returns
+/// The Damerau-Levenshtein distance, in text units.
+pub fn damerau_levenshtein(s1: &str, s2: &str, unit: TextUnit) -> usize
+{
+    let s1_units = text_units(s1, unit);
+    let s2_units = text_units(s2, unit);
+    let (len1, len2) = (s1_units.len(), s2_units.len());
+    let infinity = len1 + len2;
+
+    // Row and column 0 hold the sentinel `infinity`, row and column 1 the distances to the empty string
+    let width = len2 + 2;
+    let mut distances = vec![0; (len1 + 2) * width];
+    distances[0] = infinity;
+    for i in 0..=len1
+    {
+        distances[(i + 1) * width] = infinity;
+        distances[(i + 1) * width + 1] = i;
+    }
+    for j in 0..=len2
+    {
+        distances[j + 1] = infinity;
+        distances[width + j + 1] = j;
+    }
 
-        for (j, s1_char) in s1.chars().enumerate()
+    // The last row each unit of `s1` was seen in
+    let mut last_row: HashMap<&[u8], usize> = HashMap::new();
+
+    for i in 1..=len1
+    {
+        // The last column of the current row whose units matched
+        let mut last_match_column = 0;
+
+        for j in 1..=len2
         {
-            let cost = if s1_char == s2_char { 0 } else { 1 };
-            curr_costs[j + 1] = *[
-                curr_costs[j] + 1,
-                prev_costs[j + 1] + 1,
-                prev_costs[j] + cost,
-            ].iter().min().unwrap();
+            let k = last_row.get(s2_units[j - 1]).copied().unwrap_or(0);
+            let l = last_match_column;
+            let cost = if s1_units[i - 1] == s2_units[j - 1]
+            {
+                last_match_column = j;
+                0
+            }
+            else
+            {
+                1
+            };
+
+            distances[(i + 1) * width + j + 1] = (distances[i * width + j] + cost)
+                .min(distances[(i + 1) * width + j] + 1)
+                .min(distances[i * width + j + 1] + 1)
+                .min(distances[k * width + l] + (i - k - 1) + 1 + (j - l - 1));
         }
 
-        prev_costs.clone_from_slice(&curr_costs);
+        last_row.insert(s1_units[i - 1], i);
     }
 
-    curr_costs[s1.len()]
+    distances[(len1 + 1) * width + len2 + 1]
 }
 
-/// Calculates the Dice coefficient between two strings.
+/// The largest number of leading units the Winkler adjustment rewards.
+pub const JARO_WINKLER_MAX_PREFIX: usize = 4;
+
+/// Calculates the Jaro-Winkler similarity between two strings.
 ///
 /// # Parameters
 /// - `s1`: The first string.
 /// - `s2`: The second string.
+/// - `prefix_scale`: How much a common prefix raises the Jaro similarity; at most 0.25 to stay within 1.
+/// - `unit`: The text unit matches and transpositions are counted in.
 ///
 /// # Returns
-/// The Dice coefficient.
-pub fn dice_coefficient(s1: &str, s2: &str) -> f64
+/// The Jaro-Winkler similarity, between 0 (nothing in common) and 1 (identical).
+pub fn jaro_winkler(s1: &str, s2: &str, prefix_scale: f64, unit: TextUnit) -> f64
 {
-    let s1_bigrams = extract_bigrams(s1);
-    let s2_bigrams = extract_bigrams(s2);
+    let s1_units = text_units(s1, unit);
+    let s2_units = text_units(s2, unit);
+
+    if s1_units.is_empty() && s2_units.is_empty()
+    {
+        return 1.0;
+    }
+
+    if s1_units.is_empty() || s2_units.is_empty()
+    {
+        return 0.0;
+    }
+
+    // Units match when they are equal and no further apart than the window
+    let window = (s1_units.len().max(s2_units.len()) / 2).saturating_sub(1);
+    let mut s1_matched = vec![false; s1_units.len()];
+    let mut s2_matched = vec![false; s2_units.len()];
+    let mut matches = 0;
+
+    for (i, s1_unit) in s1_units.iter().enumerate()
+    {
+        let end = (i + window + 1).min(s2_units.len());
+        for j in i.saturating_sub(window)..end
+        {
+            if !s2_matched[j] && s2_units[j] == *s1_unit
+            {
+                s1_matched[i] = true;
+                s2_matched[j] = true;
+                matches += 1;
+                break;
+            }
+        }
+    }
+
+    if matches == 0
+    {
+        return 0.0;
+    }
+
+    // Matched units appearing in a different order count as half a transposition each
+    let s1_sequence = s1_units.iter().zip(&s1_matched).filter(|(_, matched)| **matched).map(|(unit, _)| unit);
+    let s2_sequence = s2_units.iter().zip(&s2_matched).filter(|(_, matched)| **matched).map(|(unit, _)| unit);
+    let transpositions = s1_sequence.zip(s2_sequence).filter(|(a, b)| a != b).count() / 2;
+
+    let matches = matches as f64;
+    let jaro = (matches / s1_units.len() as f64
+        + matches / s2_units.len() as f64
+        + (matches - transpositions as f64) / matches) / 3.0;
+
+    let prefix = s1_units.iter().zip(&s2_units)
+        .take(JARO_WINKLER_MAX_PREFIX)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    jaro + prefix as f64 * prefix_scale * (1.0 - jaro)
+}
+
+/// Calculates the Jaccard index of the n-gram sets of two strings.
+///
+/// # Parameters
+/// - `s1`: The first string.
+/// - `s2`: The second string.
+/// - `ngram_size`: The number of text units in each n-gram.
+/// - `unit`: The text unit the n-grams are made of.
+///
+/// # Returns
+/// The number of distinct n-grams shared over the number of distinct n-grams of either string; strings
+/// too short to have an n-gram score 1 if they are equal and 0 otherwise.
+pub fn jaccard_index(s1: &str, s2: &str, ngram_size: usize, unit: TextUnit) -> f64
+{
+    let s1_ngrams: HashSet<&[u8]> = tokenize_by_ngram(s1, ngram_size, unit).into_iter().collect();
+    let s2_ngrams: HashSet<&[u8]> = tokenize_by_ngram(s2, ngram_size, unit).into_iter().collect();
+
+    let union_count = s1_ngrams.union(&s2_ngrams).count();
+    if union_count == 0
+    {
+        return if s1 == s2 { 1.0 } else { 0.0 };
+    }
+
+    s1_ngrams.intersection(&s2_ngrams).count() as f64 / union_count as f64
+}
+
+/// Calculates the length of the longest common substring of two strings.
+///
+/// # Parameters
+/// - `s1`: The first string.
+/// - `s2`: The second string.
+/// - `unit`: The text unit the substrings are made of.
+///
+/// # Returns
+/// The length of the longest run of units both strings contain, in text units.
+pub fn longest_common_substring(s1: &str, s2: &str, unit: TextUnit) -> usize
+{
+    let s1_units = text_units(s1, unit);
+    let s2_units = text_units(s2, unit);
+
+    // The length of the common run ending at each unit of `s2`, for the previous unit of `s1`
+    let mut prev_lengths = vec![0; s2_units.len() + 1];
+    let mut curr_lengths = vec![0; s2_units.len() + 1];
+    let mut longest = 0;
+
+    for s1_unit in &s1_units
+    {
+        for (j, s2_unit) in s2_units.iter().enumerate()
+        {
+            curr_lengths[j + 1] = if s1_unit == s2_unit { prev_lengths[j] + 1 } else { 0 };
+            longest = longest.max(curr_lengths[j + 1]);
+        }
+
+        std::mem::swap(&mut prev_lengths, &mut curr_lengths);
+    }
+
+    longest
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::Random;
+
+    const UNITS: [TextUnit; 3] = [TextUnit::Byte, TextUnit::Char, TextUnit::Grapheme];
+
+    /// Pieces random strings are made of: ASCII, multi-byte characters, combining marks on their own and
+    /// after a letter, and a zero-width joiner sequence.
+    const PIECES: [&str; 12] = ["a", "b", "/", ".", "é", "ß", "日", "😀", "e\u{301}", "\u{301}", "\u{308}", "👩\u{200d}💻"];
+
+    const DELIMITERS: [&str; 7] = ["/", ".", "a", "ab", "é", "\u{301}", "😀"];
+
+    /// The units of a string, split without the shared boundary helper.
+    fn reference_units(string: &str, unit: TextUnit) -> Vec<Vec<u8>>
+    {
+        match unit
+        {
+            TextUnit::Byte => string.bytes().map(|byte| vec![byte]).collect(),
+            TextUnit::Char => string.chars().map(|c| c.to_string().into_bytes()).collect(),
+            TextUnit::Grapheme => string.graphemes(true).map(|g| g.as_bytes().to_vec()).collect(),
+        }
+    }
+
+    /// Levenshtein distance over the full dynamic programming matrix.
+    fn reference_levenshtein(s1: &str, s2: &str, unit: TextUnit) -> usize
+    {
+        let (a, b) = (reference_units(s1, unit), reference_units(s2, unit));
+        let mut matrix = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+        for (i, row) in matrix.iter_mut().enumerate()
+        {
+            row[0] = i;
+        }
+        for (j, cell) in matrix[0].iter_mut().enumerate()
+        {
+            *cell = j;
+        }
+        for i in 1..=a.len()
+        {
+            for j in 1..=b.len()
+            {
+                let substitution = matrix[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+                matrix[i][j] = substitution.min(matrix[i - 1][j] + 1).min(matrix[i][j - 1] + 1);
+            }
+        }
+
+        matrix[a.len()][b.len()]
+    }
+
+    /// Splits by trying every delimiter at every unit boundary, building the tokens unit by unit.
+    fn reference_split(source: &str, delimiters: &[String], unit: TextUnit) -> Vec<String>
+    {
+        let units = reference_units(source, unit);
+        let mut boundaries = vec![0];
+        for unit in &units
+        {
+            boundaries.push(boundaries.last().unwrap() + unit.len());
+        }
+
+        let mut tokens = Vec::new();
+        let mut token = Vec::new();
+        let mut k = 0;
+        while k < units.len()
+        {
+            let rest = &source.as_bytes()[boundaries[k]..];
+            let matched = delimiters.iter()
+                .filter(|delimiter| !delimiter.is_empty() && rest.starts_with(delimiter.as_bytes()))
+                .find_map(|delimiter| boundaries.iter().position(|&boundary| boundary == boundaries[k] + delimiter.len()));
 
-    let intersection_count = s1_bigrams.iter().filter(|&bigram| s2_bigrams.contains(bigram)).count();
-    let total_bigrams = s1_bigrams.len() + s2_bigrams.len();
+            match matched
+            {
+                Some(end) =>
+                {
+                    if !token.is_empty()
+                    {
+                        tokens.push(String::from_utf8(std::mem::take(&mut token)).unwrap());
+                    }
+                    k = end;
+                },
+                None =>
+                {
+                    token.extend_from_slice(&units[k]);
+                    k += 1;
+                },
+            }
+        }
+        if !token.is_empty()
+        {
+            tokens.push(String::from_utf8(token).unwrap());
+        }
 
-    (2 * intersection_count) as f64 / total_bigrams as f64
+        tokens
+    }
+
+    /// Multiset Dice coefficient, intersecting the bigram lists by removing matched bigrams one at a time.
+    fn reference_dice(s1: &str, s2: &str, unit: TextUnit) -> f64
+    {
+        let bigrams = |string: &str| -> Vec<Vec<u8>> {
+            let units = reference_units(string, unit);
+            if units.len() < 2
+            {
+                return vec![string.as_bytes().to_vec()];
+            }
+            units.windows(2).map(|pair| pair.concat()).collect()
+        };
+        let (a, mut b) = (bigrams(s1), bigrams(s2));
+        let total = a.len() + b.len();
+
+        let mut intersection = 0;
+        for gram in &a
+        {
+            if let Some(index) = b.iter().position(|other| other == gram)
+            {
+                b.swap_remove(index);
+                intersection += 1;
+            }
+        }
+
+        (2 * intersection) as f64 / total as f64
+    }
+
+    #[test]
+    fn levenshtein_matches_reference_on_random_input()
+    {
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2000
+        {
+            let (s1, s2) = (random.string(&PIECES, 9), random.string(&PIECES, 9));
+            for unit in UNITS
+            {
+                assert_eq!(levenshtein(&s1, &s2, unit), reference_levenshtein(&s1, &s2, unit), "{:?} {:?} {}", s1, s2, unit);
+            }
+        }
+    }
+
+    #[test]
+    fn split_matches_reference_on_random_input()
+    {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000
+        {
+            let source = random.string(&PIECES, 9);
+            let delimiters: Vec<String> = (0..=random.below(3)).map(|_| DELIMITERS[random.below(DELIMITERS.len())].to_string()).collect();
+            for unit in UNITS
+            {
+                assert_eq!(
+                    split_by_multiple_delimiters(&source, &delimiters, unit),
+                    reference_split(&source, &delimiters, unit),
+                    "{:?} {:?} {}", source, delimiters, unit
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn split_by_single_characters_matches_str_split()
+    {
+        let mut random = Random(0x1234_5678_9abc_def1);
+        let delimiters = vec!["/".to_string(), "é".to_string()];
+        for _ in 0..500
+        {
+            let source = random.string(&PIECES, 9);
+            let expected: Vec<&str> = source.split(['/', 'é']).filter(|token| !token.is_empty()).collect();
+            assert_eq!(split_by_multiple_delimiters(&source, &delimiters, TextUnit::Char), expected, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn dice_coefficient_matches_reference_on_random_input()
+    {
+        let mut random = Random(0xdead_beef_cafe_f00d);
+        for _ in 0..2000
+        {
+            let (s1, s2) = (random.string(&PIECES, 9), random.string(&PIECES, 9));
+            for unit in UNITS
+            {
+                let dice = dice_coefficient(&s1, &s2, unit);
+                assert!((dice - reference_dice(&s1, &s2, unit)).abs() < 1e-12, "{:?} {:?} {}", s1, s2, unit);
+                assert!((dice - dice_coefficient(&s2, &s1, unit)).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn byte_units_split_multi_byte_characters()
+    {
+        assert_eq!(text_units("é", TextUnit::Byte), [&[0xc3][..], &[0xa9][..]]);
+        assert_eq!(unit_count("e\u{301}😀", TextUnit::Byte), 7);
+        assert_eq!(unit_count("e\u{301}😀", TextUnit::Char), 3);
+        assert_eq!(unit_count("e\u{301}😀", TextUnit::Grapheme), 2);
+
+        assert_eq!(levenshtein("é", "e", TextUnit::Byte), 2);
+        assert_eq!(levenshtein("é", "e", TextUnit::Char), 1);
+        assert_eq!(levenshtein("e\u{301}", "e", TextUnit::Grapheme), 1);
+
+        // Delimiters never match inside a character, even when every byte is a unit
+        let delimiters = vec!["/".to_string(), "\u{301}".to_string()];
+        assert_eq!(split_by_multiple_delimiters("é/ü", &delimiters, TextUnit::Byte), ["é", "ü"]);
+        assert_eq!(split_by_multiple_delimiters("e\u{301}/x", &delimiters, TextUnit::Char), ["e", "x"]);
+        assert_eq!(split_by_multiple_delimiters("e\u{301}/x", &delimiters, TextUnit::Grapheme), ["e\u{301}", "x"]);
+    }
+
+    #[test]
+    fn bigram_profiles_of_short_inputs_use_the_whole_input()
+    {
+        for unit in UNITS
+        {
+            let empty = BigramProfile::new("", unit);
+            assert_eq!((empty.total(), empty.count(b"")), (1, 1));
+
+            let single = BigramProfile::new("a", unit);
+            assert_eq!((single.total(), single.count(b"a")), (1, 1));
+        }
+
+        let accented = BigramProfile::new("e\u{301}", TextUnit::Grapheme);
+        assert_eq!((accented.total(), accented.count("e\u{301}".as_bytes())), (1, 1));
+        assert_eq!(BigramProfile::new("e\u{301}", TextUnit::Char).total(), 1);
+        assert_eq!(BigramProfile::new("é", TextUnit::Byte).count("é".as_bytes()), 1);
+
+        assert_eq!(dice_coefficient("a", "a", TextUnit::Char), 1.0);
+        assert_eq!(dice_coefficient("a", "b", TextUnit::Char), 0.0);
+        assert_eq!(dice_coefficient("a", "ab", TextUnit::Char), 0.0);
+    }
+
+    #[test]
+    fn empty_strings_are_identical()
+    {
+        for unit in UNITS
+        {
+            assert_eq!(levenshtein("", "", unit), 0);
+            assert_eq!(dice_coefficient("", "", unit), 1.0);
+            assert_eq!(split_by_multiple_delimiters("", &["/".to_string()], unit), Vec::<String>::new());
+            assert_eq!(levenshtein("", "é", unit), reference_units("é", unit).len());
+        }
+    }
+
+    #[test]
+    fn dice_coefficient_counts_repeated_bigrams()
+    {
+        assert_eq!(dice_coefficient("aaaa", "aa", TextUnit::Char), 0.5);
+        assert_eq!(dice_coefficient("night", "nacht", TextUnit::Char), 0.25);
+        assert_eq!(dice_coefficient("ababab", "abab", TextUnit::Char), 0.75);
+    }
+
+    #[test]
+    fn percent_decode_turns_only_input_plus_signs_into_spaces()
+    {
+        let decode = |source: &str| percent_decode(source, &DecodeOptions::default()).text;
+
+        assert_eq!(decode("a+b"), "a b");
+        assert_eq!(decode("%2B"), "+");
+        assert_eq!(decode("%252B"), "+");
+        assert_eq!(decode("a+%2B%2b"), "a ++");
+        assert_eq!(percent_decode("%2B", &DecodeOptions::default()).passes, 1);
+
+        let literal = DecodeOptions { plus_as_space: false, ..DecodeOptions::default() };
+        assert_eq!(percent_decode("a+%2B", &literal).text, "a++");
+    }
+
+    #[test]
+    fn other_metrics_match_known_values()
+    {
+        assert_eq!(damerau_levenshtein("CA", "ABC", TextUnit::Char), 2);
+        assert_eq!(damerau_levenshtein("kitten", "sitting", TextUnit::Char), 3);
+        assert!((jaro_winkler("MARTHA", "MARHTA", 0.1, TextUnit::Char) - 0.9611).abs() < 1e-4);
+        assert!((jaro_winkler("DIXON", "DICKSONX", 0.1, TextUnit::Char) - 0.8133).abs() < 1e-4);
+        assert!((jaro_winkler("DWAYNE", "DUANE", 0.1, TextUnit::Char) - 0.84).abs() < 1e-4);
+        assert!((jaccard_index("night", "nacht", 2, TextUnit::Char) - 1.0 / 7.0).abs() < 1e-12);
+        assert_eq!(longest_common_substring("/etc/passwd", "../../etc/pass", TextUnit::Char), 9);
+    }
+
+    #[test]
+    fn url_remove_drops_the_valid_escapes_only()
+    {
+        assert_eq!(url_remove("a%2Fb+c%zz%"), "ab+c%zz%");
+        assert_eq!(url_remove("%C3%A9t%C3%A9"), "t");
+        assert_eq!(process_decoded_string("a%2Fb+c%zz%", 0), "a/b+c%zz%");
+    }
 }
@@ -0,0 +1,443 @@
+/// File: supervisor.rs
+///
+/// This file contains the supervisor of the live watcher's worker threads. The watcher, analyzer and
+/// reporter run as separate threads connected by bounded channels, so a slow stage holds the previous
+/// ones back instead of buffering without limit. The supervisor restarts a worker that panicked or
+/// failed, waiting longer after each crash, and on SIGINT or SIGTERM it stops the workers in pipeline
+/// order: each stage finishes the work already queued for it before the next one is stopped, so every
+/// line read is reported and its checkpoint saved before the process exits.
+/// The file includes the following items:
+///
+/// - `POLL_INTERVAL`: How often waiting workers check whether shutdown was requested.
+/// - `Inbox`: The receiving end of a bounded channel, shared by the successive runs of a worker.
+/// - `Supervisor`: Runs the worker threads, restarts crashed ones and shuts them down on a signal.
+///
+/// Modules Required:
+/// - None; the workers are provided by the caller.
+use std::any::Any;
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+
+/// How often waiting workers and the supervisor check whether shutdown was requested.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The delay before the first restart of a crashed worker.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The longest delay before restarting a crashed worker.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long a worker has to run before a crash is no longer counted as part of a crash loop.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+/// How many times a message is handed to a worker before it is dropped as the cause of its crashes.
+const MAX_DELIVERIES: u32 = 3;
+
+/// Exit status after a second signal, when the process exits without waiting for the workers.
+const FORCED_EXIT_STATUS: i32 = 130;
+
+/// Locks a mutex, recovering the data if a crashed worker poisoned it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T>
+{
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The receiving end of a bounded channel, shared by the successive runs of a worker.
+///
+/// # Summary
+/// The message being processed stays in the inbox until the worker marks it done, so a worker that
+/// crashes gets the same message again when it is restarted. A message that crashed the worker
+/// `MAX_DELIVERIES` times is dropped.
+#[derive(Debug)]
+pub struct Inbox<T>
+{
+    receiver: Mutex<Receiver<T>>,
+    /// The message being processed and how many times it was handed out.
+    pending: Mutex<Option<(T, u32)>>,
+}
+
+impl<T: Clone> Inbox<T>
+{
+    /// Creates a bounded channel.
+    ///
+    /// # Parameters
+    /// - `capacity`: The number of messages that can wait in the channel before senders block.
+    ///
+    /// # Returns
+    /// The sender and the inbox of the channel.
+    pub fn channel(capacity: usize) -> (SyncSender<T>, Inbox<T>)
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        (sender, Inbox { receiver: Mutex::new(receiver), pending: Mutex::new(None) })
+    }
+
+    /// Returns the message to process: the one left unfinished by a crashed run, or the next one sent.
+    ///
+    /// # Returns
+    /// The message, or `None` once every sender is gone and the channel is empty.
+    pub fn next(&self) -> Option<T>
+    {
+        let mut pending = lock(&self.pending);
+
+        if let Some((_, deliveries)) = pending.as_ref()
+        {
+            if *deliveries >= MAX_DELIVERIES
+            {
+                error!("Dropping a message that crashed its worker {} times", deliveries);
+                *pending = None;
+            }
+        }
+
+        if pending.is_none()
+        {
+            *pending = Some((lock(&self.receiver).recv().ok()?, 0));
+        }
+
+        pending.as_mut().map(|(message, deliveries)| {
+            *deliveries += 1;
+            message.clone()
+        })
+    }
+
+    /// Marks the message returned by `next` as processed.
+    pub fn done(&self)
+    {
+        *lock(&self.pending) = None;
+    }
+}
+
+/// The body of a worker thread, called again on every restart with the shutdown flag.
+type WorkerBody = Arc<dyn Fn(&AtomicBool) -> io::Result<()> + Send + Sync>;
+
+/// A worker thread and its restart state.
+struct Worker
+{
+    name: String,
+    /// Dropped once the worker is stopped, which closes the channels it sends to.
+    body: Option<WorkerBody>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+    started: Instant,
+    backoff: Duration,
+    restart_at: Option<Instant>,
+}
+
+/// Returns the message of a panic.
+fn panic_message(payload: &(dyn Any + Send)) -> String
+{
+    payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+impl Worker
+{
+    /// Starts a run of the worker on a new thread.
+    fn start(&mut self, shutdown: &Arc<AtomicBool>) -> io::Result<()>
+    {
+        let Some(body) = self.body.clone() else
+        {
+            return Ok(());
+        };
+
+        let shutdown = Arc::clone(shutdown);
+        self.handle = Some(thread::Builder::new().name(self.name.clone()).spawn(move || body(&shutdown))?);
+        self.started = Instant::now();
+        self.restart_at = None;
+        Ok(())
+    }
+
+    /// Returns whether the current run has ended.
+    fn is_finished(&self) -> bool
+    {
+        self.handle.as_ref().is_some_and(|handle| handle.is_finished())
+    }
+
+    /// Joins the finished run and logs how it ended.
+    ///
+    /// # Parameters
+    /// - `shutting_down`: Whether the run was expected to end.
+    ///
+    /// # Returns
+    /// Whether the run crashed: it panicked, failed, or returned before shutdown was requested.
+    fn join(&mut self, shutting_down: bool) -> bool
+    {
+        let Some(handle) = self.handle.take() else
+        {
+            return false;
+        };
+
+        match handle.join()
+        {
+            Ok(Ok(())) if shutting_down =>
+            {
+                info!("Worker {} stopped", self.name);
+                return false;
+            },
+            Ok(Ok(())) => warn!("Worker {} stopped unexpectedly", self.name),
+            Ok(Err(e)) => error!("Worker {} failed: {}", self.name, e),
+            Err(payload) => error!("Worker {} panicked: {}", self.name, panic_message(payload.as_ref())),
+        }
+        true
+    }
+
+    /// Schedules the restart of the worker after its run ended, doubling the delay after each crash.
+    fn schedule_restart(&mut self)
+    {
+        if self.started.elapsed() >= STABLE_RUN
+        {
+            self.backoff = INITIAL_BACKOFF;
+        }
+
+        warn!("Restarting worker {} in {:?}", self.name, self.backoff);
+        self.restart_at = Some(Instant::now() + self.backoff);
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Runs the worker threads, restarts crashed ones and shuts them down on a signal.
+pub struct Supervisor
+{
+    shutdown: Arc<AtomicBool>,
+    /// The workers in pipeline order: each one only sends to workers spawned after it.
+    workers: Vec<Worker>,
+}
+
+impl Supervisor
+{
+    /// Creates a supervisor without workers.
+    pub fn new() -> Supervisor
+    {
+        Supervisor { shutdown: Arc::new(AtomicBool::new(false)), workers: Vec::new() }
+    }
+
+    /// Requests shutdown on SIGINT or SIGTERM; a second signal exits at once, without waiting for the workers.
+    ///
+    /// # Returns
+    /// A result indicating failure to install the signal handler.
+    pub fn install_signal_handler(&self) -> io::Result<()>
+    {
+        let shutdown = Arc::clone(&self.shutdown);
+        ctrlc::set_handler(move || {
+            if shutdown.swap(true, Ordering::SeqCst)
+            {
+                warn!("Second signal received; exiting without saving the pending results");
+                process::exit(FORCED_EXIT_STATUS);
+            }
+            info!("Shutting down: finishing the pending log entries and saving the checkpoints");
+        })
+        .map_err(io::Error::other)
+    }
+
+    /// Starts a worker thread.
+    ///
+    /// # Parameters
+    /// - `name`: The name of the worker and its thread.
+    /// - `body`: The work of the thread, given the shutdown flag. It is called again when it panics or
+    ///   returns before shutdown was requested, so it has to resume from state saved outside the thread.
+    ///   Workers reading an `Inbox` return when their channel is closed; the others return once the flag is set.
+    ///
+    /// # Returns
+    /// A result indicating failure to create the thread.
+    pub fn spawn<F>(&mut self, name: &str, body: F) -> io::Result<()>
+    where
+        F: Fn(&AtomicBool) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let mut worker = Worker {
+            name: name.to_string(),
+            body: Some(Arc::new(body)),
+            handle: None,
+            started: Instant::now(),
+            backoff: INITIAL_BACKOFF,
+            restart_at: None,
+        };
+        worker.start(&self.shutdown)?;
+        self.workers.push(worker);
+        Ok(())
+    }
+
+    /// Supervises the workers until shutdown is requested, then stops them in pipeline order.
+    ///
+    /// # Summary
+    /// Each worker is waited for and then released, which closes the channel it sends to, so the next
+    /// worker finishes the messages queued for it and returns. Workers after the one being waited for are
+    /// still restarted if they crash, so the queued work is not stranded.
+    ///
+    /// # Returns
+    /// A result indicating failure to create a thread when restarting a worker.
+    pub fn run(mut self) -> io::Result<()>
+    {
+        while !self.shutdown.load(Ordering::SeqCst)
+        {
+            self.supervise(0)?;
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        for index in 0..self.workers.len()
+        {
+            while self.workers[index].handle.is_some() && !self.workers[index].is_finished()
+            {
+                self.supervise(index + 1)?;
+                thread::sleep(POLL_INTERVAL);
+            }
+
+            let worker = &mut self.workers[index];
+            worker.join(true);
+            worker.body = None;
+        }
+
+        info!("All workers stopped");
+        Ok(())
+    }
+
+    /// Joins the crashed workers from an index on and restarts those whose backoff has elapsed.
+    fn supervise(&mut self, first: usize) -> io::Result<()>
+    {
+        let shutting_down = self.shutdown.load(Ordering::SeqCst);
+
+        for worker in &mut self.workers[first..]
+        {
+            if worker.is_finished() && worker.join(shutting_down)
+            {
+                worker.schedule_restart();
+            }
+
+            if worker.restart_at.is_some_and(|restart_at| Instant::now() >= restart_at)
+            {
+                info!("Restarting worker {}", worker.name);
+                worker.start(&self.shutdown)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn inbox_drops_a_message_after_max_deliveries()
+    {
+        let (sender, inbox) = Inbox::channel(4);
+        sender.send("poison").unwrap();
+        sender.send("next").unwrap();
+
+        // Without `done`, every call hands out the same message again, as after a crash
+        for _ in 0..MAX_DELIVERIES
+        {
+            assert_eq!(inbox.next(), Some("poison"));
+        }
+        assert_eq!(inbox.next(), Some("next"));
+    }
+
+    #[test]
+    fn done_clears_the_pending_message()
+    {
+        let (sender, inbox) = Inbox::channel(4);
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        drop(sender);
+
+        assert_eq!(inbox.next(), Some(1));
+        inbox.done();
+        assert_eq!(inbox.next(), Some(2));
+        inbox.done();
+        assert_eq!(inbox.next(), None);
+    }
+
+    #[test]
+    fn backoff_doubles_and_resets_after_a_stable_run()
+    {
+        let mut worker = Worker {
+            name: "test".to_string(),
+            body: None,
+            handle: None,
+            started: Instant::now(),
+            backoff: INITIAL_BACKOFF,
+            restart_at: None,
+        };
+
+        worker.schedule_restart();
+        worker.schedule_restart();
+        assert_eq!(worker.backoff, INITIAL_BACKOFF * 4);
+        assert!(worker.restart_at.is_some());
+
+        worker.backoff = MAX_BACKOFF;
+        worker.schedule_restart();
+        assert_eq!(worker.backoff, MAX_BACKOFF);
+
+        worker.started = Instant::now().checked_sub(STABLE_RUN).expect("the monotonic clock is past the stable run");
+        worker.schedule_restart();
+        assert_eq!(worker.backoff, INITIAL_BACKOFF * 2);
+    }
+
+    #[test]
+    fn panicking_worker_is_restarted()
+    {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = Supervisor::new();
+
+        {
+            let runs = Arc::clone(&runs);
+            supervisor.spawn("flaky", move |shutdown| {
+                if runs.fetch_add(1, Ordering::SeqCst) == 0
+                {
+                    panic!("first run crashes");
+                }
+                shutdown.store(true, Ordering::SeqCst);
+                Ok(())
+            }).unwrap();
+        }
+
+        supervisor.run().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn shutdown_drains_the_pipeline_in_order()
+    {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut supervisor = Supervisor::new();
+        let (sender, inbox) = Inbox::channel(1);
+
+        supervisor.spawn("producer", move |shutdown| {
+            for message in 0..5
+            {
+                sender.send(message).map_err(io::Error::other)?;
+            }
+            while !shutdown.load(Ordering::SeqCst)
+            {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Ok(())
+        }).unwrap();
+
+        {
+            let received = Arc::clone(&received);
+            supervisor.spawn("consumer", move |_| {
+                while let Some(message) = inbox.next()
+                {
+                    thread::sleep(Duration::from_millis(20));
+                    lock(&received).push(message);
+                    inbox.done();
+                }
+                Ok(())
+            }).unwrap();
+        }
+
+        supervisor.shutdown.store(true, Ordering::SeqCst);
+        supervisor.run().unwrap();
+
+        assert_eq!(*lock(&received), vec![0, 1, 2, 3, 4]);
+    }
+}
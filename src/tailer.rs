@@ -0,0 +1,444 @@
+/// File: tailer.rs
+///
+/// This file contains the follow-by-name tailer of the live watcher. The tailer keeps the log file it
+/// reads open at the end of the last read, so each read costs only the bytes appended since, and it
+/// checks the path whenever it reaches the end of the file, so it survives the usual ways log files
+/// are rotated:
+///
+/// - rename and create (logrotate's default): the old file is drained to its end through the open
+///   handle, then the tailer switches to the new file at the path; when resuming from a checkpoint,
+///   the old file is found among the rotated siblings (e.g. `<name>.1`) and drained first;
+/// - remove and create: the same, as the removed file stays readable through the open handle;
+/// - copy and truncate (`copytruncate`): the last analyzed line is no longer before the offset, so the
+///   file is read from the start, even if it already grew past the offset again;
+/// - rewrite in place: the same as copy and truncate.
+///
+/// Truncation and rewrites are checked against the checkpoint before every read from the file.
+///
+/// The file includes the following items:
+///
+/// - `Tailer`: Reads the lines appended to a log file, following its path across rotations.
+/// - `Lines`: Iterator over the complete lines appended to a log file that are available now.
+///
+/// Modules Required:
+/// - `checkpoint`: Provides the position of the tailer and the detection of truncated files.
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+
+use tracing::{debug, info, warn};
+
+use crate::checkpoint::{self, Checkpoint, FileChange};
+
+/// Reads the lines appended to a log file, following its path across rotations.
+#[derive(Debug)]
+pub struct Tailer
+{
+    /// The position of the last complete line returned.
+    checkpoint: Checkpoint,
+    /// The file currently read, positioned after the partial line; after a rotation it is drained
+    /// before the new file is opened.
+    reader: BufReader<File>,
+    /// The bytes of a trailing line whose line break has not been written yet.
+    partial_line: Vec<u8>,
+}
+
+/// Iterator over the complete lines appended to a log file that are available now; it ends at the end
+/// of the file and picks up where it stopped when created again.
+#[derive(Debug)]
+pub struct Lines<'a>
+{
+    tailer: &'a mut Tailer,
+}
+
+impl Iterator for Lines<'_>
+{
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        self.tailer.next_line().transpose()
+    }
+}
+
+impl Tailer
+{
+    /// Opens the file of a checkpoint and positions the tailer at its offset.
+    ///
+    /// # Summary
+    /// If the path now refers to another file, the checkpointed file is looked up among the rotated
+    /// siblings of the path (`<name>.1`, `<name>.old`, ...) and the rest of it is read first. If it
+    /// cannot be found, or the file was truncated or rewritten since the checkpoint was taken, the
+    /// tailer starts at the beginning of the file at the path.
+    ///
+    /// # Parameters
+    /// - `checkpoint`: The checkpoint to resume at.
+    ///
+    /// # Returns
+    /// A result containing the tailer or an error if the file cannot be opened.
+    pub fn open(checkpoint: Checkpoint) -> io::Result<Tailer>
+    {
+        let mut file = File::open(&checkpoint.path)?;
+        let inode = checkpoint::file_inode(&file.metadata()?);
+
+        let change = if inode != checkpoint.inode
+        {
+            match find_rotated_file(&checkpoint)?
+            {
+                Some(mut rotated_file) =>
+                {
+                    let change = checkpoint.detect_change(&mut rotated_file)?;
+                    if change == FileChange::Unchanged
+                    {
+                        info!("{} was rotated since offset {}; reading the rest of the rotated file first", checkpoint.path, checkpoint.offset);
+                        file = rotated_file;
+                    }
+                    change
+                },
+                None => FileChange::Rotated,
+            }
+        }
+        else
+        {
+            checkpoint.detect_change(&mut file)?
+        };
+
+        let checkpoint = match change
+        {
+            FileChange::Unchanged => checkpoint,
+            change =>
+            {
+                warn!("{} changed ({:?}) since offset {}; reading it from the start", checkpoint.path, change, checkpoint.offset);
+                Checkpoint::start_of(&checkpoint.path, inode)
+            },
+        };
+
+        file.seek(SeekFrom::Start(checkpoint.offset))?;
+        Ok(Tailer { checkpoint, reader: BufReader::new(file), partial_line: Vec::new() })
+    }
+
+    /// Returns the position of the last complete line returned, to be saved once the lines are processed.
+    pub fn checkpoint(&self) -> &Checkpoint
+    {
+        &self.checkpoint
+    }
+
+    /// Returns the metadata of the file currently read, which is not the file at the path while a
+    /// rotated file is drained.
+    pub fn metadata(&self) -> io::Result<Metadata>
+    {
+        self.reader.get_ref().metadata()
+    }
+
+    /// Returns an iterator over the complete lines appended since the last read.
+    pub fn lines(&mut self) -> Lines<'_>
+    {
+        Lines { tailer: self }
+    }
+
+    /// Reads the next complete line appended to the log file.
+    ///
+    /// # Summary
+    /// Only the bytes appended since the last read are read. A trailing line without its line break is
+    /// buffered until the line break arrives, unless the file has been rotated away, in which case it is
+    /// returned as the last line of the old file. Before every read from the file, the checkpoint is
+    /// compared against it, and a truncated or rewritten file is read from the start. At the end of the
+    /// file, rotation is checked before reporting that no line is available.
+    ///
+    /// # Returns
+    /// A result containing the line without its line break, `None` if no complete line is available,
+    /// or an error if a file cannot be read.
+    pub fn next_line(&mut self) -> io::Result<Option<String>>
+    {
+        loop
+        {
+            if self.reader.buffer().is_empty()
+            {
+                // The next read goes to the file, which may have been truncated or rewritten meanwhile
+                self.restart_if_changed()?;
+            }
+
+            let read = self.reader.read_until(b'\n', &mut self.partial_line)?;
+
+            if self.partial_line.ends_with(b"\n")
+            {
+                return Ok(Some(self.take_partial_line()));
+            }
+
+            if read > 0
+            {
+                // More of the partial line arrived; keep reading until the end of the file
+                continue;
+            }
+
+            let Some(file) = self.open_rotated_file()? else
+            {
+                return Ok(None);
+            };
+
+            // The last line of the rotated file may never get its line break
+            let last_line = if self.partial_line.is_empty() { None } else { Some(self.take_partial_line()) };
+            self.checkpoint = Checkpoint::start_of(&self.checkpoint.path, checkpoint::file_inode(&file.metadata()?));
+            self.reader = BufReader::new(file);
+
+            if last_line.is_some()
+            {
+                return Ok(last_line);
+            }
+        }
+    }
+
+    /// Compares the checkpoint against the file being read and moves to its start if it was truncated
+    /// or rewritten; otherwise positions the reader after the partial line again.
+    ///
+    /// # Returns
+    /// A result indicating success or failure.
+    fn restart_if_changed(&mut self) -> io::Result<()>
+    {
+        let read_position = self.checkpoint.offset + self.partial_line.len() as u64;
+        let file = self.reader.get_mut();
+
+        let mut change = self.checkpoint.detect_change(file)?;
+        if change == FileChange::Unchanged && file.metadata()?.len() < read_position
+        {
+            // Only the partial line was cut off
+            change = FileChange::Truncated;
+        }
+
+        if change == FileChange::Unchanged
+        {
+            self.reader.seek(SeekFrom::Start(read_position))?;
+            return Ok(());
+        }
+
+        warn!("{} was {:?} in place; reading it from the start", self.checkpoint.path, change);
+        self.checkpoint = Checkpoint::start_of(&self.checkpoint.path, self.checkpoint.inode);
+        self.partial_line.clear();
+        self.reader.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+
+    /// Opens the file the path refers to if it is not the file being read.
+    ///
+    /// # Returns
+    /// A result containing the new file, `None` if the path still refers to the file being read or does
+    /// not exist, or an error if the new file cannot be opened.
+    fn open_rotated_file(&self) -> io::Result<Option<File>>
+    {
+        let path_inode = match fs::metadata(&self.checkpoint.path)
+        {
+            Ok(metadata) => checkpoint::file_inode(&metadata),
+            Err(e) if e.kind() == io::ErrorKind::NotFound =>
+            {
+                // Renamed or removed, and not created again yet: keep reading the open file
+                debug!("{} is missing; waiting for it to be created", self.checkpoint.path);
+                return Ok(None);
+            },
+            Err(e) => return Err(e),
+        };
+
+        if path_inode == self.checkpoint.inode
+        {
+            return Ok(None);
+        }
+
+        info!("{} was rotated; following the new file", self.checkpoint.path);
+        File::open(&self.checkpoint.path).map(Some)
+    }
+
+    /// Removes the buffered line, moves the checkpoint past it and returns it without its line break.
+    fn take_partial_line(&mut self) -> String
+    {
+        let bytes = std::mem::take(&mut self.partial_line);
+        self.checkpoint.advance(&bytes);
+
+        let line = String::from_utf8_lossy(&bytes);
+        line.trim_end_matches('\n').trim_end_matches('\r').to_string()
+    }
+}
+
+/// Looks up the checkpointed file among the rotated siblings of its path, i.e. the files in the same
+/// directory whose name starts with the name of the log file and a dot.
+///
+/// # Parameters
+/// - `checkpoint`: The checkpoint whose file was rotated away from its path.
+///
+/// # Returns
+/// A result containing the rotated file, `None` if no sibling has the inode of the checkpoint, or an
+/// error if the directory cannot be read.
+fn find_rotated_file(checkpoint: &Checkpoint) -> io::Result<Option<File>>
+{
+    let path = Path::new(&checkpoint.path);
+    let Some(file_name) = path.file_name() else
+    {
+        return Ok(None);
+    };
+
+    // Without inodes the rotated file cannot be told apart from other files
+    if checkpoint.inode == 0
+    {
+        return Ok(None);
+    }
+
+    let directory = match path.parent()
+    {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}.", file_name.to_string_lossy());
+
+    for entry in fs::read_dir(directory)?
+    {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with(&prefix)
+        {
+            continue;
+        }
+
+        let Ok(metadata) = entry.metadata() else
+        {
+            continue;
+        };
+        if metadata.is_file() && checkpoint::file_inode(&metadata) == checkpoint.inode
+        {
+            debug!("found the rotated file of {} at {}", checkpoint.path, entry.path().display());
+            return File::open(entry.path()).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::{append, test_dir};
+    use std::fs::OpenOptions;
+
+    fn open_tailer(path: &Path) -> Tailer
+    {
+        let path = path.to_string_lossy();
+        let inode = checkpoint::file_inode(&fs::metadata(path.as_ref()).unwrap());
+        Tailer::open(Checkpoint::start_of(&path, inode)).unwrap()
+    }
+
+    fn read_lines(tailer: &mut Tailer) -> Vec<String>
+    {
+        tailer.lines().collect::<io::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn buffers_partial_lines_until_their_line_break()
+    {
+        let directory = test_dir("tailer-partial");
+        let log = directory.join("app.log");
+        append(&log, "first\nsec");
+
+        let mut tailer = open_tailer(&log);
+        assert_eq!(read_lines(&mut tailer), ["first"]);
+
+        append(&log, "ond\n");
+        assert_eq!(read_lines(&mut tailer), ["second"]);
+        assert_eq!(tailer.checkpoint().offset, 13);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn follows_rename_and_create()
+    {
+        let directory = test_dir("tailer-rename");
+        let log = directory.join("app.log");
+        append(&log, "a\nb\n");
+
+        let mut tailer = open_tailer(&log);
+        assert_eq!(read_lines(&mut tailer), ["a", "b"]);
+
+        append(&log, "c\ntail");
+        fs::rename(&log, directory.join("app.log.1")).unwrap();
+        append(&log, "d\n");
+
+        assert_eq!(read_lines(&mut tailer), ["c", "tail", "d"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn follows_remove_and_create()
+    {
+        let directory = test_dir("tailer-remove");
+        let log = directory.join("app.log");
+        append(&log, "a\n");
+
+        let mut tailer = open_tailer(&log);
+        assert_eq!(read_lines(&mut tailer), ["a"]);
+
+        append(&log, "b\n");
+        fs::remove_file(&log).unwrap();
+        assert_eq!(read_lines(&mut tailer), ["b"]);
+
+        append(&log, "c\n");
+        assert_eq!(read_lines(&mut tailer), ["c"]);
+    }
+
+    #[test]
+    fn restarts_after_copytruncate_even_if_the_file_grew_past_the_offset()
+    {
+        let directory = test_dir("tailer-copytruncate");
+        let log = directory.join("app.log");
+        append(&log, "a\nb\n");
+
+        let mut tailer = open_tailer(&log);
+        assert_eq!(read_lines(&mut tailer), ["a", "b"]);
+
+        fs::copy(&log, directory.join("app.log.1")).unwrap();
+        OpenOptions::new().write(true).open(&log).unwrap().set_len(0).unwrap();
+        append(&log, "a much longer line\nnext\n");
+
+        assert_eq!(read_lines(&mut tailer), ["a much longer line", "next"]);
+    }
+
+    #[test]
+    fn restarts_after_a_rewrite_in_place()
+    {
+        let directory = test_dir("tailer-rewrite");
+        let log = directory.join("app.log");
+        append(&log, "a\nb\n");
+
+        let mut tailer = open_tailer(&log);
+        assert_eq!(read_lines(&mut tailer), ["a", "b"]);
+
+        fs::write(&log, "c\nd\ne\n").unwrap();
+        assert_eq!(read_lines(&mut tailer), ["c", "d", "e"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn resumes_in_the_rotated_sibling_before_the_new_file()
+    {
+        let directory = test_dir("tailer-resume-rotated");
+        let log = directory.join("app.log");
+        append(&log, "a\nb\n");
+        let checkpoint = Checkpoint::at_offset(&log.to_string_lossy(), 2).unwrap();
+
+        append(&log, "c\n");
+        fs::rename(&log, directory.join("app.log.1")).unwrap();
+        append(&log, "d\n");
+
+        let mut tailer = Tailer::open(checkpoint).unwrap();
+        assert_eq!(read_lines(&mut tailer), ["b", "c", "d"]);
+    }
+
+    #[test]
+    fn resumes_from_the_start_if_the_checkpoint_is_corrupt()
+    {
+        let directory = test_dir("tailer-corrupt");
+        let log = directory.join("app.log");
+        append(&log, "a\nb\n");
+
+        let mut checkpoint = Checkpoint::at_offset(&log.to_string_lossy(), 2).unwrap();
+        checkpoint.last_line_length = 3;
+
+        let mut tailer = Tailer::open(checkpoint).unwrap();
+        assert_eq!(read_lines(&mut tailer), ["a", "b"]);
+    }
+}
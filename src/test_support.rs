@@ -0,0 +1,58 @@
+/// File: test_support.rs
+///
+/// This file contains the helpers shared by the unit tests of the other modules: scratch directories
+/// for the tests that touch the file system, and a seeded random generator for the tests that compare
+/// an implementation against a reference on many inputs.
+/// The file includes the following items:
+///
+/// - `test_dir`: Creates an empty scratch directory for one test.
+/// - `append`: Appends text to a file, creating it if needed.
+/// - `Random`: A seeded Xorshift generator.
+///
+/// Modules Required:
+/// - None.
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Creates an empty directory for one test under the system temporary directory.
+///
+/// # Parameters
+/// - `name`: The name of the test, unique across the whole test suite.
+///
+/// # Returns
+/// The canonical path of the directory, so it compares equal to the paths of file system events.
+pub fn test_dir(name: &str) -> PathBuf
+{
+    let directory = std::env::temp_dir().join(format!("fs-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory.canonicalize().unwrap()
+}
+
+/// Appends text to a file, creating it if needed.
+pub fn append(path: &Path, contents: &str)
+{
+    OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(contents.as_bytes()).unwrap();
+}
+
+/// Xorshift generator, so the random inputs are the same on every run.
+pub struct Random(pub u64);
+
+impl Random
+{
+    /// Returns a number below a bound.
+    pub fn below(&mut self, bound: usize) -> usize
+    {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+
+    /// Returns a string of fewer than `max_pieces` pieces, each picked from a list.
+    pub fn string(&mut self, pieces: &[&str], max_pieces: usize) -> String
+    {
+        (0..self.below(max_pieces)).map(|_| pieces[self.below(pieces.len())]).collect()
+    }
+}
@@ -1,92 +1,520 @@
-use std::sync::mpsc;
-extern crate notify;
-use notify::{RecommendedWatcher, Watcher, RecursiveMode, DebouncedEvent};
-use std::time::Duration;
+/// File: thread_utils.rs
+///
+/// This file contains the bodies of the live watcher's worker threads. The watcher reads the lines
+/// appended to the followed log files and sends them in batches, the analyzer scores every batch against
+/// the shared dictionary, and the reporter writes the detections and saves the checkpoint of each batch.
+/// A separate thread reloads the dictionary when its files change.
+/// The file includes the following items:
+///
+/// - `LogBatch`: Log entries read from a followed file, sent by the watcher to the analyzer.
+/// - `AnalyzedBatch`: The malicious entries of a batch, sent by the analyzer to the reporter.
+/// - `watch_for_malicious_log_changes`: Reloads the dictionary and swaps its rebuilt index in when its files change.
+/// - `watch_for_new_log_entries`: Sends the lines appended to the followed log files in batches.
+/// - `analyze_log_batches`: Analyzes the batches and sends their malicious entries to the reporter.
+/// - `report_analyzed_batches`: Reports the malicious entries and saves the checkpoint of each batch.
+///
+/// Modules Required:
+/// - `checkpoint`: Provides the position a batch ends at.
+/// - `dict_index`: Provides the shared dictionary and its index.
+/// - `file_ops`: Provides the loading of the dictionary files.
+/// - `file_watcher`: Provides the notifications of file changes.
+/// - `log_format`: Provides the parsing of log entries.
+/// - `log_ops`: Provides the analysis of log entries.
+/// - `supervisor`: Provides the inboxes the workers read from and the shutdown polling interval.
+/// - `watch_set`: Provides the followed log files.
+/// - `workspace`: Provides the data directory the dictionary files are looked up in.
+use std::sync::mpsc::SyncSender;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::io;
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use crate::checkpoint::Checkpoint;
+use crate::dict_index::{DictionaryIndex, SharedDictionary};
+use crate::log_format::LogFormat;
 use crate::file_ops;
-use crate::log_ops;
+use crate::file_watcher::{FileEvent, FileWatcher};
+use crate::log_ops::{self, AnalysisResult, ScoringConfig};
+use crate::supervisor::{Inbox, POLL_INTERVAL};
+use crate::watch_set::{WatchSet, WatchedFile};
+use crate::workspace::Workspace;
+use tracing::{debug, error, info, warn};
+
+/// The maximum number of log entries analyzed between two checkpoints.
+const BATCH_SIZE: usize = 1024;
+
+/// How long the dictionary files have to stay unchanged before the dictionary is reloaded.
+const DICTIONARY_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// The longest wait for the dictionary files to stay unchanged; the dictionary is reloaded after it
+/// even if they are still being written.
+const MAX_DICTIONARY_SETTLE_TIME: Duration = Duration::from_secs(5);
+
+/// Log entries read from a followed file, sent by the watcher to the analyzer.
+#[derive(Debug, Clone)]
+pub struct LogBatch
+{
+    pub lines: Vec<String>,
+    /// The position after the last line of the batch.
+    pub checkpoint: Checkpoint,
+    /// The file the checkpoint is saved to once the batch is reported.
+    pub checkpoint_file: PathBuf,
+}
+
+/// The malicious entries of a batch, sent by the analyzer to the reporter.
+#[derive(Debug, Clone)]
+pub struct AnalyzedBatch
+{
+    /// The number of entries analyzed.
+    pub analyzed: usize,
+    /// The malicious entries and their analysis results.
+    pub detections: Vec<(String, AnalysisResult)>,
+    pub checkpoint: Checkpoint,
+    pub checkpoint_file: PathBuf,
+}
+
+/// Loads the dictionary from the given file, or from the data directory files with a prefix.
+fn load_dictionary_entries(workspace: &Workspace, dictionary_file: Option<&str>, filename_prefix: &str) -> io::Result<Vec<String>>
+{
+    match dictionary_file
+    {
+        Some(file_name) => file_ops::read_file_line_by_line(file_name),
+        None =>
+        {
+            let mut entries: Vec<String> = Vec::new();
+            file_ops::load_files_into_vector(&mut entries, file_ops::get_filenames_with_prefix(workspace, filename_prefix.to_string()))?;
+            Ok(entries)
+        },
+    }
+}
 
-/// Creates a thread that waits for changes in malicious log files.
+/// Waits until no dictionary file changed for `DICTIONARY_SETTLE_TIME`, or for `MAX_DICTIONARY_SETTLE_TIME` at most.
+///
+/// # Summary
+/// Changes of other files in the watched directory, such as a log being followed, do not extend the wait.
 ///
 /// # Parameters
+/// - `watcher`: The file system watcher.
+/// - `is_dictionary_file`: Whether a changed path is a dictionary file.
+/// - `shutdown`: Set when the watcher has to stop; it is checked at least every `POLL_INTERVAL`.
+///
+/// # Returns
+/// A result containing `false` if shutdown was requested while waiting, or an error if the watcher stops.
+fn wait_for_dictionary_writes(watcher: &mut dyn FileWatcher, is_dictionary_file: &dyn Fn(&Path) -> bool, shutdown: &AtomicBool) -> io::Result<bool>
+{
+    let deadline = Instant::now() + MAX_DICTIONARY_SETTLE_TIME;
+    let mut settled_at = Instant::now() + DICTIONARY_SETTLE_TIME;
+
+    while !shutdown.load(Ordering::SeqCst)
+    {
+        let now = Instant::now();
+        let until = settled_at.min(deadline);
+        if now >= until
+        {
+            return Ok(true);
+        }
+
+        if let Some(event) = watcher.next_event((until - now).min(POLL_INTERVAL))?
+        {
+            if event.paths().into_iter().any(is_dictionary_file)
+            {
+                settled_at = Instant::now() + DICTIONARY_SETTLE_TIME;
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Waits for changes in malicious log files and swaps the rebuilt dictionary in, until shutdown is requested.
+///
+/// # Summary
+/// When the dictionary file, or a data directory file with the prefix, is written, created, renamed or
+/// removed, the dictionary is reloaded and its index rebuilt on this thread, then published through
+/// the shared handle; threads analyzing log entries keep using the previous index until their next
+/// batch. If reloading fails, the previous dictionary stays in use.
+///
+/// # Parameters
+/// - `workspace`: The workspace whose data directory is watched.
+/// - `dictionary_file`: The dictionary file given on the command line, watched instead of the prefixed files.
 /// - `filename_prefix`: A string representing the prefix of filenames to watch for changes.
-/// - `malicious_logs`: A mutable reference to a vector to store the malicious log entries.
-pub fn watch_for_malicious_log_changes(filename_prefix: String, malicious_logs: &mut Vec<String>)
+/// - `dictionary`: The shared dictionary the rebuilt index is published to.
+/// - `watcher`: The file system watcher, not watching anything yet.
+/// - `shutdown`: Set when the watcher has to stop.
+///
+/// # Returns
+/// A result once shutdown is requested, or an error if the file system watcher cannot be started or stops.
+pub fn watch_for_malicious_log_changes(workspace: &Workspace, dictionary_file: Option<&str>, filename_prefix: &str, dictionary: &SharedDictionary, watcher: &mut dyn FileWatcher, shutdown: &AtomicBool) -> io::Result<()>
 {
-    let (tx, rx) = mpsc::channel();
-    let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_secs(2)).unwrap();
-    watcher.watch(".", RecursiveMode::Recursive).unwrap();
+    // Event paths are canonical, since the watched directory is
+    let dictionary_path = dictionary_file.map(|file_name| Path::new(file_name).canonicalize()).transpose()?;
+    let watched_dir = match &dictionary_path
+    {
+        Some(path) => path.parent().unwrap_or(Path::new("/")).to_path_buf(),
+        None => workspace.root().canonicalize()?,
+    };
+
+    let is_dictionary_file = |path: &Path| -> bool {
+        match &dictionary_path
+        {
+            Some(dictionary_path) => path == dictionary_path,
+            None => path.parent() == Some(watched_dir.as_path())
+                && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(filename_prefix))
+                && !workspace.is_output(path),
+        }
+    };
+
+    watcher.watch(&watched_dir, false)?;
 
-    loop
+    while !shutdown.load(Ordering::SeqCst)
     {
-        match rx.recv()
+        let Some(event) = watcher.next_event(POLL_INTERVAL)? else
+        {
+            continue;
+        };
+
+        if !event.paths().into_iter().any(is_dictionary_file)
+        {
+            continue;
+        }
+
+        // Wait for the writes to settle, so a file written in several chunks is loaded once
+        if !wait_for_dictionary_writes(watcher, &is_dictionary_file, shutdown)?
+        {
+            break;
+        }
+
+        match load_dictionary_entries(workspace, dictionary_file, filename_prefix)
         {
-            Ok(event) =>
+            Ok(entries) =>
             {
-                match event
-                {
-                    DebouncedEvent::Create(_) =>
-                    {
-                        println!("File created");
-                    },
-                    DebouncedEvent::Write(_) =>
-                    {
-                        malicious_logs.clear();
-                        let malicious_log_files: Vec<String> = file_ops::get_filenames_with_prefix(filename_prefix.clone());
-                        let _ = file_ops::load_files_into_vector(malicious_logs, malicious_log_files);
-                        println!("Updated malicious logs");
-                    },
-                    _ => {}
-                }
+                let current = dictionary.load();
+                let index = DictionaryIndex::new(entries, current.text_unit(), current.normalization().clone())
+                    .with_metrics(&current.weighted_metrics());
+                info!("Updated malicious logs: the dictionary now holds {} entries", index.len());
+                dictionary.store(index);
             },
-            Err(e) => println!("watch error: {:?}", e),
+            Err(e) => error!("Failed to reload the dictionary, keeping the previous one: {}", e),
         }
     }
+
+    Ok(())
 }
 
-/// Creates a thread that waits for new log entries and processes them.
+
+/// Reads the lines appended to a followed file since the last read and sends them in batches.
 ///
 /// # Parameters
-/// - `filename`: A string representing the filename to watch for new log entries.
-/// - `logs`: A mutable reference to a vector to store the log entries.
-/// - `delimiters`: A mutable reference to a vector of delimiter strings used for splitting log entries.
-/// - `dictionary`: A mutable reference to a vector of known patterns to compare against.
-pub fn watch_for_new_log_entries(filename: String, logs: &mut Vec<String>, delimiters: &mut Vec<String>, dictionary: &mut Vec<String>)
+/// - `file`: The followed file.
+/// - `batches`: The channel to the analyzer; sending blocks while it is full.
+/// - `shutdown`: Set when the watcher has to stop; reading stops after the current batch.
+///
+/// # Returns
+/// A result indicating failure to read the file, or an error if the analyzer channel is closed.
+fn send_appended_lines(file: &mut WatchedFile, batches: &SyncSender<LogBatch>, shutdown: &AtomicBool) -> io::Result<()>
 {
-    let (tx, rx) = mpsc::channel();
-    let mut current_length = logs.len();
-    let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_secs(2)).unwrap();
-    watcher.watch(".", RecursiveMode::Recursive).unwrap();
+    while !shutdown.load(Ordering::SeqCst)
+    {
+        // Bounded batches keep catching up on a large backlog from loading it all into memory
+        let lines = file.tailer.lines().take(BATCH_SIZE).collect::<io::Result<Vec<String>>>()?;
+        if lines.is_empty()
+        {
+            break;
+        }
 
-    loop
+        debug!("Read {} new log entries in: {:?}", lines.len(), file.tailer.checkpoint().path);
+        let batch = LogBatch {
+            lines,
+            checkpoint: file.tailer.checkpoint().clone(),
+            checkpoint_file: file.checkpoint_file.clone(),
+        };
+        batches.send(batch).map_err(|_| io::Error::other("the analyzer channel is closed"))?;
+    }
+
+    Ok(())
+}
+
+/// Waits for new log entries and sends them to the analyzer, until shutdown is requested.
+///
+/// # Summary
+/// Every followed file is caught up first, from its checkpoint. Afterwards, only notifications about
+/// followed files, or new files matching the watched patterns, are processed; each file is followed by
+/// name across rotations (see `Tailer`), until it is removed and drained. Checkpoints are saved by the reporter, so a restarted watcher
+/// reads again the batches that were not reported yet.
+///
+/// # Parameters
+/// - `watch_set`: The followed log files, already discovered.
+/// - `watcher`: The file system watcher, not watching anything yet.
+/// - `batches`: The channel to the analyzer.
+/// - `shutdown`: Set when the watcher has to stop.
+///
+/// # Returns
+/// A result once shutdown is requested, or an error if the file system watcher cannot be started or stops.
+pub fn watch_for_new_log_entries(watch_set: &mut WatchSet, watcher: &mut dyn FileWatcher, batches: &SyncSender<LogBatch>, shutdown: &AtomicBool) -> io::Result<()>
+{
+    // Catch up on what was appended while the watcher was not running
+    for file in watch_set.files_mut()
     {
-        match rx.recv()
+        if let Err(e) = send_appended_lines(file, batches, shutdown)
         {
-            Ok(event) =>
+            error!("Failed to read {}: {}", file.tailer.checkpoint().path, e);
+        }
+    }
+
+    for root in watch_set.roots()
+    {
+        watcher.watch(&root.directory, root.recursive)?;
+        debug!("Watching {}", root.directory.display());
+    }
+
+    while !shutdown.load(Ordering::SeqCst)
+    {
+        let Some(event) = watcher.next_event(POLL_INTERVAL)? else
+        {
+            continue;
+        };
+
+        for path in event.paths()
+        {
+            let Some(file) = watch_set.file_for_event(path) else
             {
-                match event
-                {
-                    DebouncedEvent::Create(_) =>
-                    {
-                        println!("File created");
-                    },
-                    DebouncedEvent::Write(_) =>
-                    {
-                        let new_length = file_ops::calculate_number_of_lines(filename.clone());
-
-                        if current_length != new_length
-                        {
-                            println!("Processing new log entries in: {:?}", filename);
-
-                            let mut new_logs: Vec<String> = file_ops::read_file_from_specific_line(filename.clone(), current_length);
-                            logs.append(&mut new_logs);
-                            log_ops::analyze_logs_from_index(logs, current_length, delimiters, dictionary, None);
-                            current_length = new_length;
-                        }
-                    },
-                    _ => {}
-                }
-            },
-            Err(e) => println!("watch error: {:?}", e),
+                continue;
+            };
+
+            if let Err(e) = send_appended_lines(file, batches, shutdown)
+            {
+                error!("Failed to read {}: {}", file.tailer.checkpoint().path, e);
+            }
+            else if matches!(event, FileEvent::Removed(_)) && !shutdown.load(Ordering::SeqCst)
+            {
+                watch_set.forget_removed(path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Analyzes the batches sent by the watcher and sends their malicious entries to the reporter.
+///
+/// # Parameters
+/// - `batches`: The inbox of the batches read by the watcher.
+/// - `results`: The channel to the reporter.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `dictionary`: The shared dictionary of known patterns; each batch uses the index current when it starts.
+/// - `scoring`: The thresholds and normalization used to determine the criticality.
+///
+/// # Returns
+/// A result once the watcher is gone and every batch is analyzed, or an error if the reporter channel is closed.
+pub fn analyze_log_batches(batches: &Inbox<LogBatch>, results: &SyncSender<AnalyzedBatch>, format: &dyn LogFormat, delimiters: &[String], dictionary: &SharedDictionary, scoring: &ScoringConfig) -> io::Result<()>
+{
+    while let Some(batch) = batches.next()
+    {
+        let analyzed = batch.lines.len();
+        let analysis = log_ops::analyze_logs(&batch.lines, format, delimiters, &dictionary.load(), None, scoring);
+        let detections = batch.lines.into_iter()
+            .zip(analysis)
+            .filter(|(_, result)| result.is_malicious())
+            .collect();
+
+        let analyzed = AnalyzedBatch {
+            analyzed,
+            detections,
+            checkpoint: batch.checkpoint,
+            checkpoint_file: batch.checkpoint_file,
+        };
+        results.send(analyzed).map_err(|_| io::Error::other("the reporter channel is closed"))?;
+        batches.done();
+    }
+
+    Ok(())
+}
+
+/// Reports the malicious entries found by the analyzer and saves the checkpoint of each batch.
+///
+/// # Summary
+/// The checkpoint is only saved once the batch is reported, so entries read but not reported when the
+/// process stops are read again on the next start rather than lost.
+///
+/// # Parameters
+/// - `results`: The inbox of the batches analyzed by the analyzer.
+///
+/// # Returns
+/// A result once the analyzer is gone and every batch is reported, or an error if a checkpoint cannot be saved.
+pub fn report_analyzed_batches(results: &Inbox<AnalyzedBatch>) -> io::Result<()>
+{
+    while let Some(batch) = results.next()
+    {
+        info!("Processed {} new log entries in: {:?}", batch.analyzed, batch.checkpoint.path);
+        for (log, result) in &batch.detections
+        {
+            warn!("Malicious request detected (score {:.4}): {}", result.composite_score, log);
+        }
+
+        batch.checkpoint.save(&batch.checkpoint_file)?;
+        results.done();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::test_dir;
+    use std::collections::VecDeque;
+    use std::fs;
+    use std::sync::Arc;
+    use crate::normalize::NormalizationConfig;
+    use crate::string_utils::TextUnit;
+
+    type Step = Box<dyn FnOnce() -> Option<FileEvent> + Send>;
+
+    /// A watcher replaying scripted changes, then reporting a change of another file on every call, if any.
+    struct ScriptedWatcher
+    {
+        steps: VecDeque<Step>,
+        noise: Option<PathBuf>,
+    }
+
+    impl FileWatcher for ScriptedWatcher
+    {
+        fn watch(&mut self, _directory: &Path, _recursive: bool) -> io::Result<()>
+        {
+            Ok(())
+        }
+
+        fn next_event(&mut self, timeout: Duration) -> io::Result<Option<FileEvent>>
+        {
+            if let Some(step) = self.steps.pop_front()
+            {
+                return Ok(step());
+            }
+
+            std::thread::sleep(timeout.min(Duration::from_millis(10)));
+            Ok(self.noise.clone().map(FileEvent::Modified))
         }
     }
+
+    /// Runs the dictionary reloader on a dictionary file until a condition holds, then shuts it down.
+    ///
+    /// # Returns
+    /// Whether the condition held within five seconds.
+    fn reload_until(directory: &Path, dictionary: &SharedDictionary, watcher: ScriptedWatcher, done: impl Fn() -> bool) -> bool
+    {
+        let file_name = directory.join("dictionary.txt").to_string_lossy().into_owned();
+        let workspace = Workspace::new(directory);
+        let shutdown = AtomicBool::new(false);
+        let mut watcher = watcher;
+
+        std::thread::scope(|scope| {
+            let reloader = scope.spawn(|| {
+                watch_for_malicious_log_changes(&workspace, Some(&file_name), "malicious", dictionary, &mut watcher, &shutdown)
+            });
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !done() && Instant::now() < deadline
+            {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            let held = done();
+
+            shutdown.store(true, Ordering::SeqCst);
+            reloader.join().unwrap().unwrap();
+            held
+        })
+    }
+
+    fn entries(index: &DictionaryIndex) -> Vec<&str>
+    {
+        (0..index.len()).map(|i| index.entry(i)).collect()
+    }
+
+    fn rewrite(path: &Path, contents: &'static str) -> Step
+    {
+        let path = path.to_path_buf();
+        Box::new(move || {
+            fs::write(&path, contents).unwrap();
+            Some(FileEvent::Modified(path))
+        })
+    }
+
+    #[test]
+    fn reload_swaps_the_whole_dictionary()
+    {
+        let directory = test_dir("thread-utils-reload");
+        let dictionary_file = directory.join("dictionary.txt");
+        fs::write(&dictionary_file, "<script>\n").unwrap();
+
+        let normalization: NormalizationConfig = "case".parse().unwrap();
+        let dictionary = SharedDictionary::new(DictionaryIndex::new(vec!["<script>".to_string()], TextUnit::Grapheme, normalization.clone()));
+        let before = dictionary.load();
+
+        let watcher = ScriptedWatcher { steps: vec![rewrite(&dictionary_file, "/ETC/passwd\nunion select\n")].into(), noise: None };
+        assert!(reload_until(&directory, &dictionary, watcher, || dictionary.load().len() == 2));
+
+        // A snapshot taken before the reload still sees the previous dictionary as a whole
+        assert_eq!(entries(&before), ["<script>"]);
+
+        let after = dictionary.load();
+        assert_eq!(entries(&after), ["/etc/passwd", "union select"]);
+        assert_eq!(after.text_unit(), TextUnit::Grapheme);
+        assert_eq!(after.normalization(), &normalization);
+    }
+
+    #[test]
+    fn failed_reload_keeps_the_previous_dictionary()
+    {
+        let directory = test_dir("thread-utils-failed-reload");
+        let dictionary_file = directory.join("dictionary.txt");
+        fs::write(&dictionary_file, "<script>\n").unwrap();
+
+        let dictionary = SharedDictionary::new(DictionaryIndex::new(vec!["<script>".to_string()], TextUnit::Char, NormalizationConfig::default()));
+        let before = dictionary.load();
+
+        let path = dictionary_file.clone();
+        let remove: Step = Box::new(move || {
+            fs::remove_file(&path).unwrap();
+            Some(FileEvent::Removed(path))
+        });
+        let started = Instant::now();
+        reload_until(&directory, &dictionary, ScriptedWatcher { steps: vec![remove].into(), noise: None }, || started.elapsed() > DICTIONARY_SETTLE_TIME * 2);
+
+        assert!(Arc::ptr_eq(&before, &dictionary.load()));
+    }
+
+    #[test]
+    fn other_files_do_not_hold_the_reload_back()
+    {
+        let directory = test_dir("thread-utils-busy-directory");
+        let dictionary_file = directory.join("dictionary.txt");
+        fs::write(&dictionary_file, "<script>\n").unwrap();
+
+        let dictionary = SharedDictionary::new(DictionaryIndex::new(vec!["<script>".to_string()], TextUnit::Char, NormalizationConfig::default()));
+
+        // The followed log changes every 10 ms, far more often than the settle time
+        let watcher = ScriptedWatcher {
+            steps: vec![rewrite(&dictionary_file, "/etc/passwd\nunion select\n")].into(),
+            noise: Some(directory.join("logs_to_check.txt")),
+        };
+        assert!(reload_until(&directory, &dictionary, watcher, || dictionary.load().len() == 2));
+    }
+
+    #[test]
+    fn shutdown_stops_the_wait_for_the_writes()
+    {
+        let directory = test_dir("thread-utils-settle-shutdown");
+        let dictionary_file = directory.join("dictionary.txt");
+
+        let mut watcher = ScriptedWatcher { steps: VecDeque::new(), noise: Some(dictionary_file.clone()) };
+        let shutdown = AtomicBool::new(true);
+        assert!(!wait_for_dictionary_writes(&mut watcher, &|path| path == dictionary_file, &shutdown).unwrap());
+
+        // A dictionary file written without a pause is still reloaded after the longest wait
+        let started = Instant::now();
+        shutdown.store(false, Ordering::SeqCst);
+        assert!(wait_for_dictionary_writes(&mut watcher, &|path| path == dictionary_file, &shutdown).unwrap());
+        assert!(started.elapsed() >= MAX_DICTIONARY_SETTLE_TIME);
+    }
 }
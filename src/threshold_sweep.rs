@@ -0,0 +1,412 @@
+/// File: threshold_sweep.rs
+///
+/// This file contains the threshold sweep used to tune the composite score. The similarity of every
+/// entry of a labeled dataset is calculated once; the composite score is then recomputed for each
+/// alpha/beta weighting and every distinct score is tried as the "High" cutoff, which yields the ROC
+/// and precision-recall curves of the detector. Only the "High" cutoff decides whether a request is
+/// malicious, so the "Medium" cutoff does not take part in the sweep.
+/// The file includes the following items:
+///
+/// - `ScoredEntry`: The label and similarity scores of one dataset entry.
+/// - `CurvePoint`: The confusion matrix at one cutoff.
+/// - `WeightResult`: The areas under the curves for one alpha/beta weighting.
+/// - `score_dataset`: Calculates the similarity scores of every labeled entry.
+/// - `sweep_thresholds`: Tries every distinct composite score as the cutoff.
+/// - `roc_auc`: Calculates the area under the ROC curve.
+/// - `pr_auc`: Calculates the area under the precision-recall curve.
+/// - `sweep_weights`: Calculates the areas under the curves for a grid of alpha/beta weightings.
+/// - `parse_weight_step`: Parses the distance between two alpha values of the weight sweep.
+/// - `recommend_operating_point`: Picks the cutoff with the best recall within a false positive rate budget.
+/// - `roc_curve_csv`, `pr_curve_csv`, `weights_csv`: Format the results as CSV.
+///
+/// Modules Required:
+/// - `evaluation`: Provides the confusion matrix.
+/// - `log_ops`: Provides the similarity and composite score calculation.
+use std::fmt::Write;
+
+use crate::dict_index::DictionaryIndex;
+use crate::log_format::LogFormat;
+use crate::evaluation::ConfusionMatrix;
+use crate::log_ops::{self, ScoringConfig, SimilarityScores};
+
+/// The label and similarity scores of one dataset entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoredEntry
+{
+    pub actual_malicious: bool,
+    pub scores: SimilarityScores,
+}
+
+impl ScoredEntry
+{
+    /// Returns the composite score of the entry for the given weights.
+    pub fn composite_score(&self, alpha: f64, beta: f64) -> f64
+    {
+        self.scores.composite_score(alpha, beta)
+    }
+}
+
+/// The confusion matrix at one cutoff; entries scoring at or above the cutoff are flagged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurvePoint
+{
+    pub threshold: f64,
+    pub matrix: ConfusionMatrix,
+}
+
+/// The areas under the curves for one alpha/beta weighting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeightResult
+{
+    pub alpha: f64,
+    pub beta: f64,
+    pub roc_auc: f64,
+    pub pr_auc: f64,
+}
+
+/// Calculates the similarity scores of every labeled entry.
+///
+/// # Parameters
+/// - `benign`: The entries labeled benign.
+/// - `malicious`: The entries labeled malicious.
+/// - `format`: The format of the log entries, used to extract their request target.
+/// - `delimiters`: The delimiter strings used for splitting log entries.
+/// - `dictionary`: The indexed dictionary of known patterns to compare against.
+/// - `scoring`: The normalization used for the Levenshtein distance.
+///
+/// # Returns
+/// A vector with one scored entry per labeled entry, benign entries first.
+pub fn score_dataset(
+    benign: &[String],
+    malicious: &[String],
+    format: &dyn LogFormat,
+    delimiters: &[String],
+    dictionary: &DictionaryIndex,
+    scoring: &ScoringConfig
+) -> Vec<ScoredEntry>
+{
+    let labeled = benign.iter().map(|entry| (entry, false))
+        .chain(malicious.iter().map(|entry| (entry, true)));
+
+    labeled
+        .map(|(entry, actual_malicious)| {
+            let scores = log_ops::calculate_similarity_scores(entry, format, delimiters, dictionary, scoring);
+            ScoredEntry { actual_malicious, scores }
+        })
+        .collect()
+}
+
+/// Tries every distinct composite score as the cutoff.
+///
+/// # Parameters
+/// - `entries`: The scored entries.
+/// - `alpha`: The weight of the normalized Levenshtein similarity.
+/// - `beta`: The weight of the Dice coefficient.
+///
+/// # Returns
+/// The curve points ordered from the strictest cutoff (nothing flagged) to the loosest (everything flagged).
+pub fn sweep_thresholds(entries: &[ScoredEntry], alpha: f64, beta: f64) -> Vec<CurvePoint>
+{
+    let mut scored: Vec<(f64, bool)> = entries.iter()
+        .map(|entry| (entry.composite_score(alpha, beta), entry.actual_malicious))
+        .filter(|(score, _)| !score.is_nan())
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let positives = scored.iter().filter(|(_, actual_malicious)| *actual_malicious).count();
+    let negatives = scored.len() - positives;
+
+    let mut matrix = ConfusionMatrix {
+        true_negatives: negatives,
+        false_negatives: positives,
+        ..ConfusionMatrix::default()
+    };
+    let mut points = vec![CurvePoint { threshold: f64::INFINITY, matrix }];
+
+    let mut index = 0;
+    while index < scored.len()
+    {
+        let threshold = scored[index].0;
+
+        // Entries with the same score are flagged together
+        while index < scored.len() && scored[index].0 == threshold
+        {
+            if scored[index].1
+            {
+                matrix.true_positives += 1;
+                matrix.false_negatives -= 1;
+            }
+            else
+            {
+                matrix.false_positives += 1;
+                matrix.true_negatives -= 1;
+            }
+            index += 1;
+        }
+
+        points.push(CurvePoint { threshold, matrix });
+    }
+
+    points
+}
+
+/// Calculates the area under the ROC curve with the trapezoidal rule.
+///
+/// # Parameters
+/// - `points`: The curve points, as returned by `sweep_thresholds`.
+///
+/// # Returns
+/// The area under the curve of recall over false positive rate.
+pub fn roc_auc(points: &[CurvePoint]) -> f64
+{
+    points.windows(2)
+        .map(|pair| {
+            let (a, b) = (&pair[0].matrix, &pair[1].matrix);
+            (b.false_positive_rate() - a.false_positive_rate()) * (a.recall() + b.recall()) / 2.0
+        })
+        .sum()
+}
+
+/// Calculates the area under the precision-recall curve as the average precision.
+///
+/// # Parameters
+/// - `points`: The curve points, as returned by `sweep_thresholds`.
+///
+/// # Returns
+/// The sum of the precision at every cutoff weighted by the recall gained at that cutoff.
+pub fn pr_auc(points: &[CurvePoint]) -> f64
+{
+    points.windows(2)
+        .map(|pair| (pair[1].matrix.recall() - pair[0].matrix.recall()) * pair[1].matrix.precision())
+        .sum()
+}
+
+/// Calculates the areas under the curves for a grid of alpha/beta weightings.
+///
+/// # Summary
+/// Additional similarity metrics keep the weights they were configured with.
+///
+/// # Parameters
+/// - `entries`: The scored entries.
+/// - `step`: The distance between two alpha values, rounded so the grid ends at 1; beta is always `1 - alpha`.
+///
+/// # Returns
+/// One result per weighting, from alpha 0 to alpha 1.
+pub fn sweep_weights(entries: &[ScoredEntry], step: f64) -> Vec<WeightResult>
+{
+    if step <= 0.0
+    {
+        return Vec::new();
+    }
+
+    let steps = ((1.0 / step).round() as usize).max(1);
+
+    (0..=steps)
+        .map(|index| {
+            let alpha = index as f64 / steps as f64;
+            let beta = 1.0 - alpha;
+            let points = sweep_thresholds(entries, alpha, beta);
+
+            WeightResult {
+                alpha,
+                beta,
+                roc_auc: roc_auc(&points),
+                pr_auc: pr_auc(&points),
+            }
+        })
+        .collect()
+}
+
+/// Parses the distance between two alpha values of the weight sweep.
+///
+/// # Parameters
+/// - `text`: The step, e.g. `0.1`.
+///
+/// # Returns
+/// The step, or an error if it is not a number above 0 and at most 1 (NaN included).
+pub fn parse_weight_step(text: &str) -> Result<f64, String>
+{
+    let text = text.trim();
+    match text.parse::<f64>()
+    {
+        Ok(step) if step > 0.0 && step <= 1.0 => Ok(step),
+        Ok(_) => Err(format!("weight step '{}' must be above 0 and at most 1", text)),
+        Err(_) => Err(format!("invalid weight step '{}'", text)),
+    }
+}
+
+/// Picks the cutoff with the best recall whose false positive rate stays within a budget.
+///
+/// # Summary
+/// The point above every score flags nothing, so it is no cutoff to operate at and is never recommended.
+///
+/// # Parameters
+/// - `points`: The curve points, as returned by `sweep_thresholds`.
+/// - `target_false_positive_rate`: The highest acceptable false positive rate.
+///
+/// # Returns
+/// The recommended point (the strictest one among equal recalls), or `None` if no finite cutoff qualifies.
+pub fn recommend_operating_point(points: &[CurvePoint], target_false_positive_rate: f64) -> Option<CurvePoint>
+{
+    let mut best: Option<CurvePoint> = None;
+
+    for point in points
+    {
+        if !point.threshold.is_finite() || point.matrix.false_positive_rate() > target_false_positive_rate
+        {
+            continue;
+        }
+
+        match best
+        {
+            Some(current) if current.matrix.recall() >= point.matrix.recall() => {},
+            _ => best = Some(*point),
+        }
+    }
+
+    best
+}
+
+/// Formats the ROC curve as CSV.
+pub fn roc_curve_csv(points: &[CurvePoint]) -> String
+{
+    let mut csv = String::from("threshold,false_positive_rate,true_positive_rate\n");
+    for point in points
+    {
+        let _ = writeln!(csv, "{},{},{}", point.threshold, point.matrix.false_positive_rate(), point.matrix.recall());
+    }
+    csv
+}
+
+/// Formats the precision-recall curve as CSV.
+pub fn pr_curve_csv(points: &[CurvePoint]) -> String
+{
+    let mut csv = String::from("threshold,recall,precision\n");
+    for point in points
+    {
+        let _ = writeln!(csv, "{},{},{}", point.threshold, point.matrix.recall(), point.matrix.precision());
+    }
+    csv
+}
+
+/// Formats the alpha/beta sweep as CSV.
+pub fn weights_csv(results: &[WeightResult]) -> String
+{
+    let mut csv = String::from("alpha,beta,roc_auc,pr_auc\n");
+    for result in results
+    {
+        let _ = writeln!(csv, "{},{},{},{}", result.alpha, result.beta, result.roc_auc, result.pr_auc);
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// An entry whose composite score with alpha 0 and beta 1 is its Dice coefficient.
+    fn entry(actual_malicious: bool, dice_coefficient: f64) -> ScoredEntry
+    {
+        ScoredEntry {
+            actual_malicious,
+            scores: SimilarityScores {
+                average_levenshtein: 10,
+                average_dice_coefficient: dice_coefficient,
+                max_levenshtein_distance: 10,
+                metrics: Vec::new(),
+            },
+        }
+    }
+
+    fn matrix(true_positives: usize, false_positives: usize, true_negatives: usize, false_negatives: usize) -> ConfusionMatrix
+    {
+        ConfusionMatrix { true_positives, false_positives, true_negatives, false_negatives }
+    }
+
+    #[test]
+    fn sweep_flags_one_more_score_at_every_cutoff()
+    {
+        let entries = vec![entry(true, 0.9), entry(false, 0.8), entry(true, 0.4), entry(false, 0.1)];
+        let points = sweep_thresholds(&entries, 0.0, 1.0);
+
+        let expected = vec![
+            CurvePoint { threshold: f64::INFINITY, matrix: matrix(0, 0, 2, 2) },
+            CurvePoint { threshold: 0.9, matrix: matrix(1, 0, 2, 1) },
+            CurvePoint { threshold: 0.8, matrix: matrix(1, 1, 1, 1) },
+            CurvePoint { threshold: 0.4, matrix: matrix(2, 1, 1, 0) },
+            CurvePoint { threshold: 0.1, matrix: matrix(2, 2, 0, 0) },
+        ];
+        assert_eq!(points, expected);
+
+        // ROC: (0, 0) (0, 0.5) (0.5, 0.5) (0.5, 1) (1, 1)
+        assert!((roc_auc(&points) - 0.75).abs() < 1e-12);
+        // Recall 0.5 gained at precision 1, then 0.5 at precision 2/3
+        assert!((pr_auc(&points) - (0.5 + 1.0 / 3.0)).abs() < 1e-12);
+
+        let recommended = recommend_operating_point(&points, 0.0).unwrap();
+        assert_eq!(recommended.threshold, 0.9);
+        assert_eq!(recommend_operating_point(&points, 0.5).unwrap().threshold, 0.4);
+        assert_eq!(recommend_operating_point(&points, -1.0), None);
+    }
+
+    #[test]
+    fn flagging_nothing_is_not_recommended()
+    {
+        // Every cutoff flags the benign entry scoring highest
+        let entries = vec![entry(false, 0.9), entry(true, 0.5)];
+        let points = sweep_thresholds(&entries, 0.0, 1.0);
+
+        assert_eq!(recommend_operating_point(&points, 0.0), None);
+        assert_eq!(recommend_operating_point(&points, 1.0).unwrap().threshold, 0.5);
+    }
+
+    #[test]
+    fn equal_scores_are_flagged_together()
+    {
+        let entries = vec![entry(true, 0.5), entry(false, 0.5), entry(true, 0.2)];
+        let points = sweep_thresholds(&entries, 0.0, 1.0);
+
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[1], CurvePoint { threshold: 0.5, matrix: matrix(1, 1, 0, 1) });
+    }
+
+    #[test]
+    fn perfect_separation_has_unit_areas()
+    {
+        let entries = vec![entry(true, 0.9), entry(true, 0.7), entry(false, 0.3)];
+        let points = sweep_thresholds(&entries, 0.0, 1.0);
+
+        assert!((roc_auc(&points) - 1.0).abs() < 1e-12);
+        assert!((pr_auc(&points) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn weight_grid_ends_at_one()
+    {
+        let entries = vec![entry(true, 0.9), entry(false, 0.1)];
+
+        let alphas: Vec<f64> = sweep_weights(&entries, 0.25).iter().map(|result| result.alpha).collect();
+        assert_eq!(alphas, vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+
+        // A step that does not divide 1 is rounded to one that does
+        let results = sweep_weights(&entries, 0.3);
+        assert_eq!(results.len(), 4);
+        assert!(results.iter().all(|result| (result.alpha + result.beta - 1.0).abs() < 1e-12));
+        assert_eq!(results.last().unwrap().alpha, 1.0);
+
+        assert!(sweep_weights(&entries, 0.0).is_empty());
+    }
+
+    #[test]
+    fn weight_step_lies_in_the_unit_interval()
+    {
+        assert_eq!(parse_weight_step("0.1"), Ok(0.1));
+        assert_eq!(parse_weight_step(" 1 "), Ok(1.0));
+
+        for text in ["0", "-0.1", "1.5", "NaN", "inf", "step"]
+        {
+            assert!(parse_weight_step(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+/// File: watch_set.rs
+///
+/// This file contains the set of log files followed by the live watcher. The files are selected with
+/// glob patterns (e.g. `/var/log/nginx/*.access.log`); a pattern naming a directory selects every file
+/// in it. Each matching file has its own tailer and checkpoint, files created later that match a pattern
+/// are picked up from their first line, and files that match no pattern, or were written by the tool
+/// itself, are ignored. A removed file is no longer followed once the lines written to it are read.
+/// The file includes the following items:
+///
+/// - `WatchRoot`: A directory the file system watcher has to watch to see changes to the matching files.
+/// - `WatchedFile`: The tailer and checkpoint file of one followed log file.
+/// - `WatchSet`: The followed log files and the patterns selecting them.
+///
+/// Modules Required:
+/// - `checkpoint`: Provides the checkpoints the files are resumed at.
+/// - `tailer`: Provides the reading of the lines appended to each file.
+/// - `workspace`: Provides the data directory relative patterns are resolved against, and the tool's own outputs.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use tracing::{debug, info, warn};
+
+use crate::checkpoint::{self, Checkpoint};
+use crate::tailer::Tailer;
+use crate::workspace::Workspace;
+
+/// Matching options of the patterns: wildcards do not cross directory separators, except `**`.
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A directory the file system watcher has to watch to see changes to the matching files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchRoot
+{
+    pub directory: PathBuf,
+    /// Whether matching files can be in subdirectories of the directory.
+    pub recursive: bool,
+}
+
+/// The tailer and checkpoint file of one followed log file.
+#[derive(Debug)]
+pub struct WatchedFile
+{
+    pub tailer: Tailer,
+    pub checkpoint_file: PathBuf,
+}
+
+/// The followed log files and the patterns selecting them.
+#[derive(Debug)]
+pub struct WatchSet
+{
+    patterns: Vec<Pattern>,
+    roots: Vec<WatchRoot>,
+    workspace: Workspace,
+    checkpoint_dir: PathBuf,
+    files: BTreeMap<PathBuf, WatchedFile>,
+}
+
+/// Returns whether a path component holds glob metacharacters.
+fn has_wildcard(component: &str) -> bool
+{
+    component.contains(['*', '?', '['])
+}
+
+/// Resolves a pattern against the data directory and splits it into the directory to watch and the
+/// compiled pattern.
+///
+/// # Parameters
+/// - `workspace`: The workspace relative patterns are resolved against.
+/// - `pattern`: The glob pattern, file or directory given on the command line.
+///
+/// # Returns
+/// A result containing the watch root and the pattern, or an error if the pattern is invalid or its
+/// directory does not exist.
+fn resolve_pattern(workspace: &Workspace, pattern: &str) -> io::Result<(WatchRoot, Pattern)>
+{
+    let mut path = workspace.path(pattern);
+    if path.is_dir()
+    {
+        path = path.join("*");
+    }
+
+    // The literal leading directories are made canonical, so they compare equal to the event paths
+    let components: Vec<Component> = path.components().collect();
+    let Some(last) = components.len().checked_sub(1) else
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty watch pattern"));
+    };
+    let first_wildcard = components.iter()
+        .position(|component| has_wildcard(&component.as_os_str().to_string_lossy()))
+        .unwrap_or(last);
+    let base: PathBuf = components[..first_wildcard].iter().collect();
+    let rest: PathBuf = components[first_wildcard..].iter().collect();
+
+    let directory = base.canonicalize()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot watch {}: {}", base.display(), e)))?;
+    let compiled = Pattern::new(&directory.join(&rest).to_string_lossy())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid pattern {}: {}", pattern, e)))?;
+
+    Ok((WatchRoot { directory, recursive: rest.components().count() > 1 }, compiled))
+}
+
+impl WatchSet
+{
+    /// Creates the set of files selected by glob patterns; no file is opened until `discover` is called.
+    ///
+    /// # Parameters
+    /// - `workspace`: The workspace relative patterns are resolved against.
+    /// - `patterns`: The glob patterns, files or directories to follow.
+    /// - `checkpoint_dir`: The directory the checkpoints of the files are stored in.
+    ///
+    /// # Returns
+    /// A result containing the set, or an error if a pattern is invalid or its directory does not exist.
+    pub fn new(workspace: &Workspace, patterns: &[String], checkpoint_dir: &Path) -> io::Result<WatchSet>
+    {
+        let mut roots = Vec::new();
+        let mut compiled = Vec::new();
+
+        for pattern in patterns
+        {
+            let (root, pattern) = resolve_pattern(workspace, pattern)?;
+            if !roots.contains(&root)
+            {
+                roots.push(root);
+            }
+            compiled.push(pattern);
+        }
+
+        Ok(WatchSet {
+            patterns: compiled,
+            roots,
+            workspace: workspace.clone(),
+            checkpoint_dir: checkpoint_dir.to_path_buf(),
+            files: BTreeMap::new(),
+        })
+    }
+
+    /// Returns the directories the file system watcher has to watch.
+    pub fn roots(&self) -> &[WatchRoot]
+    {
+        &self.roots
+    }
+
+    /// Checks whether a path is selected by a pattern and is not one of the tool's own outputs.
+    pub fn matches(&self, path: &Path) -> bool
+    {
+        self.patterns.iter().any(|pattern| pattern.matches_path_with(path, MATCH_OPTIONS))
+            && !self.workspace.is_output(path)
+    }
+
+    /// Opens every file currently matching the patterns.
+    ///
+    /// # Summary
+    /// A file resumes at its checkpoint, or starts at its end when it has none, so a first start does
+    /// not analyze the whole history of the logs.
+    ///
+    /// # Returns
+    /// A result indicating failure to read a checkpoint.
+    pub fn discover(&mut self) -> io::Result<()>
+    {
+        let mut paths = Vec::new();
+        for pattern in &self.patterns
+        {
+            let entries = glob::glob_with(pattern.as_str(), MATCH_OPTIONS)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+            paths.extend(entries.flatten().filter(|path| path.is_file() && self.matches(path)));
+        }
+
+        for path in paths
+        {
+            if !self.files.contains_key(&path)
+            {
+                self.add_file(&path, false)?;
+            }
+        }
+
+        if self.files.is_empty()
+        {
+            warn!("No file matches the watched patterns yet");
+        }
+        Ok(())
+    }
+
+    /// Returns the followed file a change notification is about, following new matching files.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the changed file.
+    ///
+    /// # Returns
+    /// The followed file, or `None` if the path is not followed and is not a new matching file.
+    pub fn file_for_event(&mut self, path: &Path) -> Option<&mut WatchedFile>
+    {
+        if !self.files.contains_key(path)
+        {
+            if !self.matches(path) || !path.is_file()
+            {
+                return None;
+            }
+
+            if self.is_being_read(&fs::metadata(path).ok()?)
+            {
+                debug!("Ignoring {}, a file already read under another name", path.display());
+                return None;
+            }
+
+            if let Err(e) = self.add_file(path, true)
+            {
+                warn!("Cannot follow {}: {}", path.display(), e);
+                return None;
+            }
+        }
+
+        self.files.get_mut(path)
+    }
+
+    /// Stops following a file whose path was removed, once the lines written to it are read.
+    ///
+    /// # Summary
+    /// A file renamed away from its path, or removed while it is still open elsewhere under another
+    /// name, is kept, since the tailer follows the path and drains the file first when a new file is
+    /// created there.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the removed file, already drained.
+    ///
+    /// # Returns
+    /// Whether the file was followed and is no longer.
+    pub fn forget_removed(&mut self, path: &Path) -> bool
+    {
+        let Some(file) = self.files.get(path) else
+        {
+            return false;
+        };
+
+        let removed = !path.exists() && file.tailer.metadata().is_ok_and(|metadata| checkpoint::file_links(&metadata) == 0);
+        if removed
+        {
+            info!("No longer following {}, which was removed", path.display());
+            self.files.remove(path);
+        }
+        removed
+    }
+
+    /// Returns the paths of the followed files.
+    pub fn paths(&self) -> impl Iterator<Item = &Path>
+    {
+        self.files.keys().map(|path| path.as_path())
+    }
+
+    /// Returns the followed files.
+    pub fn files_mut(&mut self) -> impl Iterator<Item = &mut WatchedFile>
+    {
+        self.files.values_mut()
+    }
+
+    /// Starts following a file.
+    ///
+    /// # Parameters
+    /// - `path`: The path of the file.
+    /// - `from_start`: Whether to start at the first line when the file has no checkpoint, rather than at its end.
+    ///
+    /// # Returns
+    /// A result indicating failure to open the file or to read its checkpoint.
+    fn add_file(&mut self, path: &Path, from_start: bool) -> io::Result<()>
+    {
+        let file_name = path.to_string_lossy().into_owned();
+        let checkpoint_file = checkpoint::checkpoint_file(&self.checkpoint_dir, &file_name);
+
+        let checkpoint = match Checkpoint::load(&checkpoint_file)?
+        {
+            Some(checkpoint) if checkpoint.path == file_name =>
+            {
+                info!("Resuming {} at offset {}", file_name, checkpoint.offset);
+                checkpoint
+            },
+            _ =>
+            {
+                let offset = if from_start { 0 } else { fs::metadata(path)?.len() };
+                let checkpoint = Checkpoint::at_offset(&file_name, offset)?;
+                // A restarted watcher has to resume here, not at the end the file has grown to by then
+                checkpoint.save(&checkpoint_file)?;
+                checkpoint
+            },
+        };
+
+        info!("Following {}", file_name);
+        let tailer = Tailer::open(checkpoint)?;
+        self.files.insert(path.to_path_buf(), WatchedFile { tailer, checkpoint_file });
+        Ok(())
+    }
+
+    /// Checks whether a file is being read by a tailer, e.g. a rotated file that still matches a
+    /// pattern under its new name. Files are told apart by device and inode, so without inodes no file is.
+    fn is_being_read(&self, metadata: &fs::Metadata) -> bool
+    {
+        let inode = checkpoint::file_inode(metadata);
+        if inode == 0
+        {
+            return false;
+        }
+
+        let device = checkpoint::file_device(metadata);
+        self.files.values()
+            .filter_map(|file| file.tailer.metadata().ok())
+            .any(|read| checkpoint::file_inode(&read) == inode && checkpoint::file_device(&read) == device)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::{append, test_dir};
+    use crate::workspace::MALICIOUS_LOGS_OUTPUT;
+
+    #[test]
+    fn directory_selects_every_file_in_it()
+    {
+        let directory = test_dir("watch-set-directory");
+        fs::create_dir(directory.join("logs")).unwrap();
+
+        let (root, pattern) = resolve_pattern(&Workspace::new(&directory), "logs").unwrap();
+
+        assert_eq!(root, WatchRoot { directory: directory.join("logs"), recursive: false });
+        assert_eq!(pattern.as_str(), directory.join("logs").join("*").to_string_lossy());
+    }
+
+    #[test]
+    fn wildcard_directories_watch_recursively()
+    {
+        let directory = test_dir("watch-set-recursive");
+        let workspace = Workspace::new(&directory);
+
+        let (flat, _) = resolve_pattern(&workspace, "*.log").unwrap();
+        assert_eq!(flat, WatchRoot { directory: directory.clone(), recursive: false });
+
+        let (nested, _) = resolve_pattern(&workspace, "*/access.log").unwrap();
+        assert_eq!(nested, WatchRoot { directory: directory.clone(), recursive: true });
+
+        let (deep, _) = resolve_pattern(&workspace, "**/*.log").unwrap();
+        assert!(deep.recursive);
+
+        // A literal file is watched through its directory
+        fs::write(directory.join("access.log"), "").unwrap();
+        let (file, pattern) = resolve_pattern(&workspace, "access.log").unwrap();
+        assert_eq!(file, WatchRoot { directory: directory.clone(), recursive: false });
+        assert!(pattern.matches_path(&directory.join("access.log")));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected()
+    {
+        assert!(resolve_pattern(&Workspace::new(""), "").is_err());
+        assert!(resolve_pattern(&Workspace::new(test_dir("watch-set-invalid")), "missing/*.log").is_err());
+    }
+
+    #[test]
+    fn outputs_of_the_tool_are_not_followed()
+    {
+        let directory = test_dir("watch-set-outputs");
+        let workspace = Workspace::new(&directory).with_output(directory.join("report.jsonl"));
+        let set = WatchSet::new(&workspace, &[directory.to_string_lossy().into_owned()], &directory.join("checkpoints")).unwrap();
+
+        assert!(set.matches(&directory.join("access.log")));
+        assert!(!set.matches(&directory.join(MALICIOUS_LOGS_OUTPUT)));
+        assert!(!set.matches(&directory.join(format!("{}.1", MALICIOUS_LOGS_OUTPUT))));
+        assert!(!set.matches(&directory.join("report.jsonl")));
+        assert!(!set.matches(&directory.join(".report.jsonl.42.tmp")));
+        // Wildcards do not cross directory separators
+        assert!(!set.matches(&directory.join("nested").join("access.log")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn renamed_files_are_not_read_again()
+    {
+        let directory = test_dir("watch-set-rename");
+        let logs = directory.join("logs");
+        fs::create_dir(&logs).unwrap();
+        fs::write(logs.join("access.log"), "GET /a HTTP/1.1\n").unwrap();
+
+        let mut set = WatchSet::new(&Workspace::new(&directory), &["logs".to_string()], &directory.join("checkpoints")).unwrap();
+        set.discover().unwrap();
+        assert_eq!(set.paths().collect::<Vec<_>>(), vec![logs.join("access.log")]);
+
+        // A rotated file keeps its inode, so it is not followed again under its new name
+        fs::rename(logs.join("access.log"), logs.join("access.old")).unwrap();
+        assert!(set.file_for_event(&logs.join("access.old")).is_none());
+
+        // A new file is followed from its first line
+        fs::write(logs.join("access.log"), "GET /b HTTP/1.1\n").unwrap();
+        let file = set.file_for_event(&logs.join("access.log")).unwrap();
+        assert_eq!(file.tailer.next_line().unwrap().as_deref(), Some("GET /b HTTP/1.1"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rotated_files_are_known_until_the_tailer_moves_off()
+    {
+        let directory = test_dir("watch-set-moved-off");
+        let logs = directory.join("logs");
+        fs::create_dir(&logs).unwrap();
+        fs::write(logs.join("access.log"), "GET /a HTTP/1.1\n").unwrap();
+
+        let mut set = WatchSet::new(&Workspace::new(&directory), &["logs".to_string()], &directory.join("checkpoints")).unwrap();
+        set.discover().unwrap();
+        fs::rename(logs.join("access.log"), logs.join("access.old")).unwrap();
+        fs::write(logs.join("access.log"), "GET /b HTTP/1.1\n").unwrap();
+        assert!(set.file_for_event(&logs.join("access.old")).is_none());
+
+        // Once the tailer switched to the new file, the rotated file is a file like any other
+        let file = set.file_for_event(&logs.join("access.log")).unwrap();
+        assert_eq!(file.tailer.next_line().unwrap().as_deref(), Some("GET /b HTTP/1.1"));
+        assert!(set.file_for_event(&logs.join("access.old")).is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removed_files_are_dropped_once_drained()
+    {
+        let directory = test_dir("watch-set-removed");
+        let logs = directory.join("logs");
+        fs::create_dir(&logs).unwrap();
+        fs::write(logs.join("access.log"), "").unwrap();
+        fs::write(logs.join("error.log"), "").unwrap();
+
+        let mut set = WatchSet::new(&Workspace::new(&directory), &["logs".to_string()], &directory.join("checkpoints")).unwrap();
+        set.discover().unwrap();
+
+        // A renamed file is still drained and followed by name
+        fs::rename(logs.join("error.log"), logs.join("error.old")).unwrap();
+        assert!(!set.forget_removed(&logs.join("error.log")));
+
+        append(&logs.join("access.log"), "GET /a HTTP/1.1\n");
+        fs::remove_file(logs.join("access.log")).unwrap();
+        let file = set.file_for_event(&logs.join("access.log")).unwrap();
+        assert_eq!(file.tailer.next_line().unwrap().as_deref(), Some("GET /a HTTP/1.1"));
+        assert!(set.forget_removed(&logs.join("access.log")));
+
+        assert_eq!(set.paths().collect::<Vec<_>>(), vec![logs.join("error.log")]);
+    }
+}
@@ -0,0 +1,277 @@
+/// File: workspace.rs
+///
+/// This file contains the `Workspace` type, the single root directory that all data files
+/// (malicious logs, special strings, logs to check, stored hashes) are resolved against.
+/// The workspace is passed explicitly to every loader, so the process working directory is never changed.
+/// Stored hashes live in `<data_dir>/hashes`, where the original tool wrote them after changing into the
+/// data directory; hashes kept elsewhere (such as an old `src/hashes`) are not read, and the dictionary
+/// is then simply rebuilt once.
+/// The file includes the following items:
+///
+/// - `Workspace`: The data directory all relative file names are resolved against.
+/// - `Workspace::new`: Creates a workspace rooted at a given directory.
+/// - `Workspace::resolve`: Determines the data directory from the CLI flag, environment variable or configuration file.
+/// - `Workspace::root`: Returns the root directory.
+/// - `Workspace::path`: Resolves a file name relative to the root directory.
+/// - `Workspace::hashes_dir`: Returns the directory where file hashes are stored.
+/// - `Workspace::checkpoints_dir`: Returns the directory where the checkpoints of the live watcher are stored.
+/// - `Workspace::with_output`: Registers a file the current run writes to.
+/// - `Workspace::is_output`: Checks whether a file was written by the tool, so it is never loaded as input.
+///
+/// Modules Required:
+/// - `config`: Provides the configuration file loader.
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+
+/// Environment variable holding the data directory.
+pub const DATA_DIR_ENV_VAR: &str = "FS_DATA_DIR";
+
+/// Configuration file key holding the data directory.
+pub const DATA_DIR_CONFIG_KEY: &str = "data_dir";
+
+/// Data directory used when none is configured.
+pub const DEFAULT_DATA_DIR: &str = "src/files";
+
+/// Name of the directory, inside the data directory, where file hashes are stored.
+const HASHES_DIR: &str = "hashes";
+
+/// Name of the directory, inside the data directory, where the checkpoints of the live watcher are stored.
+const CHECKPOINTS_DIR: &str = "checkpoints";
+
+/// Default output file of `scan` text reports, in the data directory.
+pub const MALICIOUS_LOGS_OUTPUT: &str = "malicious_logs.txt";
+
+/// Default output file name of `scan` reports in the other formats, in the data directory; the
+/// extension is the name of the format.
+pub const DETECTION_REPORT_OUTPUT: &str = "detection_report";
+
+/// Default dictionary file of `dict build`, in the data directory.
+pub const DICTIONARY_FILE: &str = "dictionary.txt";
+
+/// Extension of the temporary files output files are written to before being renamed into place.
+pub const TEMP_FILE_EXTENSION: &str = "tmp";
+
+/// The data directory all relative file names are resolved against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace
+{
+    root: PathBuf,
+    /// The files the current run writes to, besides the default output files.
+    outputs: Vec<PathBuf>,
+}
+
+impl Workspace
+{
+    /// Creates a workspace rooted at a given directory.
+    ///
+    /// # Parameters
+    /// - `root`: The data directory.
+    ///
+    /// # Returns
+    /// A new `Workspace`.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Workspace
+    {
+        Workspace { root: root.into(), outputs: Vec::new() }
+    }
+
+    /// Determines the data directory.
+    ///
+    /// # Summary
+    /// The directory given on the command line wins, then the `FS_DATA_DIR` environment variable,
+    /// then the `data_dir` key of the configuration file, and finally `src/files`.
+    ///
+    /// # Parameters
+    /// - `cli_dir`: The data directory given on the command line, if any.
+    /// - `config`: The loaded configuration file.
+    ///
+    /// # Returns
+    /// The resolved `Workspace`.
+    pub fn resolve(cli_dir: Option<&str>, config: &Config) -> Workspace
+    {
+        if let Some(dir) = cli_dir
+        {
+            return Workspace::new(dir);
+        }
+
+        if let Ok(dir) = env::var(DATA_DIR_ENV_VAR)
+        {
+            return Workspace::new(dir);
+        }
+
+        match config.get(DATA_DIR_CONFIG_KEY)
+        {
+            Some(dir) => Workspace::new(dir),
+            None => Workspace::new(DEFAULT_DATA_DIR),
+        }
+    }
+
+    /// Returns the root directory of the workspace.
+    pub fn root(&self) -> &Path
+    {
+        &self.root
+    }
+
+    /// Resolves a file name relative to the root directory.
+    ///
+    /// # Parameters
+    /// - `file_name`: The file name; absolute paths are returned unchanged.
+    ///
+    /// # Returns
+    /// The resolved path.
+    pub fn path<P: AsRef<Path>>(&self, file_name: P) -> PathBuf
+    {
+        self.root.join(file_name)
+    }
+
+    /// Returns the directory where file hashes are stored, `hashes` inside the data directory.
+    pub fn hashes_dir(&self) -> PathBuf
+    {
+        self.root.join(HASHES_DIR)
+    }
+
+    /// Returns the directory where the checkpoints of the live watcher are stored.
+    pub fn checkpoints_dir(&self) -> PathBuf
+    {
+        self.root.join(CHECKPOINTS_DIR)
+    }
+
+    /// Registers a file the current run writes to, so it is excluded from the input files.
+    ///
+    /// # Parameters
+    /// - `file_name`: The path of the output file, as it is opened for writing.
+    ///
+    /// # Returns
+    /// A copy of the workspace with the output file registered.
+    pub fn with_output<P: AsRef<Path>>(&self, file_name: P) -> Workspace
+    {
+        let mut workspace = self.clone();
+        workspace.outputs.push(normalize_path(file_name.as_ref()));
+        workspace
+    }
+
+    /// Checks whether a file was written by the tool.
+    ///
+    /// # Summary
+    /// Output files are the default outputs in the root directory, the outputs registered with
+    /// `with_output`, their rotated copies (`<output>.<n>`), and the hidden temporary files they are
+    /// written to (`.<output>.<pid>.tmp`).
+    ///
+    /// # Parameters
+    /// - `path`: The path of the file.
+    ///
+    /// # Returns
+    /// `true` if the file is an output of the tool.
+    pub fn is_output(&self, path: &Path) -> bool
+    {
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else
+        {
+            return false;
+        };
+
+        if file_name.starts_with('.') && path.extension().is_some_and(|extension| extension == TEMP_FILE_EXTENSION)
+        {
+            return true;
+        }
+
+        let base_name = strip_rotation_suffix(file_name);
+        let path = normalize_path(&path.with_file_name(base_name));
+
+        if path.parent() == self.root.canonicalize().ok().as_deref()
+            && (base_name == MALICIOUS_LOGS_OUTPUT
+                || base_name == DICTIONARY_FILE
+                || base_name.strip_prefix(DETECTION_REPORT_OUTPUT).is_some_and(|rest| rest.starts_with('.')))
+        {
+            return true;
+        }
+
+        self.outputs.contains(&path)
+    }
+}
+
+/// Removes the `.<n>` suffix of a rotated output file name.
+fn strip_rotation_suffix(file_name: &str) -> &str
+{
+    match file_name.rsplit_once('.')
+    {
+        Some((base, number)) if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) => base,
+        _ => file_name,
+    }
+}
+
+/// Returns a path with its directory in canonical form, so files that do not exist yet compare
+/// equal to the same files once written; the path is returned unchanged if the directory does not exist.
+fn normalize_path(path: &Path) -> PathBuf
+{
+    let directory = match path.parent()
+    {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    match (directory.canonicalize(), path.file_name())
+    {
+        (Ok(directory), Some(file_name)) => directory.join(file_name),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::test_dir;
+    use std::fs;
+
+    #[test]
+    fn command_line_directory_wins()
+    {
+        let directory = test_dir("workspace-resolve");
+        let config_file = directory.join("fs.conf");
+        fs::write(&config_file, "data_dir = \"from-config\"\n").unwrap();
+        let config = Config::load(&config_file.to_string_lossy()).unwrap();
+
+        assert_eq!(Workspace::resolve(Some("from-cli"), &config).root(), Path::new("from-cli"));
+
+        // The environment variable is only checked when the test environment does not set it
+        if env::var_os(DATA_DIR_ENV_VAR).is_none()
+        {
+            assert_eq!(Workspace::resolve(None, &config).root(), Path::new("from-config"));
+            assert_eq!(Workspace::resolve(None, &Config::default()).root(), Path::new(DEFAULT_DATA_DIR));
+        }
+    }
+
+    #[test]
+    fn paths_are_resolved_against_the_root()
+    {
+        let workspace = Workspace::new("data");
+
+        assert_eq!(workspace.path("logs_to_check.txt"), Path::new("data/logs_to_check.txt"));
+        assert_eq!(workspace.path("/var/log/access.log"), Path::new("/var/log/access.log"));
+        assert_eq!(workspace.hashes_dir(), Path::new("data/hashes"));
+        assert_eq!(workspace.checkpoints_dir(), Path::new("data/checkpoints"));
+    }
+
+    #[test]
+    fn outputs_are_recognized()
+    {
+        let directory = test_dir("workspace-outputs");
+        fs::create_dir(directory.join("nested")).unwrap();
+        let workspace = Workspace::new(&directory).with_output(directory.join("nested").join("report.csv"));
+
+        assert!(workspace.is_output(&directory.join(MALICIOUS_LOGS_OUTPUT)));
+        assert!(workspace.is_output(&directory.join(format!("{}.3", MALICIOUS_LOGS_OUTPUT))));
+        assert!(workspace.is_output(&directory.join(DICTIONARY_FILE)));
+        assert!(workspace.is_output(&directory.join(format!("{}.sarif", DETECTION_REPORT_OUTPUT))));
+        assert!(workspace.is_output(&directory.join(".logs_to_check.txt.42.tmp")));
+        assert!(workspace.is_output(&directory.join("nested").join("report.csv")));
+        assert!(workspace.is_output(&directory.join("nested").join("report.csv.1")));
+
+        assert!(!workspace.is_output(&directory.join("logs_to_check.txt")));
+        assert!(!workspace.is_output(&directory.join("logs_to_check.txt.old")));
+        assert!(!workspace.is_output(&directory.join(DETECTION_REPORT_OUTPUT)));
+        // The default outputs are only outputs in the root directory
+        assert!(!workspace.is_output(&directory.join("nested").join(MALICIOUS_LOGS_OUTPUT)));
+    }
+}